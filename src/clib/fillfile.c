@@ -3,6 +3,7 @@
 #include <fcntl.h>
 #include <sys/stat.h>
 #include <stdlib.h>
+#include <unistd.h>
 
 #define FILE_MODE 0700
 
@@ -24,30 +25,61 @@ unsigned long get_file_size(const char *path) {
     return filesize;
 }
 
-/* 复制指定长度文件 */
+/* 写入全部数据, 失败返回 -1 */
+int write_all(int fd, const char *buff, unsigned long len) {
+    unsigned long done = 0;
+    while (done < len) {
+        ssize_t n = write(fd, buff + done, len - done);
+        if (n <= 0) {
+            return -1;
+        }
+        done += n;
+    }
+    return 0;
+}
+
+/* 复制指定长度文件, 新文件必须不存在, 失败返回 -1 */
 int copy_file(const char *old_file, const char *new_file, unsigned long fill_len) {
 
     int fd_old = open(old_file, O_RDONLY, FILE_MODE);
-    int fd_new = open(new_file, O_WRONLY | O_CREAT, FILE_MODE);
-    char *buff = (char *) malloc(sizeof(char) * fill_len);
+    if (fd_old == -1) {
+        return -1;
+    }
+    int fd_new = open(new_file, O_WRONLY | O_CREAT | O_EXCL, FILE_MODE);
+    if (fd_new == -1) {
+        close(fd_old);
+        return -1;
+    }
 
-    int re = read(fd_old, buff, fill_len);
-    int wr = write(fd_new, buff, fill_len);
+    char buff[65536];
+    int re = 0;
+    while (fill_len > 0) {
+        unsigned long len = fill_len < sizeof(buff) ? fill_len : sizeof(buff);
+        ssize_t n = read(fd_old, buff, len);
+        if (n <= 0 || write_all(fd_new, buff, n) == -1) {
+            re = -1;
+            break;
+        }
+        fill_len -= n;
+    }
 
     close(fd_new);
     close(fd_old);
-
-    free(buff);
-    return wr;
+    return re;
 }
 
-/* 追加写文件 */
-int append_file(const char *file_name, char *buff) {
+/* 追加写入数据与文件尾, 写入后同步到磁盘, 失败返回 1 */
+int append_tail(const char *file_name, const char *data, unsigned long size, FILE_TAIL *tail) {
 
     int fd = open(file_name, O_WRONLY | O_APPEND, FILE_MODE);
-
-    int buff_len = strlen(buff);
-    int re = write(fd, buff, buff_len);
+    if (fd == -1) {
+        return 1;
+    }
+    int re = 0;
+    if (write_all(fd, data, size) == -1 || write_all(fd, (const char *) tail, sizeof(FILE_TAIL)) == -1
+        || fsync(fd) == -1) {
+        re = 1;
+    }
     close(fd);
     return re;
 }
@@ -66,17 +98,14 @@ FILE_TAIL *read_tail(const char *file_name) {
 
 /********************************************************************************************************* 对外函数 */
 /* 初始化文件 填充空白与尾部
- * 文件名: file_name.bak
+ * 写入到 out_file, 由调用者替换原文件; out_file 必须不存在
  * 
  * file_name: 文件名
+ * out_file:  输出文件名
  * size:      填充空白大小
  * pos:       0: 未初始化过, 1: 已经初始化过, 重新初始化
  */
-int init_file(const char *file_name, const char *data, unsigned long size, int pos) {
-
-    char *bak = ".bak";
-    char *file_bak = (char *) malloc(strlen(file_name) + strlen(bak));
-    sprintf(file_bak, "%s%s", file_name, bak);
+int init_file(const char *file_name, const char *out_file, const char *data, unsigned long size, int pos) {
 
     unsigned long fsize = get_file_size(file_name);
     if (pos == 1) {
@@ -85,33 +114,27 @@ int init_file(const char *file_name, const char *data, unsigned long size, int p
         free(t);
     }
     // 复制文件
-    copy_file(file_name, file_bak, fsize);
+    if (copy_file(file_name, out_file, fsize) == -1) {
+        return 1;
+    }
 
     // 填充空白
-    char *cfill = (char *) malloc(sizeof(char *) * size);
-    memset(cfill, 0, size);
-
-    int fd = open(file_bak, O_APPEND | O_WRONLY, FILE_MODE);
-    int wr = write(fd, cfill, size);
-
-    // 写入 data
-    wr = write(fd, data, strlen(data));
-    close(fd);
+    char *cfill = (char *) calloc(size, sizeof(char));
+    int fd = open(out_file, O_APPEND | O_WRONLY, FILE_MODE);
+    int re = fd == -1 || write_all(fd, cfill, size) == -1;
+    if (fd != -1) {
+        close(fd);
+    }
+    free(cfill);
+    if (re) {
+        return 1;
+    }
 
-    // 写入 TAIL 结构体
+    // 写入 data 与 TAIL 结构体
     FILE_TAIL tail;
-    tail.pos = get_file_size(file_bak) - wr;
-    tail.size = wr;
-
-    fd = open(file_bak, O_WRONLY | O_APPEND, FILE_MODE);
-    int re = write(fd, (FILE_TAIL *) &tail, sizeof(FILE_TAIL));
-
-    close(fd);
-    if (re == -1) {
-         return 1;
-    } else {
-         return 0;
-    }
+    tail.size = strlen(data);
+    tail.pos = fsize + size;
+    return append_tail(out_file, data, tail.size, &tail);
 }
 
 typedef struct __RE {
@@ -119,49 +142,54 @@ typedef struct __RE {
     char *value;
 } RE;
 
-/* 读取密码信息 */
+/* 读取密码信息
+ * 文件尾与加密信息从同一个打开的文件读取, 读取期间文件被替换也不会读到两个版本的内容 */
 RE read_info(const char *file_name) {
 
-    FILE_TAIL *tail = read_tail(file_name);
+    RE re;
+    re.size = 0;
+    re.value = (char *) malloc(1);
+    re.value[0] = '\0';
 
-    char *buff = (char *) malloc(sizeof(char) * tail->size + 1);    // 加密信息缓冲区
     int fd = open(file_name, O_RDONLY, FILE_MODE);
-    lseek(fd, tail->pos, SEEK_SET);
-    int re_size = read(fd, buff, tail->size);
+    if (fd == -1) {
+        return re;
+    }
+    struct stat statbuff;
+    FILE_TAIL tail;
+    if (fstat(fd, &statbuff) < 0 || (unsigned long) statbuff.st_size < sizeof(FILE_TAIL)
+        || pread(fd, &tail, sizeof(FILE_TAIL), statbuff.st_size - sizeof(FILE_TAIL)) != sizeof(FILE_TAIL)
+        || tail.pos + tail.size > statbuff.st_size - sizeof(FILE_TAIL)) {
+        close(fd);
+        return re;
+    }
+
+    char *buff = (char *) malloc(sizeof(char) * tail.size + 1);    // 加密信息缓冲区
+    int re_size = pread(fd, buff, tail.size, tail.pos);
     close(fd);
+    if (re_size < 0) {
+        free(buff);
+        return re;
+    }
 
-    free(tail);
-    RE re;
+    free(re.value);
     re.size = re_size;
     re.value = buff;
     re.value[re.size] = '\0';       // 截断字符串，防止溢出
     return re;
 }
 
-/* 修改密码信息 */
-int write_info(const char *file_name, char *str) {
-
-    char *bak = ".bak";
-    char *file_bak = (char *) malloc(strlen(file_name) + strlen(bak));
-    sprintf(file_bak, "%s%s", file_name, bak);
-
+/* 修改密码信息, 写入到 out_file, 由调用者替换原文件; out_file 必须不存在 */
+int write_info(const char *file_name, const char *out_file, char *str) {
 
     FILE_TAIL *tail = read_tail(file_name);
-    copy_file(file_name, file_bak, tail->pos);
-
-    int re = append_file(file_bak, str);
-    tail->size = re;
-
-    int fd = open(file_bak, O_WRONLY | O_APPEND, FILE_MODE);
+    if (copy_file(file_name, out_file, tail->pos) == -1) {
+        free(tail);
+        return 1;
+    }
 
-    re = write(fd, tail, sizeof(FILE_TAIL));
+    tail->size = strlen(str);
+    int re = append_tail(out_file, str, tail->size, tail);
     free(tail);
-
-    close(fd);
-    
-    if (re == -1) {
-         return 1;
-    } else {
-         return 0;
-    }
+    return re;
 }
@@ -10,6 +10,7 @@
 #include <stdlib.h>
 #include <errno.h>
 #include <string.h>
+#include <termios.h>
 
 #define PACKAGE_NAME "sshpass"
 #define PASSWORD_PROMPT "assword"
@@ -25,6 +26,7 @@ enum program_return_codes {
     RETURN_INCORRECT_PASSWORD,      // 5. 密码错误
     RETURN_HOST_KEY_UNKNOWN,        // 6. 主机 key 未知
     RETURN_HOST_KEY_CHANGED,        // 7. 主机 key 已改变
+    RETURN_ESCALATION_FAILED,       // 8. 提权密码错误
 };
 
 int runprogram( int argc, char *argv[] );
@@ -62,6 +64,10 @@ int handleoutput( int fd );
 int ourtty; // Our own tty
 int masterpt;
 
+/* 匹配状态, 在多次 read 之间保留, 每次 runprogram 重置 */
+static int prevmatch; // If the "password" prompt is repeated, we have the wrong password.
+static int state1, state2;
+
 void window_resize_handler(int signum);
 void sigchld_handler(int signum);
 
@@ -69,6 +75,10 @@ int runprogram( int argc, char *argv[] )
 {
     struct winsize ttysize;                         // 设置 tty 窗口大小
 
+    prevmatch = 0;
+    state1 = 0;
+    state2 = 0;
+
     // We need to interrupt a select with a SIGCHLD. In order to do so, we need a SIGCHLD handler
     signal(SIGCHLD, sigchld_handler);
 
@@ -182,8 +192,6 @@ void write_pass( int fd );
 int handleoutput( int fd )
 {
     // We are looking for the string
-    int prevmatch = 0; // If the "password" prompt is repeated, we have the wrong password.
-    int state1, state2;
     int firsttime = 1;
     const char *compare1 = PASSWORD_PROMPT; // Asking for a password
     const char compare2[] = "The authenticity of host "; // Asks to authenticate host
@@ -314,6 +322,17 @@ void window_resize_handler(int signum)
     }
 }
 
+/* 清零后释放密码, 避免残留在内存中 */
+static void free_secret(const char *s)
+{
+    volatile char *p = (volatile char *)s;
+    if( s==NULL )
+        return;
+    while( *p )
+        *p++ = 0;
+    free((char *)s);
+}
+
 // Do nothing handler - makes sure the select will terminate if the signal arrives, though.
 void sigchld_handler(int signum)
 {
@@ -356,7 +375,7 @@ int run_main(char *ip_user, char *password, char *cmd)
     free(argv[3]);
     free(argv[4]);
     free(argv[5]);
-    free((char *)args.pwsrc.password);
+    free_secret(args.pwsrc.password);
 
     if (cmd_len > 0) {
         free(argv[6]);
@@ -365,12 +384,339 @@ int run_main(char *ip_user, char *password, char *cmd)
     return re;
 }
 
+/* 以指定参数运行 ssh, argv 以 "ssh" 开头, 供外部调用 */
+int run_args(char *password, int argc, char *argv[])
+{
+    args.pwtype = PWT_PASS;
+    args.pwsrc.password = strdup(password);
+
+    int re = runprogram( argc, argv);
+
+    free_secret(args.pwsrc.password);
+
+    return re;
+}
+
+/*
+ * 伪终端模式: ssh 的标准输入输出都接到伪终端上, 由本进程在终端与伪终端之间转发,
+ * 因此可以看到会话中的输出并应答远程的 sudo/su 密码提示.
+ * 登录后远程命令先输出以 ESC_MARKER 开头的一行, 紧接着出现的密码提示用 esc_password 应答;
+ * 紧接着的输出不是密码提示时(如 sudo 免密)不再应答.
+ */
+#define ESC_MARKER "[rssh] "
+#define ESC_PROMPT_CN "密码"
+#define ESC_PROMPT_WAIT 3   // 等待提权提示的秒数
+#define ESC_RESULT_WAIT 5   // 发送提权密码后等待失败提示的秒数
+
+enum pty_phases {
+    PH_LOGIN,       // 等待 ssh 的密码提示, session 只用密码认证, 登录前一定会出现
+    PH_LOGIN_SENT,  // 已发送登录密码, 再次出现提示说明密码错误
+    PH_ESC_WAIT,    // 等待远程输出 ESC_MARKER
+    PH_ESC_LINE,    // 跳过 ESC_MARKER 所在行的剩余内容
+    PH_ESC_PROMPT,  // 等待 sudo/su 的密码提示
+    PH_ESC_SENT,    // 已发送提权密码, 再次出现提示说明密码错误
+    PH_DONE         // 不再检查输出
+};
+
+static int ptyphase;
+static const char *esc_pass;
+static int state3;
+
+// 与 match 相同, 返回匹配结束的位置, 没有匹配完成时返回 -1
+static int match_pos( const char *reference, const char *buffer, ssize_t bufsize, int *state )
+{
+    int i;
+    for( i=0;reference[*state]!='\0' && i<bufsize; ++i ) {
+        if( reference[*state]==buffer[i] )
+            (*state)++;
+        else {
+            *state=0;
+            if( reference[*state]==buffer[i] )
+                (*state)++;
+        }
+    }
+    return reference[*state]=='\0' ? i : -1;
+}
+
+static int is_blank( const char *buffer, ssize_t bufsize )
+{
+    ssize_t i;
+    for( i=0; i<bufsize; ++i ) {
+        if( buffer[i]!='\r' && buffer[i]!='\n' && buffer[i]!=' ' )
+            return 0;
+    }
+    return 1;
+}
+
+static int contains( const char *buffer, ssize_t bufsize, const char *s )
+{
+    int state = 0;
+    return match_pos( s, buffer, bufsize, &state ) >= 0;
+}
+
+static void write_all( int fd, const char *buffer, ssize_t size )
+{
+    while( size>0 ) {
+        ssize_t n = write( fd, buffer, size );
+        if( n<0 ) {
+            if( errno==EAGAIN || errno==EINTR )
+                continue;
+            return;
+        }
+        buffer += n;
+        size -= n;
+    }
+}
+
+// 检查伪终端输出, 需要时应答密码; 返回非 0 表示需要结束
+static int handle_pty( int fd, const char *buffer, ssize_t size )
+{
+    ssize_t off = 0;
+    int pos;
+
+    while( off<size ) {
+        const char *buf = buffer + off;
+        ssize_t n = size - off;
+
+        switch( ptyphase ) {
+        case PH_LOGIN:
+            pos = match_pos( PASSWORD_PROMPT, buf, n, &state1 );
+            if( pos<0 ) {
+                state2 = match( "The authenticity of host ", buf, n, state2 );
+                return state2==25 ? RETURN_HOST_KEY_UNKNOWN : 0;
+            }
+            // 提示之后的内容(如 ": ")属于提示本身, 不再检查
+            write_pass( fd );
+            state1 = 0;
+            ptyphase = PH_LOGIN_SENT;
+            return 0;
+        case PH_LOGIN_SENT:
+            if( is_blank( buf, n ) )
+                return 0;
+            if( match_pos( PASSWORD_PROMPT, buf, n, &state1 )>=0 )
+                return RETURN_INCORRECT_PASSWORD;
+            if( contains( buf, n, "Permission denied" ) )
+                return 0;
+            // 认证通过, 同一段输出继续检查
+            state1 = 0;
+            state2 = 0;
+            ptyphase = esc_pass ? PH_ESC_WAIT : PH_DONE;
+            break;
+        case PH_ESC_WAIT:
+            pos = match_pos( ESC_MARKER, buf, n, &state2 );
+            if( pos<0 )
+                return 0;
+            ptyphase = PH_ESC_LINE;
+            off += pos;
+            break;
+        case PH_ESC_LINE:
+            pos = 0;
+            while( pos<n && buf[pos]!='\n' )
+                pos++;
+            if( pos==n )
+                return 0;
+            state1 = 0;
+            state3 = 0;
+            ptyphase = PH_ESC_PROMPT;
+            off += pos + 1;
+            break;
+        case PH_ESC_PROMPT:
+            if( is_blank( buf, n ) )
+                return 0;
+            pos = match_pos( PASSWORD_PROMPT, buf, n, &state1 );
+            if( pos<0 )
+                pos = match_pos( ESC_PROMPT_CN, buf, n, &state3 );
+            if( pos<0 ) {
+                // 提示可能分多次输出, 没有部分匹配时说明不需要密码
+                if( state1==0 && state3==0 )
+                    ptyphase = PH_DONE;
+                return 0;
+            }
+            write_all( fd, esc_pass, strlen( esc_pass ) );
+            write_all( fd, "\n", 1 );
+            state1 = 0;
+            state3 = 0;
+            ptyphase = PH_ESC_SENT;
+            return 0;
+        case PH_ESC_SENT:
+            if( is_blank( buf, n ) )
+                return 0;
+            if( match_pos( PASSWORD_PROMPT, buf, n, &state1 )>=0
+                    || match_pos( ESC_PROMPT_CN, buf, n, &state3 )>=0
+                    || contains( buf, n, "try again" )
+                    || contains( buf, n, "Authentication failure" )
+                    || contains( buf, n, "认证失败" ) )
+                return RETURN_ESCALATION_FAILED;
+            ptyphase = PH_DONE;
+            return 0;
+        default:
+            return 0;
+        }
+    }
+    return 0;
+}
+
+/* 录像回调, kind 为 'o' 输出或 'i' 输入 */
+typedef void (*record_cb)(void *ctx, int kind, const char *buf, size_t len);
+
+/* 以伪终端模式运行 ssh, esc_password 为空时不应答提权提示, cb 不为空时记录输入输出, 供外部调用 */
+int run_pty(char *password, char *esc_password, int argc, char *argv[], record_cb cb, void *ctx)
+{
+    struct winsize ttysize;
+    struct termios saved, raw;
+    int israw = 0;
+
+    args.pwtype = PWT_PASS;
+    args.pwsrc.password = strdup(password);
+    esc_pass = strlen(esc_password)>0 ? strdup(esc_password) : NULL;
+    ptyphase = PH_LOGIN;
+    state1 = 0;
+    state2 = 0;
+    state3 = 0;
+
+    signal(SIGCHLD, sigchld_handler);
+
+    masterpt = posix_openpt(O_RDWR);
+    if(masterpt == -1) {
+        perror("Failed to get a pseudo terminal");
+        return RETURN_RUNTIME_ERROR;
+    }
+    fcntl(masterpt, F_SETFL, O_NONBLOCK);
+    if(grantpt(masterpt) != 0 || unlockpt(masterpt) != 0) {
+        perror("Failed to set up pseudo terminal");
+        return RETURN_RUNTIME_ERROR;
+    }
+
+    ourtty = open("/dev/tty", 0);
+    if(ourtty != -1 && ioctl(ourtty, TIOCGWINSZ, &ttysize) == 0 ) {
+        signal(SIGWINCH, window_resize_handler);
+        ioctl(masterpt, TIOCSWINSZ, &ttysize);
+    }
+
+    const char *name = ptsname(masterpt);
+    int childpid = fork();
+    if( childpid == 0 ) {
+        /* 子进程: 伪终端作为标准输入输出 */
+        setsid();
+        int slavept = open(name, O_RDWR);
+        dup2(slavept, STDIN_FILENO);
+        dup2(slavept, STDOUT_FILENO);
+        dup2(slavept, STDERR_FILENO);
+        if( slavept>STDERR_FILENO )
+            close(slavept);
+        close(masterpt);
+
+        char **new_argv = malloc(sizeof(char *)*(argc + 1));
+        int i;
+        for(i = 0; i < argc; ++i) {
+            new_argv[i] = argv[i];
+        }
+        new_argv[i] = NULL;
+
+        execvp(new_argv[0], new_argv);
+        perror("sshpass: Failed to run command");
+        exit(RETURN_RUNTIME_ERROR);
+    } else if(childpid < 0) {
+        perror("sshpass: Failed to create child process");
+        return RETURN_RUNTIME_ERROR;
+    }
+
+    /* 父进程: 终端设为原始模式, 按键原样交给远程 */
+    if( isatty(STDIN_FILENO) && tcgetattr(STDIN_FILENO, &saved)==0 ) {
+        raw = saved;
+        cfmakeraw(&raw);
+        tcsetattr(STDIN_FILENO, TCSANOW, &raw);
+        israw = 1;
+    }
+
+    sigset_t sigmask, sigmask_select;
+    sigemptyset(&sigmask_select);
+    sigemptyset(&sigmask);
+    sigaddset(&sigmask, SIGCHLD);
+    sigprocmask(SIG_SETMASK, &sigmask, NULL);
+
+    int status = 0;
+    int terminate = 0;
+    int stdin_open = 1;
+    int exited = 0;
+    char buffer[4096];
+
+    while( !exited ) {
+        fd_set readfd;
+        FD_ZERO(&readfd);
+        FD_SET(masterpt, &readfd);
+        // 认证和提权完成前不转发输入, 避免输入被当作密码
+        if( stdin_open && ptyphase==PH_DONE )
+            FD_SET(STDIN_FILENO, &readfd);
+
+        // 等待提权提示时没有输出说明不需要密码, 发送密码后没有输出说明提权成功
+        // (远程命令可能在等待输入)
+        struct timespec wait = { ptyphase==PH_ESC_PROMPT ? ESC_PROMPT_WAIT : ESC_RESULT_WAIT, 0 };
+        int waiting = ptyphase==PH_ESC_PROMPT || ptyphase==PH_ESC_SENT;
+        int selret = pselect(masterpt+1, &readfd, NULL, NULL,
+                waiting ? &wait : NULL, &sigmask_select);
+        if( selret==0 && waiting )
+            ptyphase = PH_DONE;
+        if( selret>0 && FD_ISSET(masterpt, &readfd) ) {
+            ssize_t n = read(masterpt, buffer, sizeof(buffer));
+            if( n>0 ) {
+                write_all(STDOUT_FILENO, buffer, n);
+                if( cb )
+                    cb(ctx, 'o', buffer, n);
+                terminate = handle_pty(masterpt, buffer, n);
+                if( terminate )
+                    break;
+            }
+        }
+        if( selret>0 && stdin_open && FD_ISSET(STDIN_FILENO, &readfd) ) {
+            ssize_t n = read(STDIN_FILENO, buffer, sizeof(buffer));
+            if( n>0 ) {
+                write_all(masterpt, buffer, n);
+                if( cb )
+                    cb(ctx, 'i', buffer, n);
+            } else {
+                // 输入结束, 发送 EOF 字符给远程终端
+                stdin_open = 0;
+                write_all(masterpt, "\x04", 1);
+            }
+        }
+        if( waitpid(childpid, &status, WNOHANG)==childpid ) {
+            exited = 1;
+            // 输出剩余内容
+            ssize_t n;
+            while( (n = read(masterpt, buffer, sizeof(buffer)))>0 ) {
+                write_all(STDOUT_FILENO, buffer, n);
+                if( cb )
+                    cb(ctx, 'o', buffer, n);
+            }
+        }
+    }
+
+    if( israw )
+        tcsetattr(STDIN_FILENO, TCSANOW, &saved);
+
+    close(masterpt);
+    if( !exited )
+        waitpid(childpid, &status, 0);
+
+    free_secret(args.pwsrc.password);
+    if( esc_pass )
+        free_secret(esc_pass);
+    esc_pass = NULL;
+
+    if( terminate>0 )
+        return terminate;
+    else if( WIFEXITED( status ) )
+        return WEXITSTATUS(status);
+    else
+        return 255;
+}
+
 /* 验证密码, 供外部调用 */
 int verify_pwd(char *ip_user, char *password)
 {
     args.pwtype = PWT_PASS;
-    args.pwsrc.password = (char *) malloc(20 * sizeof(char));
-    strcpy((char *)args.pwsrc.password, password);
+    args.pwsrc.password = strdup(password);
 
     int argc = 6;
     char *argv[6];
@@ -395,6 +741,6 @@ int verify_pwd(char *ip_user, char *password)
     free(argv[3]);
     free(argv[4]);
     free(argv[5]);
-    free((char *)args.pwsrc.password);
+    free_secret(args.pwsrc.password);
     return re;
 }
@@ -0,0 +1,254 @@
+use super::result;
+use super::utils;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+// 第一条记录之前的哈希
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// 单个字段的最大长度, 超过截断
+const FIELD_MAX: usize = 512;
+
+// 一条审计记录, hash = sha256(上一条的 hash \t seq \t time \t user \t command \t target \t result)
+pub struct Entry {
+    pub seq: u64,
+    pub time: u64,
+    pub user: String,
+    pub command: String,
+    pub target: String,
+    pub result: String,
+    pub hash: String,
+}
+
+impl Entry {
+    fn fields(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.seq, self.time, self.user, self.command, self.target, self.result
+        )
+    }
+
+    fn digest(&self, prev: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input_str(prev);
+        hasher.input_str("\t");
+        hasher.input_str(&self.fields());
+        hasher.result_str()
+    }
+}
+
+/* 审计日志: 数据文件同级的 ${file_name}.audit, 每行一条记录 */
+pub fn audit_path(file_name: &str) -> String {
+    format!("{}.audit", file_name)
+}
+
+// 锚点文件: 记录数与最后一条的 hash, 在日志的锁内更新, 用于发现日志被截断或替换
+fn anchor_path(file_name: &str) -> String {
+    format!("{}.audit.anchor", file_name)
+}
+
+/* 读取锚点文件中的 (记录数, 最后的 hash) */
+pub fn anchor(file_name: &str) -> Option<(u64, String)> {
+    let content = fs::read_to_string(anchor_path(file_name)).ok()?;
+    let (count, head) = content.trim_end().split_once('\t')?;
+    Some((count.parse().ok()?, head.to_string()))
+}
+
+/* 追加一条记录并更新锚点 */
+pub fn append(
+    file_name: &str,
+    command: &str,
+    target: &str,
+    result: &str,
+) -> Result<(), result::MyErr> {
+    let path = audit_path(file_name);
+    let err = || result::MyErr {
+        msg: format!("写入审计日志 {} 失败", &path),
+    };
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(&path)
+        .map_err(|_| err())?;
+    // 多个进程同时追加时保证链不分叉, 文件关闭时释放锁
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(err());
+    }
+
+    let (seq, prev) = match last_line(&mut file).and_then(|l| parse(&l)) {
+        Some(e) => (e.seq, e.hash),
+        None => (0, String::from(GENESIS)),
+    };
+    let mut entry = Entry {
+        seq: seq + 1,
+        time: utils::now(),
+        user: os_user(),
+        command: clean(command),
+        target: clean(target),
+        result: clean(result),
+        hash: String::new(),
+    };
+    entry.hash = entry.digest(&prev);
+    writeln!(file, "{}\t{}", entry.fields(), entry.hash).map_err(|_| err())?;
+    file.sync_all().map_err(|_| err())?;
+    write_anchor(file_name, entry.seq, &entry.hash)
+}
+
+// 先写临时文件再改名, 不会留下写了一半的锚点
+fn write_anchor(file_name: &str, count: u64, head: &str) -> Result<(), result::MyErr> {
+    let path = anchor_path(file_name);
+    let tmp = format!("{}.tmp", &path);
+    let re = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut f| {
+            writeln!(f, "{}\t{}", count, head)?;
+            f.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, &path));
+    if re.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return Err(result::MyErr {
+            msg: format!("写入审计锚点 {} 失败", &path),
+        });
+    }
+    Ok(())
+}
+
+/* 读取全部记录, 无法解析的行返回 None */
+pub fn read(file_name: &str) -> Vec<Option<Entry>> {
+    fs::read_to_string(audit_path(file_name))
+        .unwrap_or_default()
+        .lines()
+        .map(parse)
+        .collect()
+}
+
+/* 检查哈希链, 与锚点文件比对; 没有锚点文件时使用 legacy (旧版本记录在数据文件中的锚点)
+ * 返回记录数与提示信息; 链断开、记录被删除或与锚点不符时返回错误 */
+pub fn verify(
+    file_name: &str,
+    legacy: Option<(u64, String)>,
+) -> Result<(u64, String), result::MyErr> {
+    // 读取期间不能有追加, 否则日志与锚点可能不一致
+    let file = fs::File::open(audit_path(file_name));
+    if let Ok(f) = &file {
+        unsafe { libc::flock(f.as_raw_fd(), libc::LOCK_SH) };
+    }
+    let anchor = self::anchor(file_name).or(legacy);
+    let entries = read(file_name);
+    let mut prev = String::from(GENESIS);
+    for (i, e) in entries.iter().enumerate() {
+        let n = i as u64 + 1;
+        let e = match e {
+            Some(e) => e,
+            None => {
+                return Err(result::MyErr {
+                    msg: format!("第 {} 行格式错误", n),
+                })
+            }
+        };
+        if e.seq != n {
+            return Err(result::MyErr {
+                msg: format!("第 {} 行序号为 {}, 记录被删除或插入", n, e.seq),
+            });
+        }
+        if e.digest(&prev) != e.hash {
+            return Err(result::MyErr {
+                msg: format!("第 {} 条记录被修改", n),
+            });
+        }
+        if let Some((count, head)) = &anchor {
+            if n == *count && &e.hash != head {
+                return Err(result::MyErr {
+                    msg: format!("第 {} 条记录与锚点记录的不符, 日志被替换", n),
+                });
+            }
+        }
+        prev.clone_from(&e.hash);
+    }
+
+    let total = entries.len() as u64;
+    let note = match anchor {
+        Some((count, _)) if count > total => {
+            return Err(result::MyErr {
+                msg: format!("锚点记录了 {} 条, 日志只有 {} 条, 日志被截断", count, total),
+            })
+        }
+        Some((count, _)) if count < total => {
+            format!("最后 {} 条记录没有记入锚点", total - count)
+        }
+        None if total > 0 => String::from("没有锚点, 无法检查日志是否被截断或替换"),
+        _ => String::new(),
+    };
+    Ok((total, note))
+}
+
+fn parse(line: &str) -> Option<Entry> {
+    let f: Vec<&str> = line.split('\t').collect();
+    if f.len() != 7 {
+        return None;
+    }
+    Some(Entry {
+        seq: f[0].parse().ok()?,
+        time: f[1].parse().ok()?,
+        user: f[2].to_string(),
+        command: f[3].to_string(),
+        target: f[4].to_string(),
+        result: f[5].to_string(),
+        hash: f[6].to_string(),
+    })
+}
+
+// 文件的最后一行, 只读取末尾部分(一行最长约 3 个字段 × FIELD_MAX 个 4 字节字符)
+fn last_line(file: &mut fs::File) -> Option<String> {
+    let len = file.metadata().ok()?.len();
+    let start = len.saturating_sub(16 * FIELD_MAX as u64);
+    file.seek(SeekFrom::Start(start)).ok()?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).ok()?;
+    let text = String::from_utf8_lossy(&buf);
+    text.lines().last().map(|l| l.to_string())
+}
+
+// 字段中不能有制表符与换行
+fn clean(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c == '\t' || c == '\n' || c == '\r' {
+                ' '
+            } else {
+                c
+            }
+        })
+        .take(FIELD_MAX)
+        .collect()
+}
+
+// 当前操作系统用户, 通过 sudo 执行时附上原用户
+fn os_user() -> String {
+    let uid = unsafe { libc::getuid() };
+    let pw = unsafe { libc::getpwuid(uid) };
+    let name = if pw.is_null() {
+        uid.to_string()
+    } else {
+        unsafe { std::ffi::CStr::from_ptr((*pw).pw_name) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    match std::env::var("SUDO_USER") {
+        Ok(s) if !s.is_empty() && s != name => format!("{}(sudo:{})", name, s),
+        _ => name,
+    }
+}
@@ -0,0 +1,149 @@
+use super::my_crypto;
+use super::result;
+use super::utils;
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+// 缺省保留份数与天数
+pub const DEFAULT_KEEP: usize = 10;
+pub const DEFAULT_DAYS: u64 = 30;
+
+// 一份快照, id 为文件名去掉后缀
+pub struct Snapshot {
+    pub id: String,
+    pub time: u64,
+    pub size: u64,
+}
+
+/* 快照目录: 数据文件同级的 ${file_name}.backups */
+pub fn backup_dir(file_name: &str) -> String {
+    format!("{}.backups", file_name)
+}
+
+/* 写入一份快照, 内容为数据的 json, 用执行密码派生的密钥加密
+ * 然后按份数与天数清理旧快照 */
+pub fn snapshot(
+    file_name: &str,
+    json_data: &str,
+    pwd: &str,
+    keep: usize,
+    days: u64,
+) -> Result<(), result::MyErr> {
+    let data = my_crypto::encrypt_bundle(json_data, pwd)?;
+    write(file_name, &data, keep, days)
+}
+
+/* 无法解密的数据原样写入快照, 供 doctor 从中找回 */
+pub fn snapshot_raw(
+    file_name: &str,
+    raw: &str,
+    keep: usize,
+    days: u64,
+) -> Result<(), result::MyErr> {
+    write(file_name, raw, keep, days)
+}
+
+/* 解密快照内容; 旧版本的快照只用数据文件的固定密钥加密, 不需要密码 */
+pub fn decrypt(raw: &str, pwd: &str) -> Result<String, result::MyErr> {
+    match encrypted(raw) {
+        true => my_crypto::decrypt_bundle(raw, pwd),
+        false => my_crypto::my_crypto(raw, false),
+    }
+}
+
+/* 快照是否用执行密码加密 */
+pub fn encrypted(raw: &str) -> bool {
+    raw.starts_with(my_crypto::BUNDLE_HEADER)
+}
+
+fn write(file_name: &str, data: &str, keep: usize, days: u64) -> Result<(), result::MyErr> {
+    let dir = backup_dir(file_name);
+    if fs::metadata(&dir).is_err() && fs::DirBuilder::new().mode(0o700).create(&dir).is_err() {
+        return Err(result::MyErr {
+            msg: format!("创建备份目录 {} 失败", &dir),
+        });
+    }
+
+    // 同一秒内多次保存时追加序号
+    let now = utils::now();
+    let mut id = now.to_string();
+    let mut n = 1;
+    while fs::metadata(snapshot_path(file_name, &id)).is_ok() {
+        id = format!("{}-{}", now, n);
+        n += 1;
+    }
+
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(snapshot_path(file_name, &id))
+        .and_then(|mut f| f.write_all(data.as_bytes()));
+    if written.is_err() {
+        return Err(result::MyErr {
+            msg: format!("写入备份 {} 失败", &id),
+        });
+    }
+
+    rotate(file_name, keep, days);
+    Ok(())
+}
+
+/* 列出快照, 按时间从新到旧 */
+pub fn list(file_name: &str) -> Vec<Snapshot> {
+    let mut snaps = Vec::new();
+    let entries = match fs::read_dir(backup_dir(file_name)) {
+        Ok(e) => e,
+        Err(_) => return snaps,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.ends_with(".snap") {
+            continue;
+        }
+        let id = name.trim_end_matches(".snap").to_string();
+        let time = id.split('-').next().unwrap_or("0").parse().unwrap_or(0);
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        snaps.push(Snapshot { id, time, size });
+    }
+    snaps.sort_by(|a, b| (b.time, &b.id).cmp(&(a.time, &a.id)));
+    snaps
+}
+
+/* 读取快照内容 */
+pub fn read(file_name: &str, id: &str) -> Result<String, result::MyErr> {
+    // id 只能是 list 列出的名字, 防止读取目录外的文件
+    if !list(file_name).iter().any(|s| s.id == id) {
+        return Err(result::MyErr {
+            msg: format!("备份 {} 不存在", id),
+        });
+    }
+    match fs::read_to_string(snapshot_path(file_name, id)) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(result::MyErr {
+            msg: format!("读取备份 {} 失败", id),
+        }),
+    }
+}
+
+fn snapshot_path(file_name: &str, id: &str) -> String {
+    format!("{}/{}.snap", backup_dir(file_name), id)
+}
+
+// 超过份数或天数的快照删除, 最新的一份总是保留; 0 表示不限制
+fn rotate(file_name: &str, keep: usize, days: u64) {
+    let now = utils::now();
+    for (i, s) in list(file_name).iter().enumerate() {
+        if i == 0 {
+            continue;
+        }
+        let too_many = keep > 0 && i >= keep;
+        let too_old = days > 0 && s.time + days * 86400 < now;
+        if too_many || too_old {
+            let _ = fs::remove_file(snapshot_path(file_name, &s.id));
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use super::hostkey;
+use super::rdata;
+use super::secret::Secret;
+use super::ssh;
+
+use std::io::prelude::*;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 运行中的检查子进程, 输出由读取线程收集, 避免管道写满时子进程阻塞
+struct Running {
+    index: usize,
+    child: Child,
+    started: Instant,
+    stdout: thread::JoinHandle<String>,
+    stderr: thread::JoinHandle<String>,
+}
+
+// 缺省并发数
+pub const DEFAULT_JOBS: usize = 8;
+// 单个主机缺省的超时时间(秒), 包括连接与认证
+pub const DEFAULT_TIMEOUT: u64 = 20;
+
+// 检查结果
+#[derive(PartialEq)]
+pub enum Status {
+    Ok,
+    AuthFailed,
+    HostKeyChanged,
+    HostKeyUnknown,
+    Unreachable,
+    Timeout,
+}
+
+impl Status {
+    pub fn name(&self) -> &str {
+        match self {
+            Status::Ok => "ok",
+            Status::AuthFailed => "auth_failed",
+            Status::HostKeyChanged => "hostkey_changed",
+            Status::HostKeyUnknown => "hostkey_unknown",
+            Status::Unreachable => "unreachable",
+            Status::Timeout => "timeout",
+        }
+    }
+}
+
+// 一个检查目标
+pub struct Target {
+    pub pwd: rdata::Pwd,
+    pub port: u16,
+    pub proxy: String,     // 跳板机的 ProxyCommand
+    pub keys: Vec<String>, // 保存的主机公钥
+}
+
+/* 并发检查多个条目, exe 为自身路径, limit 为单个主机的超时(秒)
+ * 结果与 targets 顺序一致, 消息为 ssh 的最后一行输出 */
+pub fn check(exe: &str, targets: &[Target], jobs: usize, limit: u64) -> Vec<(Status, String)> {
+    let mut results: Vec<Option<(Status, String)>> = targets.iter().map(|_| None).collect();
+    let mut running: Vec<Running> = Vec::new();
+    let mut next = 0;
+
+    while next < targets.len() || !running.is_empty() {
+        // 补足并发数
+        while next < targets.len() && running.len() < jobs.max(1) {
+            // 没有保存主机公钥时不连接, 避免把密码发给中间人
+            if targets[next].keys.is_empty() {
+                results[next] = Some((Status::HostKeyUnknown, String::from("没有保存主机公钥")));
+                next += 1;
+                continue;
+            }
+            match spawn(exe, next, &targets[next]) {
+                Ok(r) => running.push(r),
+                Err(msg) => results[next] = Some((Status::Unreachable, msg)),
+            }
+            next += 1;
+        }
+
+        let mut i = 0;
+        while i < running.len() {
+            let timeout = running[i].started.elapsed() > Duration::from_secs(limit);
+            let done = match running[i].child.try_wait() {
+                Ok(Some(_)) => true,
+                Ok(None) => timeout,
+                Err(_) => true,
+            };
+            if !done {
+                i += 1;
+                continue;
+            }
+            let mut r = running.remove(i);
+            if timeout {
+                let _ = r.child.kill();
+            }
+            let n = r.index;
+            results[n] = Some(collect(r, timeout));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    results.into_iter().map(|r| r.unwrap()).collect()
+}
+
+/* 子进程入口: 从标准输入读密码与主机公钥, 执行 ssh::probe 后以其返回码退出
+ * opts 为配置中的 ssh 程序与连接超时 */
+pub fn probe_main(ip_user: &str, port: u16, proxy: &str, mut opts: ssh::Options) -> i32 {
+    let mut input = String::new();
+    if std::io::stdin().read_to_string(&mut input).is_err() {
+        return 3;
+    }
+    let input = Secret::new(input);
+    let (password, keys) = match decode_input(&input) {
+        Some(p) => p,
+        None => return 3,
+    };
+
+    opts.port = port;
+    opts.proxy = proxy.to_string();
+    if !keys.is_empty() {
+        let ip = ip_user.rsplit('@').next().unwrap_or(ip_user);
+        match hostkey::KnownHosts::new(ip, port, &keys) {
+            Ok(k) => opts.known_hosts = Some(k),
+            Err(_) => return 3,
+        }
+    }
+    ssh::probe(ip_user, password, &opts)
+}
+
+// 每个条目由一个子进程执行, sshpass 使用全局状态, 不能在线程中并发
+fn spawn(exe: &str, index: usize, t: &Target) -> Result<Running, String> {
+    let child = Command::new(exe)
+        .arg("__probe")
+        .arg(format!("{}@{}", t.pwd.user, t.pwd.ip))
+        .arg(t.port.to_string())
+        .arg(&t.proxy)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(c) => c,
+        Err(_) => return Err(String::from("启动子进程失败")),
+    };
+    // 密码通过管道传递, 不出现在命令行参数中
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(encode_input(&t.pwd.password, &t.keys).as_bytes());
+    }
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    Ok(Running {
+        index,
+        child,
+        started: Instant::now(),
+        stdout,
+        stderr,
+    })
+}
+
+// 在线程中读完管道
+fn read_all<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut out = String::new();
+        if let Some(mut p) = pipe {
+            let _ = p.read_to_string(&mut out);
+        }
+        out
+    })
+}
+
+// 子进程的输入: 密码长度一行, 密码, 换行, 然后每行一个主机公钥; 密码中可以有换行
+fn encode_input(password: &str, keys: &[String]) -> Secret {
+    let mut input = format!("{}\n{}\n", password.len(), password);
+    for k in keys {
+        input.push_str(k);
+        input.push('\n');
+    }
+    Secret::new(input)
+}
+
+// 解析 encode_input 的输出
+fn decode_input(input: &str) -> Option<(&str, Vec<String>)> {
+    let nl = input.find('\n')?;
+    let len: usize = input[..nl].parse().ok()?;
+    let rest = &input[nl + 1..];
+    let password = rest.get(..len)?;
+    let keys = rest[len..]
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .collect();
+    Some((password, keys))
+}
+
+fn collect(mut r: Running, timeout: bool) -> (Status, String) {
+    // 超时的子进程已被结束, ssh 可能仍持有管道, 不等待读取线程
+    if timeout {
+        let _ = r.child.wait();
+        return (Status::Timeout, String::from("检查超时"));
+    }
+    let code = match r.child.wait() {
+        Ok(s) => s.code().unwrap_or(255),
+        Err(_) => 255,
+    };
+    let mut out = r.stdout.join().unwrap_or_default();
+    out.push_str(&r.stderr.join().unwrap_or_default());
+    classify(code, &out)
+}
+
+// 根据 ssh 的返回码与输出判断结果
+fn classify(code: i32, out: &str) -> (Status, String) {
+    let msg = out
+        .lines()
+        .map(|l| l.trim())
+        .rfind(|l| !l.is_empty() && !l.starts_with('@') && *l != ssh::PROBE_OK)
+        .unwrap_or("")
+        .to_string();
+
+    let status = if out.contains(ssh::PROBE_OK) {
+        Status::Ok
+    } else if out.contains("host key is known") || code == 6 {
+        Status::HostKeyUnknown
+    } else if out.contains("REMOTE HOST IDENTIFICATION HAS CHANGED")
+        || out.contains("Host key verification failed")
+        || code == 7
+    {
+        Status::HostKeyChanged
+    } else if out.contains("Permission denied") || code == 5 {
+        Status::AuthFailed
+    } else if out.contains("timed out") {
+        Status::Timeout
+    } else {
+        Status::Unreachable
+    };
+    let msg = match status {
+        Status::Ok => String::new(),
+        _ if msg.is_empty() => format!("ssh 返回 {}", code),
+        _ => msg,
+    };
+    (status, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_by_output_and_code() {
+        let ok = format!("Warning: Permanently added\n{}\n", ssh::PROBE_OK);
+        assert!(classify(0, &ok) == (Status::Ok, String::new()));
+
+        let (s, msg) = classify(
+            255,
+            "@@@@@@@@@@@@\n@ WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED! @\nHost key verification failed.\n",
+        );
+        assert!(s == Status::HostKeyChanged);
+        assert_eq!(msg, "Host key verification failed.");
+        assert!(classify(7, "").0 == Status::HostKeyChanged);
+
+        let (s, _) = classify(
+            255,
+            "No ED25519 host key is known for 10.0.0.1 and you have requested strict checking.\nHost key verification failed.\n",
+        );
+        assert!(s == Status::HostKeyUnknown);
+
+        let (s, msg) = classify(255, "root@10.0.0.1: Permission denied (password).\n");
+        assert!(s == Status::AuthFailed);
+        assert_eq!(msg, "root@10.0.0.1: Permission denied (password).");
+        assert!(classify(5, "").0 == Status::AuthFailed);
+
+        let (s, _) = classify(
+            255,
+            "ssh: connect to host 10.0.0.1 port 22: Connection timed out\n",
+        );
+        assert!(s == Status::Timeout);
+
+        let (s, msg) = classify(
+            255,
+            "ssh: connect to host 10.0.0.1 port 22: Connection refused\n\n",
+        );
+        assert!(s == Status::Unreachable);
+        assert_eq!(
+            msg,
+            "ssh: connect to host 10.0.0.1 port 22: Connection refused"
+        );
+        assert!(classify(3, "") == (Status::Unreachable, String::from("ssh 返回 3")));
+    }
+
+    #[test]
+    fn input_keeps_newlines_in_password() {
+        let keys = vec![String::from("10.0.0.1 ssh-ed25519 AAAA")];
+        for password in &["plain", "两行\n密码", "", "trailing\n"] {
+            let input = encode_input(password, &keys);
+            let (p, k) = decode_input(&input).unwrap();
+            assert_eq!(p, *password);
+            assert_eq!(k, keys);
+        }
+        let input = encode_input("x", &[]);
+        assert!(decode_input(&input).unwrap().1.is_empty());
+        assert!(decode_input("5\nabc").is_none());
+        assert!(decode_input("abc\n").is_none());
+    }
+}
@@ -0,0 +1,336 @@
+use super::check;
+use super::output;
+use super::result;
+use super::ssh;
+
+use std::collections::BTreeMap;
+use std::fs;
+
+// 系统配置, 用户配置 ~/.config/rssh/config.toml 中的同名设置覆盖它
+const SYSTEM_FILE: &str = "/etc/rssh/config.toml";
+
+// 解锁策略: vault 按数据文件中的设置(-f/-k 的 flag), always 所有命令都需要密码
+// 配置只能收紧数据文件中的设置, 不能放宽
+pub const UNLOCK: [&str; 2] = ["vault", "always"];
+
+// 按主机的缺省值, pattern 支持 * 与 ? 通配, 匹配命令行中的 ip 或别名
+#[derive(Debug)]
+pub struct HostDefault {
+    pub pattern: String,
+    pub user: String, // 为空时使用全局的缺省用户
+    pub port: u16,    // 0 表示未设置
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub user: String,                  // 缺省登录用户
+    pub vault: String,                 // 数据文件路径, 为空时使用可执行文件自身
+    pub vault_dir: String,             // 命名 vault 所在目录
+    pub identity: String,              // team vault 的私钥文件
+    pub unlock: String,                // 解锁策略
+    pub ssh: String,                   // ssh 程序
+    pub connect_timeout: u64,          // ssh 的 ConnectTimeout(秒), 0 表示使用各命令的缺省值
+    pub check_timeout: u64,            // check 单个主机的超时(秒)
+    pub format: String,                // 缺省输出格式
+    pub hosts: Vec<HostDefault>,       // 按出现顺序, 后面的覆盖前面的
+    sources: BTreeMap<String, String>, // 设置 -> 所在的配置文件
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            user: String::from("root"),
+            vault: String::new(),
+            vault_dir: default_vault_dir(),
+            identity: default_identity(),
+            unlock: String::from("vault"),
+            ssh: String::from("ssh"),
+            connect_timeout: 0,
+            check_timeout: check::DEFAULT_TIMEOUT,
+            format: String::from("plain"),
+            hosts: Vec::new(),
+            sources: BTreeMap::new(),
+        }
+    }
+}
+
+/* 依次读取系统配置与用户配置, 文件不存在时使用缺省值 */
+pub fn load() -> Result<Config, result::MyErr> {
+    let mut config = Config::default();
+    let mut files = vec![String::from(SYSTEM_FILE)];
+    if let Some(f) = user_file() {
+        files.push(f);
+    }
+    for file in &files {
+        if let Ok(content) = fs::read_to_string(file) {
+            config.apply(file, &content)?;
+        }
+    }
+    Ok(config)
+}
+
+// $XDG_CONFIG_HOME/rssh/config.toml, 未设置时为 ~/.config/rssh/config.toml
+fn user_file() -> Option<String> {
+    user_dir().map(|d| format!("{}/config.toml", d))
+}
+
+// $XDG_CONFIG_HOME/rssh, 未设置时为 ~/.config/rssh
+fn user_dir() -> Option<String> {
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(d) if !d.is_empty() => Some(format!("{}/rssh", d)),
+        _ => std::env::var("HOME")
+            .ok()
+            .map(|h| format!("{}/.config/rssh", h)),
+    }
+}
+
+// 用户配置目录下的 identity
+fn default_identity() -> String {
+    user_dir()
+        .map(|d| format!("{}/identity", d))
+        .unwrap_or_default()
+}
+
+// $XDG_DATA_HOME/rssh/vaults, 未设置时为 ~/.local/share/rssh/vaults
+fn default_vault_dir() -> String {
+    match std::env::var("XDG_DATA_HOME") {
+        Ok(d) if !d.is_empty() => format!("{}/rssh/vaults", d),
+        _ => format!(
+            "{}/.local/share/rssh/vaults",
+            std::env::var("HOME").unwrap_or_default()
+        ),
+    }
+}
+
+impl Config {
+    /* 合并一个配置文件的内容 */
+    fn apply(&mut self, file: &str, content: &str) -> Result<(), result::MyErr> {
+        let mut parser = toml::Parser::new(content);
+        let table = match parser.parse() {
+            Some(t) => t,
+            None => {
+                let e = &parser.errors[0];
+                let (line, _) = parser.to_linecol(e.lo);
+                return Err(result::MyErr {
+                    msg: format!("配置文件 {} 第 {} 行: {}", file, line + 1, e.desc),
+                });
+            }
+        };
+        let invalid = |key: &str| result::MyErr {
+            msg: format!("配置文件 {}: {} 的值无效", file, key),
+        };
+
+        for (key, value) in &table {
+            match key.as_str() {
+                "user" => match value.as_str() {
+                    Some(u) if !u.is_empty() => self.user = u.to_string(),
+                    _ => return Err(invalid(key)),
+                },
+                "vault" => match value.as_str().and_then(expand_home) {
+                    Some(v) if v.starts_with('/') => self.vault = v,
+                    _ => return Err(invalid(key)),
+                },
+                "vault_dir" => match value.as_str().and_then(expand_home) {
+                    Some(v) if v.starts_with('/') => self.vault_dir = v,
+                    _ => return Err(invalid(key)),
+                },
+                "identity" => match value.as_str().and_then(expand_home) {
+                    Some(v) if v.starts_with('/') => self.identity = v,
+                    _ => return Err(invalid(key)),
+                },
+                "unlock" => match value.as_str() {
+                    Some(u) if UNLOCK.contains(&u) => self.unlock = u.to_string(),
+                    _ => return Err(invalid(key)),
+                },
+                "ssh" => match value.as_str() {
+                    Some(s) if !s.is_empty() => self.ssh = s.to_string(),
+                    _ => return Err(invalid(key)),
+                },
+                "connect_timeout" => match value.as_integer() {
+                    Some(t) if t >= 0 => self.connect_timeout = t as u64,
+                    _ => return Err(invalid(key)),
+                },
+                "check_timeout" => match value.as_integer() {
+                    Some(t) if t > 0 => self.check_timeout = t as u64,
+                    _ => return Err(invalid(key)),
+                },
+                "format" => match value.as_str() {
+                    Some(f) if output::check_format(f).is_ok() => self.format = f.to_string(),
+                    _ => return Err(invalid(key)),
+                },
+                "hosts" => {
+                    let hosts = match value.as_table() {
+                        Some(h) => h,
+                        None => return Err(invalid(key)),
+                    };
+                    for (pattern, host) in hosts {
+                        self.apply_host(file, pattern, host)?;
+                    }
+                    continue;
+                }
+                _ => {
+                    return Err(result::MyErr {
+                        msg: format!("配置文件 {}: 未知设置 {}", file, key),
+                    })
+                }
+            }
+            self.sources.insert(key.clone(), file.to_string());
+        }
+        Ok(())
+    }
+
+    // [hosts."pattern"] 中的 user 与 port
+    fn apply_host(
+        &mut self,
+        file: &str,
+        pattern: &str,
+        value: &toml::Value,
+    ) -> Result<(), result::MyErr> {
+        let name = format!("hosts.\"{}\"", pattern);
+        let invalid = |key: &str| result::MyErr {
+            msg: format!("配置文件 {}: {}.{} 的值无效", file, &name, key),
+        };
+        let table = match value.as_table() {
+            Some(t) => t,
+            None => return Err(invalid("")),
+        };
+
+        let i = match self.hosts.iter().position(|h| h.pattern == pattern) {
+            Some(i) => i,
+            None => {
+                self.hosts.push(HostDefault {
+                    pattern: pattern.to_string(),
+                    user: String::new(),
+                    port: 0,
+                });
+                self.hosts.len() - 1
+            }
+        };
+        for (key, v) in table {
+            match key.as_str() {
+                "user" => match v.as_str() {
+                    Some(u) if !u.is_empty() => self.hosts[i].user = u.to_string(),
+                    _ => return Err(invalid(key)),
+                },
+                "port" => match v.as_integer() {
+                    Some(p) if p > 0 && p <= 65535 => self.hosts[i].port = p as u16,
+                    _ => return Err(invalid(key)),
+                },
+                _ => {
+                    return Err(result::MyErr {
+                        msg: format!("配置文件 {}: 未知设置 {}.{}", file, &name, key),
+                    })
+                }
+            }
+            self.sources
+                .insert(format!("{}.{}", &name, key), file.to_string());
+        }
+        Ok(())
+    }
+
+    /* 登录用户: 匹配的主机设置, 没有时为全局缺省 */
+    pub fn user_for(&self, host: &str) -> String {
+        self.matching(host)
+            .filter(|h| !h.user.is_empty())
+            .last()
+            .map(|h| h.user.clone())
+            .unwrap_or_else(|| self.user.clone())
+    }
+
+    /* 主机端口, 数据文件中没有设置时使用, 0 表示未设置 */
+    pub fn port_for(&self, host: &str) -> u16 {
+        self.matching(host)
+            .filter(|h| h.port != 0)
+            .last()
+            .map(|h| h.port)
+            .unwrap_or(0)
+    }
+
+    fn matching<'a>(&'a self, host: &'a str) -> impl Iterator<Item = &'a HostDefault> {
+        self.hosts.iter().filter(move |h| glob(&h.pattern, host))
+    }
+
+    /* 连接选项的公共部分: ssh 程序与连接超时 */
+    pub fn ssh_options(&self) -> ssh::Options {
+        ssh::Options {
+            program: self.ssh.clone(),
+            timeout: self.connect_timeout,
+            ..Default::default()
+        }
+    }
+
+    /* 生效的设置, 每行为 设置、值、来源; vault 为实际使用的数据文件 */
+    pub fn settings(&self, vault: &str) -> Vec<Vec<String>> {
+        let timeout = match self.connect_timeout {
+            0 => String::from("-"),
+            t => t.to_string(),
+        };
+        let mut rows: Vec<Vec<String>> = vec![
+            vec![String::from("user"), self.user.clone()],
+            vec![String::from("vault"), vault.to_string()],
+            vec![String::from("vault_dir"), self.vault_dir.clone()],
+            vec![String::from("identity"), self.identity.clone()],
+            vec![String::from("unlock"), self.unlock.clone()],
+            vec![String::from("ssh"), self.ssh.clone()],
+            vec![String::from("connect_timeout"), timeout],
+            vec![
+                String::from("check_timeout"),
+                self.check_timeout.to_string(),
+            ],
+            vec![String::from("format"), self.format.clone()],
+        ];
+        for h in &self.hosts {
+            let name = format!("hosts.\"{}\"", h.pattern);
+            if !h.user.is_empty() {
+                rows.push(vec![format!("{}.user", name), h.user.clone()]);
+            }
+            if h.port != 0 {
+                rows.push(vec![format!("{}.port", name), h.port.to_string()]);
+            }
+        }
+        for row in rows.iter_mut() {
+            let source = self
+                .sources
+                .get(&row[0])
+                .cloned()
+                .unwrap_or_else(|| String::from("缺省"));
+            row.push(source);
+        }
+        rows
+    }
+}
+
+// 开头的 ~/ 替换为用户目录
+fn expand_home(path: &str) -> Option<String> {
+    match path.strip_prefix("~/") {
+        Some(rest) => std::env::var("HOME")
+            .ok()
+            .map(|h| format!("{}/{}", h.trim_end_matches('/'), rest)),
+        None => Some(path.to_string()),
+    }
+}
+
+// 通配符匹配, * 匹配任意个字符, ? 匹配一个字符
+fn glob(pattern: &str, s: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    let (mut i, mut j) = (0, 0);
+    // 最近一个 * 的位置与当时 s 的位置, 失配时回溯
+    let mut star: Option<(usize, usize)> = None;
+    while j < s.len() {
+        if i < p.len() && (p[i] == '?' || p[i] == s[j]) {
+            i += 1;
+            j += 1;
+        } else if i < p.len() && p[i] == '*' {
+            star = Some((i, j));
+            i += 1;
+        } else if let Some((si, sj)) = star {
+            i = si + 1;
+            j = sj + 1;
+            star = Some((si, sj + 1));
+        } else {
+            return false;
+        }
+    }
+    p[i..].iter().all(|c| *c == '*')
+}
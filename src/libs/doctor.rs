@@ -0,0 +1,521 @@
+use super::backup;
+use super::my_crypto;
+use super::output;
+use super::params;
+use super::rdata;
+use super::result;
+use super::utils;
+
+use std::fs;
+
+// 初始化时加密数据前填充的 0 的个数, 见 rdata::init
+const FILL_SIZE: usize = 20;
+
+// 数据文件中加密数据的位置
+struct Storage {
+    bytes: Vec<u8>,
+    tail: bool,           // 文件尾完整
+    start: Option<usize>, // 加密数据的偏移, 找不到时为 None
+    raw: String,          // 加密数据(base64)
+}
+
+// 检查结果
+struct Report {
+    rows: Vec<Vec<String>>,
+    problems: usize,
+    data: Option<rdata::Rdata>, // 能完整读取时的数据
+    partial: String,            // 不能完整读取时解出的部分 json
+}
+
+impl Report {
+    fn row(&mut self, check: &str, status: &str, detail: String) {
+        if status != "ok" {
+            self.problems += 1;
+        }
+        self.rows
+            .push(vec![check.to_string(), status.to_string(), detail]);
+    }
+}
+
+/* doctor check|repair */
+pub fn run(params: &params::Params) -> Result<(), result::MyErr> {
+    let file_name = &params.file_path;
+    let bytes = match fs::read(file_name) {
+        Ok(b) => b,
+        Err(_) => {
+            return Err(result::MyErr {
+                msg: format!("数据文件 {} 不存在", file_name),
+            })
+        }
+    };
+    let storage = locate(bytes);
+    let mut report = diagnose(file_name, &storage);
+    // 备份只作参考, 不计入问题
+    report.rows.push(backup_row(file_name));
+
+    let header = ["check", "status", "detail"];
+    if &params.format == "plain" {
+        output::print_aligned(&header, &report.rows);
+    } else {
+        output::print(&params.format, &header, &report.rows);
+    }
+    if &params.action == "repair" {
+        return repair(file_name, &storage, report, params);
+    }
+    if report.problems > 0 {
+        return Err(result::MyErr {
+            msg: format!(
+                "发现 {} 个问题, 可执行 rssh doctor repair 尝试修复",
+                report.problems
+            ),
+        });
+    }
+    println!("数据文件正常");
+    Ok(())
+}
+
+/* -f 重新初始化前检查: 数据文件中还有数据时需要确认, 损坏的数据先另存一份
+ * 返回 false 表示取消 */
+pub fn guard_init(params: &params::Params) -> Result<bool, result::MyErr> {
+    let file_name = &params.file_path;
+    let what = match rdata::Rdata::get(file_name) {
+        Ok(data) => match data.count() {
+            (0, _) => return Ok(true),
+            (hosts, users) => format!(
+                "已有 {} 个主机、{} 个条目, 重新初始化将清空(会先备份)",
+                hosts, users
+            ),
+        },
+        Err(_) if damaged(file_name) => String::from(
+            "中有无法读取的数据, 可以先执行 rssh doctor repair 尝试恢复; 重新初始化将清空",
+        ),
+        Err(_) => return Ok(true),
+    };
+    let prompt = format!("数据文件 {} {}, 确认? [y/N]: ", file_name, what);
+    if !params.yes && !utils::confirm(&prompt) {
+        return Ok(false);
+    }
+    if damaged(file_name) {
+        println!("损坏的数据文件已另存为 {}", keep_damaged(file_name)?);
+    }
+    Ok(true)
+}
+
+/* 数据文件不能读取, 但其中还有加密数据 */
+pub fn damaged(file_name: &str) -> bool {
+    if rdata::Rdata::get(file_name).is_ok() {
+        return false;
+    }
+    match fs::read(file_name) {
+        Ok(bytes) => locate(bytes).start.is_some(),
+        Err(_) => false,
+    }
+}
+
+// 把损坏的数据文件另存一份, 返回文件名
+fn keep_damaged(file_name: &str) -> Result<String, result::MyErr> {
+    let kept = format!("{}.damaged-{}", file_name, utils::now());
+    match fs::copy(file_name, &kept) {
+        Ok(_) => Ok(kept),
+        Err(_) => Err(result::MyErr {
+            msg: format!("另存损坏的数据文件 {} 失败", file_name),
+        }),
+    }
+}
+
+// 找到加密数据: 优先按文件尾, 文件尾损坏时从后向前找填充的 0 之后的 base64
+fn locate(bytes: Vec<u8>) -> Storage {
+    let len = bytes.len();
+    let tail = rdata::TAIL_SIZE as usize;
+    if len >= tail {
+        let half = tail / 2;
+        let mut pos = [0u8; std::mem::size_of::<usize>()];
+        let mut size = [0u8; std::mem::size_of::<usize>()];
+        pos.copy_from_slice(&bytes[len - tail..len - half]);
+        size.copy_from_slice(&bytes[len - half..]);
+        let (pos, size) = (usize::from_ne_bytes(pos), usize::from_ne_bytes(size));
+        if size > 0 && pos.checked_add(size) == Some(len - tail) {
+            let raw = String::from_utf8_lossy(&bytes[pos..pos + size]).into_owned();
+            return Storage {
+                bytes,
+                tail: true,
+                start: Some(pos),
+                raw,
+            };
+        }
+    }
+
+    let mut start = None;
+    for p in (FILL_SIZE..len).rev() {
+        if bytes[p - 1] == 0
+            && is_base64(bytes[p])
+            && bytes[p - FILL_SIZE..p].iter().all(|b| *b == 0)
+        {
+            start = Some(p);
+            break;
+        }
+    }
+    let raw: String = match start {
+        Some(p) => bytes[p..]
+            .iter()
+            .take_while(|b| is_base64(**b))
+            .map(|b| *b as char)
+            .collect(),
+        None => String::new(),
+    };
+    // 可执行文件中也有 0 之后跟字母的内容, 能解出 json 开头才是加密数据
+    let head: String = raw.chars().take(64).collect();
+    if !my_crypto::my_crypto_partial(&head).starts_with("{\"") {
+        start = None;
+    }
+    Storage {
+        bytes,
+        tail: false,
+        start,
+        raw,
+    }
+}
+
+fn is_base64(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'+' || b == b'/' || b == b'='
+}
+
+// 依次检查文件尾、base64、解密、json 与数据内容
+fn diagnose(file_name: &str, s: &Storage) -> Report {
+    let mut r = Report {
+        rows: Vec::new(),
+        problems: 0,
+        data: None,
+        partial: String::new(),
+    };
+    r.row(
+        "文件",
+        "ok",
+        format!("{}, {} 字节", file_name, s.bytes.len()),
+    );
+    match (s.tail, s.start) {
+        (true, Some(p)) => r.row("文件尾", "ok", format!("偏移 {}, 大小 {}", p, s.raw.len())),
+        (_, Some(p)) => {
+            r.row("文件尾", "fail", String::from("不完整或已损坏"));
+            r.row(
+                "加密数据",
+                "warn",
+                format!("在偏移 {} 找到 {} 字节", p, s.raw.len()),
+            );
+        }
+        (_, None) => {
+            r.row("文件尾", "fail", String::from("不完整或已损坏"));
+            r.row("加密数据", "fail", String::from("未找到"));
+            return r;
+        }
+    }
+
+    match base64::decode(&s.raw) {
+        Ok(d) => r.row("base64", "ok", format!("{} 字节", d.len())),
+        Err(_) if s.raw.len() % 4 != 0 => r.row(
+            "base64",
+            "fail",
+            String::from("长度不是 4 的倍数, 数据可能被截断"),
+        ),
+        Err(_) => r.row("base64", "fail", String::from("包含无效字符")),
+    }
+    let json_data = match my_crypto::my_crypto(&s.raw, false) {
+        Ok(j) => {
+            r.row("解密", "ok", String::new());
+            Some(j)
+        }
+        Err(e) => {
+            r.row("解密", "fail", e.msg);
+            None
+        }
+    };
+    match json_data.map(|j| rdata::Rdata::from_json(&j)) {
+        Some(Ok(data)) => {
+            let (hosts, users) = data.count();
+            r.row("json", "ok", format!("{} 个主机, {} 个条目", hosts, users));
+            for p in data.check() {
+                r.row("内容", "warn", p);
+            }
+            r.data = Some(data);
+            return r;
+        }
+        Some(Err(e)) => r.row("json", "fail", e.msg),
+        None => {}
+    }
+
+    // 不能完整读取, 看残留的数据能找回多少
+    r.partial = my_crypto::my_crypto_partial(&s.raw);
+    let mut found = rdata::Rdata::new("", 0);
+    match found.salvage(&r.partial) {
+        0 => r.row("残留数据", "fail", String::from("没有完整的主机")),
+        n => r.row("残留数据", "warn", format!("可找回 {} 个主机", n)),
+    }
+    r
+}
+
+// 最新的可用备份, pwd 用于解密用执行密码加密的备份
+// 能用 pwd 解密时备份写入时的执行密码即为 pwd, 修改密码后的第一份备份中的数据仍是旧密码
+fn latest_backup(file_name: &str, pwd: &str) -> Option<(backup::Snapshot, rdata::Rdata)> {
+    for s in backup::list(file_name) {
+        let raw = match backup::read(file_name, &s.id) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        let data = backup::decrypt(&raw, pwd).and_then(|j| rdata::Rdata::from_json(&j));
+        if let Ok(mut d) = data {
+            if backup::encrypted(&raw) {
+                d.pwd = pwd.to_string();
+            }
+            return Some((s, d));
+        }
+    }
+    None
+}
+
+// 备份情况; 检查时不询问密码, 加密的备份在修复时才能验证
+fn backup_row(file_name: &str) -> Vec<String> {
+    let snaps = backup::list(file_name);
+    let encrypted = snaps.iter().any(|s| {
+        backup::read(file_name, &s.id)
+            .map(|raw| backup::encrypted(&raw))
+            .unwrap_or(false)
+    });
+    let (status, detail) = match latest_backup(file_name, "") {
+        Some((s, d)) => (
+            "ok",
+            format!(
+                "{} 份, 最新可用 {} ({}), {} 个条目",
+                snaps.len(),
+                s.id,
+                utils::fmt_time(s.time),
+                d.count().1
+            ),
+        ),
+        None if snaps.is_empty() => ("warn", String::from("没有备份")),
+        None if encrypted => (
+            "ok",
+            format!("{} 份, 已用执行密码加密, 修复时验证", snaps.len()),
+        ),
+        None => ("warn", format!("{} 份, 都不可用", snaps.len())),
+    };
+    vec![String::from("备份"), status.to_string(), detail]
+}
+
+// 修复: 数据能读取时修复内容; 否则从最新的备份与残留数据恢复
+fn repair(
+    file_name: &str,
+    s: &Storage,
+    report: Report,
+    params: &params::Params,
+) -> Result<(), result::MyErr> {
+    if report.problems == 0 {
+        println!("数据文件正常, 无需修复");
+        return Ok(());
+    }
+    let _lock = rdata::lock(file_name)?;
+
+    if let Some(mut data) = report.data {
+        check_pwd(&data)?;
+        if !params.yes && !utils::confirm("合并重复的主机与用户, 删除空的条目? [y/N]: ")
+        {
+            println!("已取消");
+            return Ok(());
+        }
+        let n = data.fix();
+        data.save(file_name)?;
+        println!("已修复 {} 个问题", n);
+        return Ok(());
+    }
+
+    // 备份用执行密码加密, 先输入密码再查找可用的备份
+    let mut pass = utils::read_passphrase("请输入密码: ", false)?;
+    let (mut data, from) = match latest_backup(file_name, &pass) {
+        Some((snap, d)) => (
+            d,
+            format!("备份 {} ({})", snap.id, utils::fmt_time(snap.time)),
+        ),
+        None => (rdata::Rdata::new("", 1), String::from("空数据")),
+    };
+    let found = data.salvage(&report.partial);
+    if from == "空数据" && found == 0 {
+        return Err(result::MyErr {
+            msg: String::from("没有可恢复的数据: 没有可用的备份, 残留数据中也没有完整的主机"),
+        });
+    }
+    data.fix();
+    if data.pwd.is_empty() {
+        // 没有备份时残留数据中通常没有密码, 重新设置
+        match partial_pwd(&report.partial) {
+            Some((pwd, version)) => {
+                data.pwd = pwd;
+                data.version = version;
+            }
+            None => {
+                println!("残留数据中没有执行密码, 请设置新的密码");
+                pass = utils::read_passphrase("新密码: ", true)?;
+                data.pwd = pass.to_string();
+            }
+        }
+    }
+    if *pass != *data.pwd {
+        return Err(result::MyErr {
+            msg: String::from("密码错误"),
+        });
+    }
+
+    let (hosts, users) = data.count();
+    println!(
+        "将从{}恢复, 并从残留数据找回 {} 个主机; 恢复后共 {} 个主机、{} 个条目",
+        from, found, hosts, users
+    );
+    if !params.yes && !utils::confirm("恢复后覆盖数据文件, 确认? [y/N]: ") {
+        println!("已取消");
+        return Ok(());
+    }
+    println!("损坏的数据文件已另存为 {}", keep_damaged(file_name)?);
+
+    // 保留加密数据之前的内容; 找不到时可执行文件保留全部, 单独的数据文件重新填充
+    let prefix = match s.start {
+        Some(p) => s.bytes[..p].to_vec(),
+        None if s.bytes.starts_with(b"\x7fELF") => {
+            let mut b = s.bytes.clone();
+            b.extend_from_slice(&[0u8; FILL_SIZE]);
+            b
+        }
+        None => vec![0u8; FILL_SIZE],
+    };
+    data.rebuild(file_name, &prefix)?;
+    println!("已恢复");
+    Ok(())
+}
+
+// 修复前验证执行密码
+fn check_pwd(data: &rdata::Rdata) -> Result<(), result::MyErr> {
+    match utils::read_passphrase("请输入密码: ", false) {
+        Ok(p) if *p == *data.pwd => Ok(()),
+        _ => Err(result::MyErr {
+            msg: String::from("密码错误"),
+        }),
+    }
+}
+
+// 从残留的 json 中取出执行密码与标志
+fn partial_pwd(partial: &str) -> Option<(String, u8)> {
+    let p = partial.find("\"pwd\":")? + "\"pwd\":".len();
+    let rest = &partial[p..];
+    let mut escaped = false;
+    let mut end = None;
+    for (i, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => {
+                end = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let pwd: String = rustc_serialize::json::decode(&rest[..=end?]).ok()?;
+    let v = partial.find("\"version\":")? + "\"version\":".len();
+    let version = partial[v..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>()
+        .parse()
+        .ok()?;
+    Some((pwd, version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = r#"{"current":[],"hosts":[{"ip":"10.0.0.1","users":[{"id":"a1","username":"root","password":"p1"}]},{"ip":"10.0.0.2","users":[{"id":"a2","username":"root","password":"p2"}]},{"ip":"10.0.0.3","users":[{"id":"a3","username":"root","password":"p3"}]}],"pwd":"pw\"1","version":1}"#;
+
+    const PREFIX: &[u8] = b"\x7fELF\0\0\0\0abc\0def";
+
+    // 可执行文件内容 | 填充的 0 | 加密数据 | 文件尾(偏移, 大小)
+    fn file(raw: &str, tail: bool) -> Vec<u8> {
+        let mut bytes = PREFIX.to_vec();
+        bytes.extend_from_slice(&[0u8; FILL_SIZE]);
+        let pos = bytes.len();
+        bytes.extend_from_slice(raw.as_bytes());
+        if tail {
+            bytes.extend_from_slice(&pos.to_ne_bytes());
+            bytes.extend_from_slice(&raw.len().to_ne_bytes());
+        }
+        bytes
+    }
+
+    fn encrypted() -> String {
+        my_crypto::my_crypto(DATA, true).unwrap()
+    }
+
+    #[test]
+    fn locate_by_tail_and_by_fill() {
+        let raw = encrypted();
+        let s = locate(file(&raw, true));
+        assert!(s.tail);
+        assert_eq!(s.start, Some(PREFIX.len() + FILL_SIZE));
+        assert_eq!(s.raw, raw);
+
+        // 文件尾丢失时从填充的 0 之后找
+        let s = locate(file(&raw, false));
+        assert!(!s.tail);
+        assert_eq!(s.start, Some(PREFIX.len() + FILL_SIZE));
+        assert_eq!(s.raw, raw);
+    }
+
+    #[test]
+    fn locate_ignores_text_that_is_not_data() {
+        let s = locate(file("notencryptedatall", false));
+        assert_eq!(s.start, None);
+        assert!(locate(vec![0u8; 8]).start.is_none());
+    }
+
+    #[test]
+    fn diagnose_readable_data() {
+        let r = diagnose("f", &locate(file(&encrypted(), true)));
+        assert_eq!(r.problems, 0);
+        assert_eq!(r.data.unwrap().count(), (3, 3));
+    }
+
+    #[test]
+    fn diagnose_truncated_data_salvages_complete_hosts() {
+        let raw = encrypted();
+        // 截断到第三个主机中间
+        let cut = DATA.find("10.0.0.3").unwrap() * 4 / 3;
+        let s = locate(file(&raw[..cut], false));
+        let r = diagnose("f", &s);
+        assert!(r.data.is_none());
+        assert!(r.problems > 0);
+
+        let mut d = rdata::Rdata::new("", 0);
+        assert_eq!(d.salvage(&r.partial), 2);
+        assert_eq!(d.count(), (2, 2));
+        // 残留数据中没有执行密码
+        assert_eq!(partial_pwd(&r.partial), None);
+    }
+
+    #[test]
+    fn partial_pwd_reads_escaped_password() {
+        assert_eq!(partial_pwd(DATA), Some((String::from("pw\"1"), 1)));
+        assert_eq!(partial_pwd(r#"{"pwd":"abc"#), None);
+        assert_eq!(partial_pwd(r#"{"pwd":"abc","ver"#), None);
+    }
+
+    #[test]
+    fn fix_merges_duplicates_and_drops_empty_entries() {
+        let json = r#"{"current":[{"ip":"","index":1}],"hosts":[{"ip":"10.0.0.1","users":[{"id":"a1","username":"root","password":"old","modified":1}]},{"ip":"10.0.0.1","users":[{"id":"a1","username":"root","password":"new","modified":2},{"username":"","password":"x"}]},{"ip":"","users":[{"username":"root","password":"y"}]}],"pwd":"pw","version":0}"#;
+        let mut d = rdata::Rdata::from_json(json).unwrap();
+        assert!(!d.check().is_empty());
+        assert!(d.fix() > 0);
+        assert!(d.check().is_empty());
+        assert_eq!(d.count(), (1, 1));
+        assert_eq!(
+            d.find("10.0.0.1", "root").map(|p| p.password.to_string()),
+            Some(String::from("new"))
+        );
+    }
+}
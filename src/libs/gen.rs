@@ -0,0 +1,175 @@
+use super::params;
+use super::result;
+
+use rand::Rng;
+use std::fs;
+
+// 生成密码使用的字符, 不含引号、反斜杠、空格等需要转义的字符
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGIT: &str = "0123456789";
+const SYMBOL: &str = "!@#%^*-_=+.,:";
+// 容易看错的字符
+const AMBIGUOUS: &str = "Il1O0o|:;,.";
+
+// 缺省密码长度
+pub const DEFAULT_LENGTH: usize = 20;
+
+// 内置口令短语词表
+const WORDS: &str = "able acid aged also area army away baby back bald bank base bath bear beat \
+    bell belt best bird blow blue boat body bold bone book boot born boss both bowl bulk burn \
+    bush busy cafe cage cake calm camp card care cart case cash cast cell chef chip city clay \
+    clip club coal coat code coin cold cook cool copy core corn cost crew crop cube dark data \
+    dawn deal deck deep deer desk dial dice diet disk dock door dose down drum duck dust duty \
+    east easy edge epic even exit face fact fair farm fast fern file film fire fish flag flat \
+    foam fold folk food foot fork form fort free frog fuel full fund gain game gate gear gift \
+    glad glow goal gold golf good gown grid grip gulf half hall hand hard harp hawk heat herb \
+    hero high hill hint hive hold hole home hook hope horn host hour huge idea inch iron item \
+    jazz join joke jump jury keen kept kick kind king kite knee knot lace lake lamp land lane \
+    last lawn lazy leaf lean left lens life lift lime line link lion list loaf lock loft logo \
+    long loop lord lucky mail main mall malt many mark mask mast meal meat melt menu mesh mild \
+    milk mill mind mint mist mode mole moon moss most moth move much mule nail name navy near \
+    neat neck nest news next nice nine node noon nose note oath oven over pace pack page palm \
+    park part path peak pear pine pink pipe plan play plot plum poem pole pond pony pool port \
+    post pump pure quiz race rack rail rain ramp rare rate read reed reef rent rest rice rich \
+    ride ring riot rise road rock roof room root rope rose ruby rule safe sage sail salt sand \
+    save seal seed ship shoe shop silk sing site size skip slow snow soap sock sofa soil song \
+    soup spin spot star stem step stir suit swan tail tank tape task team tent term test text \
+    tide tile time tiny tone tool town tree trip tube tune twin unit vase vast verb vest view \
+    vine vote wage wall warm wave weed well west wide wild wind wing wire wise wolf wood wool \
+    word work yard yarn year zero zone";
+
+// 密码策略, name 为标签名, default 为全局缺省
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+pub struct Policy {
+    pub name: String,
+    pub length: usize,        // 字符密码长度
+    pub classes: String,      // 字符类别: lower,upper,digit,symbol
+    pub no_ambiguous: bool,   // 排除容易看错的字符
+    pub words: usize,         // 大于 0 时生成由单词组成的口令短语
+    pub separator: String,    // 口令短语分隔符
+    pub wordlist: String,     // 词表文件, 为空使用内置词表
+    pub max_age: Option<u64>, // 密码最长使用天数
+}
+
+impl Policy {
+    /* 内置缺省策略 */
+    pub fn builtin() -> Policy {
+        Policy {
+            name: String::from("default"),
+            length: DEFAULT_LENGTH,
+            classes: String::from("lower,upper,digit,symbol"),
+            no_ambiguous: false,
+            words: 0,
+            separator: String::from("-"),
+            wordlist: String::new(),
+            max_age: None,
+        }
+    }
+
+    /* 用命令行选项覆盖策略 */
+    pub fn apply(&mut self, params: &params::Params) {
+        if params.length > 0 {
+            self.length = params.length;
+            self.words = 0;
+        }
+        if !params.classes.is_empty() {
+            self.classes.clone_from(&params.classes);
+            self.words = 0;
+        }
+        if params.no_ambiguous {
+            self.no_ambiguous = true;
+        }
+        if params.words > 0 {
+            self.words = params.words;
+        }
+        if !params.separator.is_empty() {
+            self.separator.clone_from(&params.separator);
+        }
+        if !params.wordlist.is_empty() {
+            self.wordlist.clone_from(&params.wordlist);
+        }
+        if params.max_age != 0 {
+            self.max_age = Some(params.max_age);
+        }
+    }
+
+    /* 生成一个密码 */
+    pub fn generate(&self) -> Result<String, result::MyErr> {
+        if self.words > 0 {
+            self.passphrase()
+        } else {
+            self.characters()
+        }
+    }
+
+    // 每类字符至少出现一次
+    fn characters(&self) -> Result<String, result::MyErr> {
+        let mut classes: Vec<Vec<char>> = Vec::new();
+        for c in self.classes.split(',').filter(|c| !c.is_empty()) {
+            let set = match c.trim() {
+                "lower" => LOWER,
+                "upper" => UPPER,
+                "digit" => DIGIT,
+                "symbol" => SYMBOL,
+                other => {
+                    return Err(result::MyErr {
+                        msg: format!("未知字符类别: {}, 可选: lower,upper,digit,symbol", other),
+                    })
+                }
+            };
+            classes.push(
+                set.chars()
+                    .filter(|ch| !self.no_ambiguous || !AMBIGUOUS.contains(*ch))
+                    .collect(),
+            );
+        }
+        if classes.is_empty() {
+            return Err(result::MyErr {
+                msg: String::from("至少需要一种字符类别"),
+            });
+        }
+        let all: Vec<char> = classes.concat();
+        let mut rng = rand::thread_rng();
+
+        let length = self.length.max(classes.len());
+        let mut pwd: Vec<char> = classes
+            .iter()
+            .map(|cs| cs[rng.gen_range(0, cs.len())])
+            .collect();
+        while pwd.len() < length {
+            pwd.push(all[rng.gen_range(0, all.len())]);
+        }
+        // 打乱顺序, 避免前几位的字符类别固定
+        for i in (1..pwd.len()).rev() {
+            let j = rng.gen_range(0, i + 1);
+            pwd.swap(i, j);
+        }
+        Ok(pwd.into_iter().collect())
+    }
+
+    fn passphrase(&self) -> Result<String, result::MyErr> {
+        let content = match self.wordlist.as_str() {
+            "" => String::from(WORDS),
+            f => match fs::read_to_string(f) {
+                Ok(c) => c,
+                Err(_) => {
+                    return Err(result::MyErr {
+                        msg: format!("读取词表 {} 失败", f),
+                    })
+                }
+            },
+        };
+        let words: Vec<&str> = content.split_whitespace().collect();
+        if words.len() < 2 {
+            return Err(result::MyErr {
+                msg: String::from("词表至少需要两个单词"),
+            });
+        }
+        let mut rng = rand::thread_rng();
+        let picked: Vec<&str> = (0..self.words)
+            .map(|_| words[rng.gen_range(0, words.len())])
+            .collect();
+        Ok(picked.join(&self.separator))
+    }
+}
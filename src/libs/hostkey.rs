@@ -0,0 +1,169 @@
+use super::result;
+use super::ssh;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 同一进程内临时文件的序号
+static SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/* 获取主机公钥, 每个为 "类型 base64"; 使用 opts 的端口, 经过跳板机时使用其 ProxyCommand 与 ssh 程序 */
+pub fn scan(ip: &str, opts: &ssh::Options) -> Result<Vec<String>, result::MyErr> {
+    if !opts.proxy.is_empty() {
+        return scan_proxy(ip, opts);
+    }
+    let port = if opts.port == 0 { 22 } else { opts.port };
+    let out = Command::new("ssh-keyscan")
+        .args(["-T", "5", "-p", &port.to_string(), ip])
+        .output();
+    let out = match out {
+        Ok(o) => o,
+        Err(_) => {
+            return Err(result::MyErr {
+                msg: String::from("执行 ssh-keyscan 失败"),
+            })
+        }
+    };
+
+    parse_keys(ip, &String::from_utf8_lossy(&out.stdout))
+}
+
+// ssh-keyscan 不支持 ProxyCommand, 用 ssh 连接一次, 由 accept-new 把主机公钥写入临时文件
+// 不做任何认证, 连接在认证阶段失败
+fn scan_proxy(ip: &str, opts: &ssh::Options) -> Result<Vec<String>, result::MyErr> {
+    let file = KnownHosts::new(ip, opts.port, &[])?;
+    let mut args: Vec<String> = Vec::new();
+    for o in &[
+        String::from("StrictHostKeyChecking=accept-new"),
+        format!("UserKnownHostsFile={}", &file.path),
+        String::from("GlobalKnownHostsFile=/dev/null"),
+        String::from("BatchMode=yes"),
+        String::from("PubkeyAuthentication=no"),
+        String::from("PasswordAuthentication=no"),
+        String::from("KbdInteractiveAuthentication=no"),
+        opts.connect_timeout(5),
+        format!("ProxyCommand={}", &opts.proxy),
+    ] {
+        args.push(String::from("-o"));
+        args.push(o.clone());
+    }
+    if opts.port != 0 {
+        args.push(String::from("-p"));
+        args.push(opts.port.to_string());
+    }
+    args.push(format!("rssh-hostkey@{}", ip));
+    args.push(String::from("exit"));
+    let _ = Command::new(opts.program())
+        .args(&args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+
+    let content = fs::read_to_string(&file.path).unwrap_or_default();
+    parse_keys(ip, &content)
+}
+
+// 解析 known_hosts 格式的 "主机 类型 base64"
+fn parse_keys(ip: &str, content: &str) -> Result<Vec<String>, result::MyErr> {
+    let mut keys: Vec<String> = Vec::new();
+    for line in content.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() >= 3 {
+            let key = format!("{} {}", fields[1], fields[2]);
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    if keys.is_empty() {
+        return Err(result::MyErr {
+            msg: format!("获取 {} 的主机 Key 失败", ip),
+        });
+    }
+    keys.sort();
+    Ok(keys)
+}
+
+/* 公钥指纹, 与 ssh-keygen -l 相同的 SHA256:base64 格式 */
+pub fn fingerprint(key: &str) -> String {
+    let blob = key
+        .split_whitespace()
+        .nth(1)
+        .and_then(|b| base64::decode(b).ok())
+        .unwrap_or_default();
+    let mut hasher = Sha256::new();
+    hasher.input(&blob);
+    let mut digest = [0u8; 32];
+    hasher.result(&mut digest);
+    format!(
+        "SHA256:{}",
+        base64::encode_config(digest, base64::STANDARD_NO_PAD)
+    )
+}
+
+/* 公钥类型 */
+pub fn key_type(key: &str) -> &str {
+    key.split_whitespace().next().unwrap_or("")
+}
+
+/* 主机当前的公钥与保存的都不相同时返回 true, 连接失败时无法判断返回 false */
+pub fn changed(ip: &str, opts: &ssh::Options, pinned: &[String]) -> bool {
+    match scan(ip, opts) {
+        Ok(keys) => !keys.iter().any(|k| pinned.contains(k)),
+        Err(_) => false,
+    }
+}
+
+// 临时 known_hosts 文件, 离开作用域时删除
+pub struct KnownHosts {
+    pub path: String,
+}
+
+impl KnownHosts {
+    /* 写入保存的主机公钥, 供 ssh 的 UserKnownHostsFile 使用 */
+    pub fn new(ip: &str, port: u16, keys: &[String]) -> Result<KnownHosts, result::MyErr> {
+        let host = match port {
+            0 | 22 => ip.to_string(),
+            p => format!("[{}]:{}", ip, p),
+        };
+        let mut content = String::new();
+        for k in keys {
+            content.push_str(&format!("{} {}\n", host, k));
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "rssh-known-{}-{}",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::SeqCst)
+        ));
+        let path = path.to_string_lossy().into_owned();
+        let written = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut f| f.write_all(content.as_bytes()));
+        if written.is_err() {
+            return Err(result::MyErr {
+                msg: format!("写入临时文件 {} 失败", &path),
+            });
+        }
+        Ok(KnownHosts { path })
+    }
+}
+
+impl Drop for KnownHosts {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
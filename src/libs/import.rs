@@ -0,0 +1,865 @@
+use super::my_crypto;
+use super::result;
+use super::secret;
+use super::secret::Secret;
+use super::utils;
+
+use rustc_serialize::json::{self, Json};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::prelude::*;
+
+// 支持的导入格式
+pub const KINDS: [&str; 6] = ["plain", "csv", "json", "ssh", "ini", "yaml"];
+
+// 导入的一条记录, password 为 None 时不修改已有密码
+#[derive(Debug)]
+pub struct Entry {
+    pub line: usize,
+    pub ip: String,
+    pub user: String,
+    pub password: Option<String>,
+    pub port: u16,
+    pub alias: Vec<String>,
+    pub tags: Vec<String>,
+    pub updated: Option<u64>, // 来源中记录的密码修改时间
+}
+
+// 释放时清零密码
+impl Drop for Entry {
+    fn drop(&mut self) {
+        if let Some(p) = self.password.as_mut() {
+            secret::wipe_string(p);
+        }
+    }
+}
+
+impl Entry {
+    fn new(line: usize) -> Entry {
+        Entry {
+            line,
+            ip: String::new(),
+            user: String::new(),
+            password: None,
+            port: 0,
+            alias: vec![],
+            tags: vec![],
+            updated: None,
+        }
+    }
+}
+
+// (行号, 错误信息)
+pub type LineErr = (usize, String);
+
+/* 解析导入文件, kind 为空时按文件名推断格式 */
+pub fn parse(
+    file_name: &str,
+    kind: &str,
+    map: &[String],
+) -> Result<(Vec<Entry>, Vec<LineErr>), result::MyErr> {
+    let mut content = String::new();
+    match File::open(file_name) {
+        Ok(mut f) => {
+            if f.read_to_string(&mut content).is_err() {
+                return Err(result::MyErr {
+                    msg: format!("读取文件: {} 失败", file_name),
+                });
+            }
+        }
+        Err(_) => {
+            return Err(result::MyErr {
+                msg: format!("打开文件: {} 失败", file_name),
+            })
+        }
+    }
+
+    // 文件中可能有明文密码, 解析后清零
+    let content = Secret::new(content);
+
+    // 加密导出的文件, 解密后按 json 导入
+    if content.starts_with(my_crypto::BUNDLE_HEADER) {
+        let pass = utils::read_passphrase("请输入导出口令: ", false)?;
+        return parse_json(&Secret::new(my_crypto::decrypt_bundle(&content, &pass)?));
+    }
+
+    let kind = match kind {
+        "" => detect(file_name, &content),
+        k => k,
+    };
+    match kind {
+        "plain" => Ok(parse_plain(&content)),
+        "csv" => parse_csv(&content, map),
+        "json" => parse_json(&content),
+        "ssh" => Ok(parse_ssh_config(&content)),
+        "ini" => Ok(parse_ansible_ini(&content)),
+        "yaml" => Ok(parse_ansible_yaml(&content)),
+        _ => Err(result::MyErr {
+            msg: format!("不支持的导入格式: {}, 可选: {}", kind, KINDS.join("|")),
+        }),
+    }
+}
+
+// 按扩展名与内容推断格式
+fn detect<'a>(file_name: &str, content: &str) -> &'a str {
+    let lower = file_name.to_lowercase();
+    if lower.ends_with(".csv") {
+        "csv"
+    } else if lower.ends_with(".json") || looks_like_json(content) {
+        "json"
+    } else if lower.ends_with(".yml") || lower.ends_with(".yaml") {
+        "yaml"
+    } else if lower.ends_with(".ini") {
+        "ini"
+    } else if lower.ends_with("ssh/config") || lower.ends_with("ssh_config") {
+        "ssh"
+    } else {
+        "plain"
+    }
+}
+
+// 以 [ 开头且第一个元素是对象, 区别于 ini 的 [group]
+fn looks_like_json(content: &str) -> bool {
+    let t = content.trim_start();
+    t.starts_with('[') && t[1..].trim_start().starts_with(['{', ']'])
+}
+
+// 按行拆分, 去掉 Windows 换行留下的 \r
+fn lines(content: &str) -> impl Iterator<Item = &str> {
+    content.split('\n').map(|l| l.trim_end_matches('\r'))
+}
+
+// ip user pwd, 密码中可以包含空格
+fn parse_plain(content: &str) -> (Vec<Entry>, Vec<LineErr>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (n, line) in lines(content).enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let ip_user: Vec<&str> = line.splitn(3, ' ').collect();
+        if ip_user.len() < 3 || ip_user[0].is_empty() || ip_user[1].is_empty() {
+            errors.push((n + 1, String::from("格式错误, 应为: ip user pwd")));
+            continue;
+        }
+        let mut e = Entry::new(n + 1);
+        e.ip = ip_user[0].to_string();
+        e.user = ip_user[1].to_string();
+        e.password = Some(ip_user[2].to_string());
+        entries.push(e);
+    }
+    (entries, errors)
+}
+
+// 表头别名 -> 字段
+fn csv_field(name: &str) -> &str {
+    match name.trim().to_lowercase().as_str() {
+        "ip" | "host" | "hostname" | "address" | "addr" | "ansible_host" => "ip",
+        "user" | "username" | "login" | "ansible_user" => "user",
+        "password" | "pwd" | "pass" | "passwd" | "ansible_password" => "password",
+        "port" | "ansible_port" => "port",
+        "alias" | "name" => "alias",
+        "tags" | "tag" | "group" | "groups" => "tags",
+        "updated" | "modified" | "changed" => "updated",
+        _ => "",
+    }
+}
+
+// 首行为表头, map 为 列名=字段 的显式映射
+fn parse_csv(content: &str, map: &[String]) -> Result<(Vec<Entry>, Vec<LineErr>), result::MyErr> {
+    let mut rows = lines(content).enumerate();
+    let header = match rows.next() {
+        Some((_, h)) if !h.trim().is_empty() => split_csv(h),
+        _ => return Ok((vec![], vec![])),
+    };
+
+    let mut fields: Vec<String> = header.iter().map(|h| csv_field(h).to_string()).collect();
+    for m in map {
+        let kv: Vec<&str> = m.splitn(2, '=').collect();
+        if kv.len() != 2 {
+            return Err(result::MyErr {
+                msg: format!("映射格式错误: {}, 应为 列名=字段", m),
+            });
+        }
+        match header.iter().position(|h| h.trim() == kv[0]) {
+            Some(i) => fields[i] = kv[1].to_string(),
+            None => {
+                return Err(result::MyErr {
+                    msg: format!("表头中不存在列: {}", kv[0]),
+                })
+            }
+        }
+    }
+    for need in &["ip", "user"] {
+        if !fields.iter().any(|f| f == need) {
+            return Err(result::MyErr {
+                msg: format!("表头缺少 {} 列, 可用 --map 列名={} 指定", need, need),
+            });
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (n, line) in rows {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let cols = split_csv(line);
+        if cols.len() != header.len() {
+            errors.push((
+                n + 1,
+                format!("列数 {} 与表头 {} 不一致", cols.len(), header.len()),
+            ));
+            continue;
+        }
+        let mut e = Entry::new(n + 1);
+        let mut bad = None;
+        for (f, v) in fields.iter().zip(cols) {
+            match f.as_str() {
+                "ip" => e.ip = v,
+                "user" => e.user = v,
+                "password" => e.password = Some(v),
+                "port" if !v.is_empty() => match v.parse() {
+                    Ok(p) => e.port = p,
+                    Err(_) => bad = Some(format!("端口错误: {}", v)),
+                },
+                "alias" => e.alias = split_list(&v),
+                "tags" => e.tags = split_list(&v),
+                "updated" if !v.is_empty() => match parse_time(&v) {
+                    Some(t) => e.updated = Some(t),
+                    None => bad = Some(format!("时间错误: {}", v)),
+                },
+                _ => {}
+            }
+        }
+        if bad.is_none() && (e.ip.is_empty() || e.user.is_empty()) {
+            bad = Some(String::from("ip 或 user 为空"));
+        }
+        match bad {
+            Some(msg) => errors.push((n + 1, msg)),
+            None => entries.push(e),
+        }
+    }
+    Ok((entries, errors))
+}
+
+// 拆分一行 csv, 支持双引号与 "" 转义
+fn split_csv(line: &str) -> Vec<String> {
+    let mut cols = Vec::new();
+    let mut cur = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    cur.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            } else {
+                cur.push(c);
+            }
+        } else if c == '"' {
+            quoted = true;
+        } else if c == ',' {
+            cols.push(cur);
+            cur = String::new();
+        } else {
+            cur.push(c);
+        }
+    }
+    cols.push(cur);
+    cols
+}
+
+/* json 对象数组, 字段与 --format json 导出的一致, 行号为数组下标 + 1
+ * port、updated 可以是数字或字符串 */
+pub fn parse_json(content: &str) -> Result<(Vec<Entry>, Vec<LineErr>), result::MyErr> {
+    let list = match Json::from_str(content) {
+        Ok(Json::Array(l)) => l,
+        Ok(_) => {
+            return Err(result::MyErr {
+                msg: String::from("json格式错误: 应为对象数组"),
+            })
+        }
+        Err(e) => {
+            return Err(result::MyErr {
+                msg: format!("json解码出错: {}", e),
+            })
+        }
+    };
+
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (n, item) in list.into_iter().enumerate() {
+        let mut obj = match item {
+            Json::Object(o) => o,
+            _ => {
+                errors.push((n + 1, String::from("不是对象")));
+                continue;
+            }
+        };
+        let mut e = Entry::new(n + 1);
+        e.ip = json_field(&mut obj, "ip").unwrap_or_default();
+        e.user = json_field(&mut obj, "user").unwrap_or_default();
+        e.password = json_field(&mut obj, "password");
+        e.alias = split_list(&json_field(&mut obj, "alias").unwrap_or_default());
+        e.tags = split_list(&json_field(&mut obj, "tags").unwrap_or_default());
+        if e.ip.is_empty() || e.user.is_empty() {
+            errors.push((n + 1, String::from("ip 或 user 为空")));
+            continue;
+        }
+        match json_field(&mut obj, "updated") {
+            Some(t) if !t.is_empty() => match parse_time(&t) {
+                Some(t) => e.updated = Some(t),
+                None => {
+                    errors.push((n + 1, format!("时间错误: {}", t)));
+                    continue;
+                }
+            },
+            _ => {}
+        }
+        match json_field(&mut obj, "port") {
+            Some(p) if !p.is_empty() => match p.parse() {
+                Ok(p) => e.port = p,
+                Err(_) => {
+                    errors.push((n + 1, format!("端口错误: {}", p)));
+                    continue;
+                }
+            },
+            _ => {}
+        }
+        entries.push(e);
+    }
+    Ok((entries, errors))
+}
+
+// 取出对象的字段, 字符串原样返回(不复制, 密码由 Entry 清零), 数字转为字符串, null 视为没有
+fn json_field(obj: &mut json::Object, name: &str) -> Option<String> {
+    match obj.remove(name) {
+        Some(Json::String(s)) => Some(s),
+        Some(Json::Null) | None => None,
+        Some(v) => Some(v.to_string()),
+    }
+}
+
+// ~/.ssh/config 的 Host 块, 只导入主机、端口、用户, 不含密码
+fn parse_ssh_config(content: &str) -> (Vec<Entry>, Vec<LineErr>) {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut errors = Vec::new();
+    let mut cur: Option<Entry> = None;
+
+    for (n, line) in lines(content).enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.find([' ', '\t', '=']) {
+            Some(i) => (
+                line[..i].to_lowercase(),
+                line[i + 1..].trim_start_matches([' ', '\t', '=']),
+            ),
+            None => {
+                errors.push((n + 1, format!("无法解析: {}", line)));
+                continue;
+            }
+        };
+        let value = value.trim().trim_matches('"');
+
+        match key.as_str() {
+            "host" => {
+                if let Some(e) = cur.take() {
+                    entries.push(e);
+                }
+                let names: Vec<String> = value
+                    .split_whitespace()
+                    .filter(|h| !h.contains('*') && !h.contains('?') && !h.starts_with('!'))
+                    .map(|h| h.to_string())
+                    .collect();
+                if names.is_empty() {
+                    // 通配块不是具体主机
+                    continue;
+                }
+                let mut e = Entry::new(n + 1);
+                e.ip.clone_from(&names[0]);
+                e.alias = names;
+                cur = Some(e);
+            }
+            "match" => {
+                if let Some(e) = cur.take() {
+                    entries.push(e);
+                }
+            }
+            "hostname" => {
+                if let Some(e) = cur.as_mut() {
+                    e.ip = value.to_string();
+                }
+            }
+            "user" => {
+                if let Some(e) = cur.as_mut() {
+                    e.user = value.to_string();
+                }
+            }
+            "port" => {
+                if let Some(e) = cur.as_mut() {
+                    match value.parse() {
+                        Ok(p) => e.port = p,
+                        Err(_) => errors.push((n + 1, format!("端口错误: {}", value))),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(e) = cur.take() {
+        entries.push(e);
+    }
+
+    for e in entries.iter_mut() {
+        // 别名与 ip 相同时不记录
+        let ip = e.ip.clone();
+        e.alias.retain(|a| a != &ip);
+        if e.user.is_empty() {
+            e.user = String::from("root");
+        }
+    }
+    (entries, errors)
+}
+
+// ansible 主机定义
+struct AnsibleHost {
+    line: usize,
+    vars: BTreeMap<String, String>,
+    groups: Vec<String>,
+}
+
+// ansible 清单解析结果: 主机、组变量、组 -> 父组
+#[derive(Default)]
+struct Inventory {
+    hosts: BTreeMap<String, AnsibleHost>,
+    group_vars: BTreeMap<String, BTreeMap<String, String>>,
+    parents: BTreeMap<String, Vec<String>>,
+}
+
+impl Inventory {
+    fn add_host(&mut self, name: &str, line: usize, group: &str) -> &mut AnsibleHost {
+        let h = self.hosts.entry(name.to_string()).or_insert(AnsibleHost {
+            line,
+            vars: BTreeMap::new(),
+            groups: vec![],
+        });
+        if !group.is_empty() && !h.groups.iter().any(|g| g == group) {
+            h.groups.push(group.to_string());
+        }
+        h
+    }
+
+    fn add_parent(&mut self, child: &str, parent: &str) {
+        let p = self.parents.entry(child.to_string()).or_default();
+        if !p.iter().any(|x| x == parent) {
+            p.push(parent.to_string());
+        }
+    }
+
+    // 组及其所有父组
+    fn ancestors(&self, group: &str, out: &mut Vec<String>) {
+        if out.iter().any(|g| g == group) {
+            return;
+        }
+        out.push(group.to_string());
+        if let Some(ps) = self.parents.get(group) {
+            for p in ps {
+                self.ancestors(p, out);
+            }
+        }
+    }
+
+    // 变量优先级: 主机 > 组(子组优先) > all
+    fn into_entries(self, errors: &mut Vec<LineErr>) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        for (name, h) in &self.hosts {
+            let mut groups = Vec::new();
+            for g in &h.groups {
+                self.ancestors(g, &mut groups);
+            }
+            groups.push(String::from("all"));
+
+            let var = |keys: &[&str]| -> Option<String> {
+                for k in keys {
+                    if let Some(v) = h.vars.get(*k) {
+                        return Some(v.clone());
+                    }
+                }
+                for g in &groups {
+                    if let Some(vars) = self.group_vars.get(g) {
+                        for k in keys {
+                            if let Some(v) = vars.get(*k) {
+                                return Some(v.clone());
+                            }
+                        }
+                    }
+                }
+                None
+            };
+
+            let mut e = Entry::new(h.line);
+            e.ip = var(&["ansible_host", "ansible_ssh_host"]).unwrap_or_else(|| name.clone());
+            e.user =
+                var(&["ansible_user", "ansible_ssh_user"]).unwrap_or_else(|| String::from("root"));
+            e.password = var(&["ansible_password", "ansible_ssh_pass"]);
+            if &e.ip != name {
+                e.alias = vec![name.clone()];
+            }
+            if let Some(p) = var(&["ansible_port", "ansible_ssh_port"]) {
+                match p.parse() {
+                    Ok(p) => e.port = p,
+                    Err(_) => {
+                        errors.push((h.line, format!("端口错误: {}", p)));
+                        continue;
+                    }
+                }
+            }
+            e.tags = groups
+                .into_iter()
+                .filter(|g| g != "all" && g != "ungrouped")
+                .collect();
+            entries.push(e);
+        }
+        entries.sort_by_key(|e| e.line);
+        entries
+    }
+}
+
+// ansible INI 清单: [group] [group:vars] [group:children]
+fn parse_ansible_ini(content: &str) -> (Vec<Entry>, Vec<LineErr>) {
+    let mut inv = Inventory::default();
+    let mut errors = Vec::new();
+    let mut section = String::from("ungrouped");
+
+    for (n, line) in lines(content).enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') {
+            if !line.ends_with(']') {
+                errors.push((n + 1, format!("无法解析: {}", line)));
+                continue;
+            }
+            section = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+
+        if section.ends_with(":vars") {
+            // 每行一个变量, 等号两侧可以有空白, 值可以包含空白
+            let group = &section[..section.len() - ":vars".len()];
+            match line.find('=') {
+                Some(i) => {
+                    inv.group_vars
+                        .entry(group.to_string())
+                        .or_insert_with(BTreeMap::new)
+                        .insert(line[..i].trim().to_string(), unquote(&line[i + 1..]));
+                }
+                None => errors.push((n + 1, format!("变量格式错误: {}", line))),
+            }
+            continue;
+        }
+
+        let kvs = split_ini(line);
+        if section.ends_with(":children") {
+            let group = &section[..section.len() - ":children".len()];
+            inv.add_parent(&kvs[0], group);
+        } else {
+            let h = inv.add_host(&kvs[0], n + 1, &section);
+            for kv in &kvs[1..] {
+                match kv.find('=') {
+                    Some(i) => {
+                        h.vars.insert(kv[..i].to_string(), unquote(&kv[i + 1..]));
+                    }
+                    None => errors.push((n + 1, format!("变量格式错误: {}", kv))),
+                }
+            }
+        }
+    }
+    let entries = inv.into_entries(&mut errors);
+    (entries, errors)
+}
+
+// 按空白拆分, 保留引号内的空白
+fn split_ini(line: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut cur = String::new();
+    let mut quote: Option<char> = None;
+    for c in line.chars() {
+        match quote {
+            Some(q) => {
+                cur.push(c);
+                if c == q {
+                    quote = None;
+                }
+            }
+            None if c == '"' || c == '\'' => {
+                cur.push(c);
+                quote = Some(c);
+            }
+            None if c.is_whitespace() => {
+                if !cur.is_empty() {
+                    out.push(cur);
+                    cur = String::new();
+                }
+            }
+            None => cur.push(c),
+        }
+    }
+    if !cur.is_empty() {
+        out.push(cur);
+    }
+    out
+}
+
+// ansible YAML 清单, 只支持 hosts/vars/children 组成的映射结构
+fn parse_ansible_yaml(content: &str) -> (Vec<Entry>, Vec<LineErr>) {
+    let mut inv = Inventory::default();
+    let mut errors = Vec::new();
+    // (缩进, 键)
+    let mut path: Vec<(usize, String)> = Vec::new();
+
+    for (n, raw) in lines(content).enumerate() {
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed == "---" || trimmed == "..." {
+            continue;
+        }
+        let indent = raw.len() - raw.trim_start().len();
+        let (key, value) = match trimmed.find(':') {
+            Some(i) => (unquote(&trimmed[..i]), trimmed[i + 1..].trim()),
+            None => {
+                errors.push((n + 1, format!("无法解析: {}", trimmed)));
+                continue;
+            }
+        };
+        while let Some((i, _)) = path.last() {
+            if *i >= indent {
+                path.pop();
+            } else {
+                break;
+            }
+        }
+        let keys: Vec<&str> = path.iter().map(|(_, k)| k.as_str()).collect();
+        let depth = keys.len();
+
+        if depth >= 1 && keys[depth - 1] == "hosts" {
+            // 主机定义, 所属组为 hosts 的上一级
+            let group = if depth >= 2 {
+                keys[depth - 2]
+            } else {
+                "ungrouped"
+            };
+            inv.add_host(&key, n + 1, group);
+        } else if depth >= 2 && keys[depth - 2] == "hosts" {
+            // 主机变量
+            let v = unquote(value);
+            inv.add_host(keys[depth - 1], n + 1, "")
+                .vars
+                .insert(key.clone(), v);
+        } else if depth >= 2 && keys[depth - 1] == "vars" {
+            inv.group_vars
+                .entry(keys[depth - 2].to_string())
+                .or_insert_with(BTreeMap::new)
+                .insert(key.clone(), unquote(value));
+        } else if depth >= 2 && keys[depth - 1] == "children" {
+            inv.add_parent(&key, keys[depth - 2]);
+        }
+
+        if value.is_empty() {
+            path.push((indent, key));
+        }
+    }
+    let entries = inv.into_entries(&mut errors);
+    (entries, errors)
+}
+
+// unix 秒或本地时间 %Y-%m-%d [%H:%M[:%S]]
+fn parse_time(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if let Ok(t) = s.parse::<u64>() {
+        return Some(t);
+    }
+    for fmt in &["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%d"] {
+        if let Ok(mut tm) = time::strptime(s, fmt) {
+            tm.tm_isdst = -1;
+            tm.tm_utcoff = time::now().tm_utcoff;
+            return Some(tm.to_timespec().sec as u64);
+        }
+    }
+    None
+}
+
+fn unquote(s: &str) -> String {
+    let s = s.trim();
+    if s.len() >= 2
+        && ((s.starts_with('"') && s.ends_with('"')) || (s.starts_with('\'') && s.ends_with('\'')))
+    {
+        s[1..s.len() - 1].to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+// 逗号分隔的列表
+fn split_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_keeps_spaces_and_strips_cr() {
+        let (entries, errors) =
+            parse_plain("10.0.0.1 root p w\r\n# 注释\r\n\r\n10.0.0.2 admin x\r\nbad\r\n");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].password.as_deref(), Some("p w"));
+        assert_eq!(entries[1].ip, "10.0.0.2");
+        assert_eq!(entries[1].password.as_deref(), Some("x"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 5);
+    }
+
+    #[test]
+    fn csv_header_alias_quotes_and_map() {
+        let content = "Host,Login,Secret,Port,Group\r\n\
+                       10.0.0.1,root,\"a,\"\"b\",2222,\"web,prod\"\r\n\
+                       10.0.0.2,root,x,bad,\r\n\
+                       10.0.0.3,root\r\n";
+        let (entries, errors) = parse_csv(content, &[String::from("Secret=password")]).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].password.as_deref(), Some("a,\"b"));
+        assert_eq!(entries[0].port, 2222);
+        assert_eq!(entries[0].tags, vec!["web", "prod"]);
+        let lines: Vec<usize> = errors.iter().map(|e| e.0).collect();
+        assert_eq!(lines, vec![3, 4]);
+    }
+
+    #[test]
+    fn csv_requires_ip_and_user() {
+        assert!(parse_csv("name,password\nweb,x\n", &[]).is_err());
+        assert!(parse_csv("ip,user\n", &[String::from("nope=ip")]).is_err());
+        assert!(parse_csv("", &[]).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn json_port_number_or_string() {
+        let content = r#"[
+            {"ip": "10.0.0.1", "user": "root", "password": "x", "port": 22},
+            {"ip": "10.0.0.2", "user": "root", "port": "2222", "updated": 1700000000},
+            {"ip": "10.0.0.3", "user": "root", "port": null, "tags": "a, b"},
+            {"ip": "10.0.0.4", "user": "root", "port": "ssh"},
+            {"ip": "", "user": "root"},
+            "10.0.0.5"
+        ]"#;
+        let (entries, errors) = parse_json(content).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].port, 22);
+        assert_eq!(entries[0].password.as_deref(), Some("x"));
+        assert_eq!(entries[1].port, 2222);
+        assert_eq!(entries[1].password, None);
+        assert_eq!(entries[1].updated, Some(1700000000));
+        assert_eq!(entries[2].port, 0);
+        assert_eq!(entries[2].tags, vec!["a", "b"]);
+        let lines: Vec<usize> = errors.iter().map(|e| e.0).collect();
+        assert_eq!(lines, vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn json_must_be_array() {
+        assert!(parse_json("{\"ip\": \"10.0.0.1\"}").is_err());
+        assert!(parse_json("[").is_err());
+    }
+
+    #[test]
+    fn ssh_config_hosts() {
+        let content = "Host *\n  User nobody\n\
+                       Host web1 web\n  HostName 10.0.0.1\n  Port=2222\n  User deploy\n\
+                       Host db\n  Port x\n";
+        let (entries, errors) = parse_ssh_config(content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].ip, "10.0.0.1");
+        assert_eq!(entries[0].user, "deploy");
+        assert_eq!(entries[0].port, 2222);
+        assert_eq!(entries[0].alias, vec!["web1", "web"]);
+        assert_eq!(entries[1].ip, "db");
+        assert_eq!(entries[1].user, "root");
+        assert!(entries[1].alias.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 8);
+    }
+
+    #[test]
+    fn ansible_ini_vars_and_children() {
+        let content = "[web]\r\n\
+                       web1 ansible_host=10.0.0.1 ansible_port=2222\r\n\
+                       [db]\r\n\
+                       10.0.0.2 ansible_password='p w'\r\n\
+                       [prod:children]\r\n\
+                       web\r\n\
+                       [prod:vars]\r\n\
+                       ansible_user = deploy\r\n\
+                       ansible_password = \"s e\"\r\n\
+                       [all:vars]\r\n\
+                       ansible_user=admin\r\n\
+                       oops\r\n";
+        let (entries, errors) = parse_ansible_ini(content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].ip, "10.0.0.1");
+        assert_eq!(entries[0].alias, vec!["web1"]);
+        assert_eq!(entries[0].port, 2222);
+        assert_eq!(entries[0].user, "deploy");
+        assert_eq!(entries[0].password.as_deref(), Some("s e"));
+        assert_eq!(entries[0].tags, vec!["web", "prod"]);
+        assert_eq!(entries[1].ip, "10.0.0.2");
+        assert_eq!(entries[1].user, "admin");
+        assert_eq!(entries[1].password.as_deref(), Some("p w"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 12);
+    }
+
+    #[test]
+    fn ansible_yaml_inventory() {
+        let content = "all:\n\
+                       \x20 vars:\n\
+                       \x20   ansible_user: admin\n\
+                       \x20 children:\n\
+                       \x20   web:\n\
+                       \x20     hosts:\n\
+                       \x20       web1:\n\
+                       \x20         ansible_host: 10.0.0.1\n\
+                       \x20         ansible_port: \"2222\"\n\
+                       \x20       10.0.0.2:\n";
+        let (entries, errors) = parse_ansible_yaml(content);
+        assert!(errors.is_empty());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].ip, "10.0.0.1");
+        assert_eq!(entries[0].port, 2222);
+        assert_eq!(entries[0].user, "admin");
+        assert_eq!(entries[0].tags, vec!["web"]);
+        assert_eq!(entries[1].ip, "10.0.0.2");
+    }
+
+    #[test]
+    fn time_formats() {
+        assert_eq!(parse_time(" 1700000000 "), Some(1700000000));
+        let day = parse_time("2024-01-02").unwrap();
+        assert_eq!(parse_time("2024-01-02 10:00"), Some(day + 36000));
+        assert_eq!(parse_time("2024-01-02 10:00:30"), Some(day + 36030));
+        assert_eq!(parse_time("yesterday"), None);
+        assert_eq!(parse_time(""), None);
+    }
+}
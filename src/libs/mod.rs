@@ -1,6 +1,23 @@
+pub mod audit;
+pub mod backup;
+pub mod check;
+pub mod config;
+pub mod doctor;
+pub mod gen;
+pub mod hostkey;
+pub mod import;
 pub mod my_crypto;
+pub mod output;
 pub mod params;
 pub mod rdata;
+pub mod record;
 pub mod result;
+pub mod rotate;
+pub mod secret;
 pub mod ssh;
+pub mod team;
+pub mod tunnel;
+pub mod unlock;
+pub mod utils;
+pub mod vault;
 
@@ -1,12 +1,26 @@
 use crate::libs::result;
+use crate::libs::secret;
+use crate::libs::secret::SecretBytes;
 use base64;
 use crypto::buffer::{BufferResult, ReadBuffer, WriteBuffer};
-use crypto::{aes, blockmodes, buffer, symmetriccipher};
+use crypto::curve25519::{curve25519, curve25519_base};
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use crypto::{aes, blockmodes, buffer, pbkdf2, symmetriccipher, util};
+use rand::RngCore;
+
+// 加密导出文件的首行标识
+pub const BUNDLE_HEADER: &str = "RSSH-BUNDLE-1";
+const PBKDF2_ROUNDS: u32 = 100_000;
+// 数据文件的加密密钥
+const DATA_KEY: &str = "3ZmTfHY5pzgNN9m3+Y04/w==Yike5212";
+const DATA_IV: [u8; 16] = [3; 16];
 
 // 加密解密
 pub fn my_crypto(content: &str, crypt: bool) -> Result<String, result::MyErr> {
-    let key = "3ZmTfHY5pzgNN9m3+Y04/w==Yike5212";
-    let iv: [u8; 16] = [3; 16];
+    let key = DATA_KEY;
+    let iv = DATA_IV;
 
     let data = match crypt {
         true => match aes256_cbc_encrypt(content.as_bytes(), &key.as_bytes(), &iv) {
@@ -27,7 +41,14 @@ pub fn my_crypto(content: &str, crypt: bool) -> Result<String, result::MyErr> {
                 }
             };
             match aes256_cbc_decrypt(&encrypt_data, &key.as_bytes(), &iv) {
-                Ok(e) => String::from_utf8(e).unwrap(),
+                Ok(e) => match String::from_utf8(e) {
+                    Ok(s) => s,
+                    Err(_) => {
+                        return Err(result::MyErr {
+                            msg: String::from("AES解密出错"),
+                        })
+                    }
+                },
                 Err(_) => {
                     return Err(result::MyErr {
                         msg: String::from("AES解密出错"),
@@ -39,6 +60,209 @@ pub fn my_crypto(content: &str, crypt: bool) -> Result<String, result::MyErr> {
 
     Ok(data)
 }
+
+/* 尽量解密被截断或损坏的数据, 返回能解出的前缀; 用于数据文件的检查与恢复 */
+pub fn my_crypto_partial(content: &str) -> String {
+    // 取最长的合法 base64 前缀, 按 4 字符对齐
+    let mut len = content
+        .bytes()
+        .take_while(|b| b.is_ascii_alphanumeric() || *b == b'+' || *b == b'/')
+        .count();
+    len -= len % 4;
+    let mut data = match base64::decode(&content[..len]) {
+        Ok(d) => d,
+        Err(_) => return String::new(),
+    };
+    // 只解密完整的块, 不校验填充
+    data.truncate(data.len() - data.len() % 16);
+    let mut decryptor = aes::cbc_decryptor(
+        aes::KeySize::KeySize256,
+        DATA_KEY.as_bytes(),
+        &DATA_IV,
+        blockmodes::NoPadding,
+    );
+    let mut out = vec![0u8; data.len()];
+    let mut read_buffer = buffer::RefReadBuffer::new(&data);
+    let mut write_buffer = buffer::RefWriteBuffer::new(&mut out);
+    if decryptor
+        .decrypt(&mut read_buffer, &mut write_buffer, true)
+        .is_err()
+    {
+        return String::new();
+    }
+    let plain = String::from_utf8_lossy(&out).into_owned();
+    secret::wipe_vec(&mut out);
+    plain
+}
+
+// 由口令派生 aes 密钥与 hmac 密钥
+fn derive_key(passphrase: &str, salt: &[u8]) -> (SecretBytes, SecretBytes) {
+    let mut mac = Hmac::new(Sha256::new(), passphrase.as_bytes());
+    let mut out = [0u8; 64];
+    pbkdf2::pbkdf2(&mut mac, salt, PBKDF2_ROUNDS, &mut out);
+    let enc = SecretBytes::new(out[..32].to_vec());
+    let auth = SecretBytes::new(out[32..].to_vec());
+    secret::wipe(&mut out);
+    (enc, auth)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::new(Sha256::new(), key);
+    mac.input(data);
+    mac.result().code().to_vec()
+}
+
+/* 解锁凭证: base64(随机数 | pid | 过期时间 | hmac(密钥, 前三项)), 子进程据此确认父进程已验证口令
+ * 密钥由口令经 pbkdf2 派生, 凭证不能低成本地用于猜测口令; pid 与过期时间由调用者检查 */
+pub fn unlock_proof(passphrase: &str, pid: u32, expiry: u64) -> String {
+    let mut raw = vec![0u8; 16];
+    rand::thread_rng().fill_bytes(&mut raw);
+    raw.extend_from_slice(&pid.to_be_bytes());
+    raw.extend_from_slice(&expiry.to_be_bytes());
+    let (_, auth) = derive_key(passphrase, &raw[..16]);
+    let mac = hmac_sha256(&auth, &raw);
+    raw.extend(mac);
+    base64::encode(&raw)
+}
+
+/* 解锁凭证由同一口令生成时返回其中的 (pid, 过期时间) */
+pub fn check_unlock_proof(passphrase: &str, proof: &str) -> Option<(u32, u64)> {
+    let raw = match base64::decode(proof) {
+        Ok(r) if r.len() == 60 => r,
+        _ => return None,
+    };
+    let (_, auth) = derive_key(passphrase, &raw[..16]);
+    if !util::fixed_time_eq(&hmac_sha256(&auth, &raw[..28]), &raw[28..]) {
+        return None;
+    }
+    let mut pid = [0u8; 4];
+    pid.copy_from_slice(&raw[16..20]);
+    let mut expiry = [0u8; 8];
+    expiry.copy_from_slice(&raw[20..28]);
+    Some((u32::from_be_bytes(pid), u64::from_be_bytes(expiry)))
+}
+
+/* aes 加密并认证, 输出: iv | hmac(iv | 密文) | 密文 */
+pub fn seal(data: &[u8], enc: &[u8], auth: &[u8]) -> Result<Vec<u8>, result::MyErr> {
+    let mut iv = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut iv);
+    let data = match aes256_cbc_encrypt(data, enc, &iv) {
+        Ok(d) => d,
+        Err(_) => {
+            return Err(result::MyErr {
+                msg: String::from("AES加密错误"),
+            })
+        }
+    };
+    let mut out = Vec::with_capacity(48 + data.len());
+    out.extend_from_slice(&iv);
+    out.extend_from_slice(&hmac_sha256(auth, &[&iv[..], &data[..]].concat()));
+    out.extend_from_slice(&data);
+    Ok(out)
+}
+
+/* 校验并解密 seal 的输出, 密钥错误或数据被修改时返回 None */
+pub fn open(raw: &[u8], enc: &[u8], auth: &[u8]) -> Option<Vec<u8>> {
+    if raw.len() < 48 {
+        return None;
+    }
+    let (iv, rest) = raw.split_at(16);
+    let (tag, data) = rest.split_at(32);
+    if !util::fixed_time_eq(tag, &hmac_sha256(auth, &[iv, data].concat())) {
+        return None;
+    }
+    aes256_cbc_decrypt(data, enc, iv).ok()
+}
+
+/* 用口令加密, 输出: 标识行 + base64(salt | iv | hmac | 密文) */
+pub fn encrypt_bundle(content: &str, passphrase: &str) -> Result<String, result::MyErr> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let (enc, auth) = derive_key(passphrase, &salt);
+
+    let mut out = salt.to_vec();
+    out.extend(seal(content.as_bytes(), &enc, &auth)?);
+    Ok(format!("{}\n{}\n", BUNDLE_HEADER, base64::encode(out)))
+}
+
+/* 解密 encrypt_bundle 的输出 */
+pub fn decrypt_bundle(content: &str, passphrase: &str) -> Result<String, result::MyErr> {
+    let body = content
+        .trim_start()
+        .trim_start_matches(BUNDLE_HEADER)
+        .trim();
+    let raw = match base64::decode(body) {
+        Ok(r) if r.len() > 64 => r,
+        _ => {
+            return Err(result::MyErr {
+                msg: String::from("加密文件格式错误"),
+            })
+        }
+    };
+    let (salt, rest) = raw.split_at(16);
+    let (enc, auth) = derive_key(passphrase, salt);
+
+    let data = match open(rest, &enc, &auth) {
+        Some(d) => d,
+        None => {
+            return Err(result::MyErr {
+                msg: String::from("口令错误或文件已损坏"),
+            })
+        }
+    };
+    match String::from_utf8(data) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(result::MyErr {
+            msg: String::from("AES解密出错"),
+        }),
+    }
+}
+
+/* 生成 X25519 私钥 */
+pub fn x25519_secret() -> [u8; 32] {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/* X25519 私钥对应的公钥 */
+pub fn x25519_public(secret: &[u8]) -> [u8; 32] {
+    curve25519_base(secret)
+}
+
+// 由 X25519 共享密钥派生 aes 密钥与 hmac 密钥, 绑定临时公钥与接收者公钥
+fn derive_shared(shared: &[u8], epk: &[u8], recipient: &[u8]) -> (SecretBytes, SecretBytes) {
+    let info = [epk, recipient].concat();
+    let enc = hmac_sha256(shared, &[&info[..], b"rssh-team-enc"].concat());
+    let auth = hmac_sha256(shared, &[&info[..], b"rssh-team-auth"].concat());
+    (SecretBytes::new(enc), SecretBytes::new(auth))
+}
+
+/* 用接收者的公钥加密 key: 临时密钥与接收者公钥做 X25519, 返回 (临时公钥, 密文) */
+pub fn wrap_key(key: &[u8], recipient: &[u8]) -> Result<([u8; 32], Vec<u8>), result::MyErr> {
+    let mut ephemeral = x25519_secret();
+    let epk = x25519_public(&ephemeral);
+    let mut shared = curve25519(&ephemeral, recipient);
+    secret::wipe(&mut ephemeral);
+    // 接收者公钥为小子群的点时共享密钥全为 0
+    if shared.iter().all(|b| *b == 0) {
+        return Err(result::MyErr {
+            msg: String::from("公钥无效"),
+        });
+    }
+    let (enc, auth) = derive_shared(&shared, &epk, recipient);
+    secret::wipe(&mut shared);
+    Ok((epk, seal(key, &enc, &auth)?))
+}
+
+/* 用自己的私钥解密 wrap_key 的输出, 不是发给自己的返回 None */
+pub fn unwrap_key(secret: &[u8], epk: &[u8], wrapped: &[u8]) -> Option<Vec<u8>> {
+    let mut shared = curve25519(secret, epk);
+    let (enc, auth) = derive_shared(&shared, epk, &x25519_public(secret));
+    secret::wipe(&mut shared);
+    open(wrapped, &enc, &auth)
+}
+
 // aes 加密
 fn aes256_cbc_encrypt(
     data: &[u8],
@@ -80,13 +304,21 @@ fn aes256_cbc_decrypt(
     let mut decryptor =
         aes::cbc_decryptor(aes::KeySize::KeySize256, key, iv, blockmodes::PkcsPadding);
 
-    let mut final_result = Vec::<u8>::new();
+    // 预留足够的容量, 扩容时不会在旧的内存中留下明文副本
+    let mut final_result = Vec::<u8>::with_capacity(encrypted_data.len());
     let mut read_buffer = buffer::RefReadBuffer::new(encrypted_data);
     let mut buffer = [0; 4096];
     let mut write_buffer = buffer::RefWriteBuffer::new(&mut buffer);
 
     loop {
-        let result = decryptor.decrypt(&mut read_buffer, &mut write_buffer, true)?;
+        let result = match decryptor.decrypt(&mut read_buffer, &mut write_buffer, true) {
+            Ok(r) => r,
+            Err(e) => {
+                secret::wipe_vec(&mut final_result);
+                secret::wipe(&mut buffer);
+                return Err(e);
+            }
+        };
         final_result.extend(
             write_buffer
                 .take_read_buffer()
@@ -100,6 +332,6 @@ fn aes256_cbc_decrypt(
         }
     }
 
+    secret::wipe(&mut buffer);
     Ok(final_result)
 }
-
@@ -0,0 +1,185 @@
+use super::result;
+use super::secret;
+use super::secret::Secret;
+
+use rustc_serialize::json;
+use std::collections::BTreeMap;
+
+// 支持的输出格式
+pub const FORMATS: [&str; 3] = ["plain", "json", "tsv"];
+
+/* 校验输出格式 */
+pub fn check_format(format: &str) -> Result<(), result::MyErr> {
+    if FORMATS.contains(&format) {
+        Ok(())
+    } else {
+        Err(result::MyErr {
+            msg: format!("不支持的输出格式: {}, 可选: {}", format, FORMATS.join("|")),
+        })
+    }
+}
+
+/* 按格式渲染多行记录, json 输出对象数组, tsv 首行为表头, plain 以空格分隔 */
+pub fn render<S: AsRef<str>>(format: &str, header: &[&str], rows: &[Vec<S>]) -> String {
+    match format {
+        "json" => {
+            let list: Vec<BTreeMap<&str, &str>> =
+                rows.iter().map(|r| to_object(header, r)).collect();
+            json::encode(&list).unwrap() + "\n"
+        }
+        _ => lines(
+            format,
+            header,
+            &rows.iter().map(|r| r.as_slice()).collect::<Vec<&[S]>>(),
+        ),
+    }
+}
+
+/* 按格式渲染单条记录, json 输出单个对象 */
+pub fn render_one<S: AsRef<str>>(format: &str, header: &[&str], row: &[S]) -> String {
+    match format {
+        "json" => json::encode(&to_object(header, row)).unwrap() + "\n",
+        _ => lines(format, header, &[row]),
+    }
+}
+
+// tsv 与 plain 格式, 单元格直接写入预留好的缓冲区, 不留下密码的副本
+fn lines<S: AsRef<str>>(format: &str, header: &[&str], rows: &[&[S]]) -> String {
+    let size: usize = rows
+        .iter()
+        .flat_map(|r| r.iter())
+        .map(|c| c.as_ref().len() * 2 + 1)
+        .sum();
+    let mut out = String::with_capacity(size + rows.len() + header.join("\t").len() + 1);
+    if format == "tsv" {
+        out.push_str(&header.join("\t"));
+        out.push('\n');
+    }
+    for r in rows {
+        for (i, c) in r.iter().enumerate() {
+            match format {
+                "tsv" if i > 0 => out.push('\t'),
+                "tsv" => {}
+                _ if i > 0 => out.push(' '),
+                _ => {}
+            }
+            match format {
+                "tsv" => escape_tsv(&mut out, c.as_ref()),
+                _ => out.push_str(c.as_ref()),
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/* 远程命令的执行结果, json 中 code 为数字 */
+#[derive(RustcEncodable)]
+pub struct ExecResult {
+    pub ip: String,
+    pub user: String,
+    pub code: i32,
+    pub status: String,
+}
+
+/* 按格式渲染执行结果 */
+pub fn render_exec(format: &str, r: &ExecResult) -> String {
+    match format {
+        "json" => json::encode(r).unwrap() + "\n",
+        _ => render_one(
+            format,
+            &["ip", "user", "code", "status"],
+            &[
+                r.ip.clone(),
+                r.user.clone(),
+                r.code.to_string(),
+                r.status.clone(),
+            ],
+        ),
+    }
+}
+
+/* 打印多行记录, 渲染的内容打印后清零 */
+pub fn print<S: AsRef<str>>(format: &str, header: &[&str], rows: &[Vec<S>]) {
+    print!("{}", &*Secret::new(render(format, header, rows)));
+}
+
+/* 打印单条记录 */
+pub fn print_one<S: AsRef<str>>(format: &str, header: &[&str], row: &[S]) {
+    print!("{}", &*Secret::new(render_one(format, header, row)));
+}
+
+/* 按列对齐打印表格, 供 plain 格式的列表使用 */
+pub fn print_aligned<S: AsRef<str>>(header: &[&str], rows: &[Vec<S>]) {
+    let mut width: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for r in rows {
+        for (i, c) in r.iter().enumerate() {
+            let n = c.as_ref().chars().count();
+            if i < width.len() && n > width[i] {
+                width[i] = n;
+            }
+        }
+    }
+    let total: usize = width.iter().map(|w| w * 4 + 2).sum();
+    // 缓冲区预留足够的大小, 不会重新分配而留下副本, 打印后清零
+    let line = |cols: Vec<&str>| {
+        let mut out = String::with_capacity(total);
+        for (i, c) in cols.iter().enumerate() {
+            if i > 0 {
+                out.push_str("  ");
+            }
+            out.push_str(c);
+            let pad = width[i].saturating_sub(c.chars().count());
+            out.extend(std::iter::repeat(' ').take(pad));
+        }
+        let end = out.trim_end().len();
+        out.truncate(end);
+        println!("{}", out);
+        secret::wipe_string(&mut out);
+    };
+    line(header.to_vec());
+    for r in rows {
+        line(r.iter().map(|c| c.as_ref()).collect());
+    }
+}
+
+fn to_object<'a, S: AsRef<str>>(header: &[&'a str], row: &'a [S]) -> BTreeMap<&'a str, &'a str> {
+    let mut obj = BTreeMap::new();
+    for (k, v) in header.iter().zip(row.iter()) {
+        obj.insert(*k, v.as_ref());
+    }
+    obj
+}
+
+// tsv 字段中的制表符、换行、反斜杠需要转义
+fn escape_tsv(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_formats() {
+        let rows = vec![vec![Secret::from("10.0.0.1"), Secret::from("a\tb\\c")]];
+        let header = ["ip", "password"];
+        assert_eq!(render("plain", &header, &rows), "10.0.0.1 a\tb\\c\n");
+        assert_eq!(
+            render("tsv", &header, &rows),
+            "ip\tpassword\n10.0.0.1\ta\\tb\\\\c\n"
+        );
+        assert_eq!(
+            render_one("json", &header, &rows[0]),
+            "{\"ip\":\"10.0.0.1\",\"password\":\"a\\tb\\\\c\"}\n"
+        );
+    }
+}
@@ -1,23 +1,81 @@
+use super::check;
+use super::config::Config;
+use super::output;
+use super::record;
 use super::result;
+use super::secret;
+use super::tunnel;
+use super::vault;
 use std::env::Args;
 
 #[derive(Debug)]
 pub struct Params {
-    pub option: char,      // 选项
-    pub ip: String,        // ip
-    pub user: String,      // 用户
-    pub password: String,  // 密码
-    pub in_file: String,   // 导入导出文件
-    pub key: String,       // 认证口令
-    pub cmd: String,       // 远程执行命令
-    pub file_path: String, // 二进制文件路径
-    pub pwd: String,       // 执行该程序时需要输入的密码
-    pub version: u8,       // 1: 所有命令均需要输入密码, 0: 特权命令才需要密码
+    pub option: char,        // 选项
+    pub ip: String,          // ip
+    pub user: String,        // 用户
+    pub password: String,    // 密码
+    pub in_file: String,     // 导入导出文件
+    pub key: String,         // 认证口令
+    pub cmd: String,         // 远程执行命令
+    pub file_path: String,   // 数据文件路径, 未配置 vault 时为二进制文件自身
+    pub exe_path: String,    // 二进制文件路径, 用于启动子进程
+    pub pwd: String,         // 执行该程序时需要输入的密码
+    pub version: u8,         // 1: 所有命令均需要输入密码, 0: 特权命令才需要密码
+    pub format: String,      // 输出格式: plain, json, tsv
+    pub port: u16,           // ssh 端口, 0 表示未指定
+    pub alias: Vec<String>,  // 主机别名
+    pub tags: Vec<String>,   // 主机标签, 新增时设置, ls 时过滤
+    pub filter_user: String, // ls 按用户过滤
+    pub sort: String,        // ls 排序: ip, user, last
+    pub show_password: bool, // ls 显示密码
+    pub import_from: String, // 导入格式, 为空时按文件名推断
+    pub map: Vec<String>,    // csv 列映射: 列名=字段
+    pub dry_run: bool,       // 只显示将要进行的修改, 不保存
+    pub merge: String,       // 合并策略: overwrite, skip, newer, interactive
+    pub filter_host: String, // 按 ip 或别名过滤
+    pub plaintext: bool,     // 明文导出
+    pub yes: bool,           // 跳过确认
+    pub action: String,      // 子命令动作
+    pub args: Vec<String>,   // 子命令的其余参数
+    pub length: usize,       // 生成密码的长度, 0 表示按策略
+    pub classes: String,     // 生成密码的字符类别
+    pub no_ambiguous: bool,  // 排除容易看错的字符
+    pub words: usize,        // 口令短语单词数
+    pub separator: String,   // 口令短语分隔符
+    pub wordlist: String,    // 口令短语词表文件
+    pub policy: String,      // 使用指定名称的策略
+    pub count: usize,        // gen 生成的个数
+    pub generate: bool,      // -c 时生成密码
+    pub max_age: u64,        // 密码最长使用天数, 0 表示不设置
+    pub expired: bool,       // 只选已过期的条目
+    pub expiring: u64,       // 只选 n 天内过期的条目(含已过期), 0 表示不过滤
+    pub stale: bool,         // 只选 check 认证失败的条目
+    pub jobs: usize,         // check 并发数
+    pub mark: bool,          // check 结果写入数据文件
+    pub jump: String,        // -c 时设置跳板机 user@ip, none 为清除
+    pub escalate: String,    // -c 时设置提权方式 sudo:user 或 su:user, none 为清除
+    pub auth: String,        // -c 时设置认证方式 password 或 key, none 为清除
+    pub background: bool,    // forward 在后台运行
+    pub record: String,      // -c 时设置主机的录像模式 off|on|input, none 为使用全局设置
+    pub vault: String,       // 使用的 vault 名称, default 为原有的数据文件
+    pub config: Config,      // 配置文件中的设置
+}
+
+// 释放时清零命令行中的密码
+impl Drop for Params {
+    fn drop(&mut self) {
+        secret::wipe_string(&mut self.password);
+        secret::wipe_string(&mut self.pwd);
+    }
 }
 
 impl Params {
-    pub fn parse(args: &mut Args, data_file: String) -> Result<Params, result::MyErr> {
-        // rssh 192.168.137.220                     // 默认用户 root 登录
+    pub fn parse(
+        args: &mut Args,
+        exe_path: String,
+        config: Config,
+    ) -> Result<Params, result::MyErr> {
+        // rssh 192.168.137.220                     // 默认用户登录, 见配置文件的 user 与 hosts
         // rssh 192.168.137.220 root                // 指定用户登录
         // rssh -f pwd version                      // 初始化, 复制源文件, 填充到指定大小, 初始化密码
         //                                                version 1: 所有命令均需要输入密码, 0: 特权命令才需要密码
@@ -26,28 +84,307 @@ impl Params {
         // rssh -l /root/password.txt               // 批量导入密码
         // rssh -e /root/password.txt               // 批量导出密码
         // rssh -g 192.168.137.220 root             // 获取指定密码
+        // rssh -g 192.168.137.220 root yikeyike    // 脚本调用, 只用于解锁策略不含 get 的主机 (unlock set lab none)
         // rssh -r 192.168.137.220 root 'ls -l'     // 运行命令
 
         // rssh -d 192.168.137.220 root             // 删除用户
         // rssh -v                                  // 验证密码正确性
         // rssh -p 192.168.137.200                  // 列出指定 ip 所有用户
+        // rssh ls [pattern]                        // 列出所有条目, pattern 匹配 ip 或别名
+        // rssh backup list                         // 列出自动备份
+        // rssh backup config 10 30                 // 保留最近 10 份且不超过 30 天的备份
+        // rssh restore 1760000000                  // 恢复到指定备份
+        // rssh history 192.168.137.220 root        // 查看历史密码, --show-password 显示密码
+        // rssh history 192.168.137.220 root restore 1   // 恢复第 1 个历史密码
+        // rssh rotate 192.168.137.220 root         // 修改远程密码, 验证后更新
+        // rssh rotate [pattern] --tag prod         // 批量修改
+        // rssh check [pattern] --tag prod --mark   // 并发检查密码是否可用, 认证失败的标记为 stale
+        // rssh hostkey show [pattern]              // 查看保存的主机公钥指纹
+        // rssh hostkey accept|forget 192.168.137.220   // 重新获取并信任主机公钥, 删除主机公钥
+        // rssh forward -L 5432:db:5432 -D 1080 host [user] [--background]   // 端口转发, 断开后自动重连
+        // rssh forward list | forward stop id      // 列出、结束运行中的隧道
+        // rssh record [off|on|input]               // 查看、设置全局会话录像模式
+        // rssh replay [id] [--host 192.168]        // 列出录像, 回放指定录像
+        // rssh audit show [pattern] | audit verify  // 查看、校验审计日志
+        // rssh config show                         // 查看生效的配置
+        // rssh unlock [list]                       // 列出解锁策略: 哪些操作需要输入密码
+        // rssh unlock set prod all | unlock set lab none   // 设置标签的解锁策略, default 为其他主机
+        // rssh unlock rm lab                       // 删除解锁策略
+        // rssh vault [list] | vault use customerA   // 列出 vault, 切换缺省 vault
+        // rssh vault create customerA pwd [flag]   // 新建 vault, 有单独的密码
+        // rssh vault copy default customerA [pattern] --tag t   // 在 vault 之间复制条目
+        // rssh team keygen                         // 生成 team vault 的私钥, 显示公钥
+        // rssh team init team.rssh alice           // 新建 team vault, 自己为第一个成员
+        // rssh team add team.rssh bob <公钥>       // 添加成员, team members 列出成员
+        // rssh team rm team.rssh bob               // 删除成员并更换数据密钥
+        // rssh team push team.rssh [pattern] --tag t   // 把当前 vault 的条目合并到 team vault
+        // rssh team pull team.rssh [pattern] --tag t   // 把 team vault 的条目导入当前 vault
+        // rssh merge other.vault [base.vault]      // 三方合并另一份数据文件, base 为共同的上一版本
+        // rssh doctor [check]                      // 检查数据文件: 文件尾、base64、解密、json、重复主机等
+        // rssh doctor repair [--yes]               // 从备份或残留的数据恢复损坏的数据文件
+        // rssh gen --length 24 --no-ambiguous      // 生成密码
+        // rssh -c 192.168.137.220 root --generate  // 按主机标签的策略生成密码并保存
+        // rssh policy [list]                       // 列出密码策略
+        // rssh policy set prod --length 32         // 设置标签 prod 的策略, default 为全局缺省
+        // rssh policy rm prod                      // 删除策略
+        //
+        // 长选项可出现在任意位置:
+        //   --vault customerA                        // 使用指定的 vault, 可用于所有命令
+        //   --format plain|json|tsv                  // 输出格式, 作用于 -p -g -r ls 与明文 -e
+        //   --port 22 --alias web1 --tag prod,db     // -c 时设置主机信息
+        //   --jump root@10.0.0.1                     // -c 时设置跳板机(已保存的条目), none 为清除
+        //   --escalate sudo:alice | su:alice         // -c 时设置提权方式(登录用户需已保存), none 为清除
+        //   --auth password|key                      // -c 时设置认证方式, none 为清除
+        //   --record off|on|input                    // -c 时设置主机的录像模式, none 为使用全局设置
+        //   --tag prod --user root --sort ip|user|last --show-password   // ls 过滤与排序
+        //   --from plain|csv|json|ssh|ini|yaml --map 列名=字段 --dry-run  // -l 导入
+        //   --overwrite | --skip-existing | --keep-newer | --interactive   // -l、merge 冲突时的处理
+        //   --host 192.168 --tag prod --plaintext --yes                   // -e 过滤, 明文导出
+        //   --cmd 'printf "%s:%s\n" {user} {new} | chpasswd'              // rotate 远程修改命令
+        //   --length 20 --classes lower,upper,digit,symbol --no-ambiguous  // 生成字符密码
+        //   --words 5 --separator - --wordlist words.txt --policy prod     // 生成口令短语, 指定策略
+        //   --count 5 --generate                                           // gen 个数, -c 生成密码
+        //   --max-age 90d                                  // -c 设置主机、policy set 设置标签的密码有效期
+        //   --expired --expiring 14d                       // ls、rotate 只选已过期或 14 天内过期的条目
+        //   --stale --jobs 8 --mark                        // 只选 stale 条目; check 并发数, 写入检查结果
 
         let exe_name = args.next().unwrap();
 
+        // 先取出长选项, 剩余的按位置解析
+        let mut format = config.format.clone();
+        let mut port: u16 = 0;
+        let mut alias: Vec<String> = Vec::new();
+        let mut tags: Vec<String> = Vec::new();
+        let mut filter_user = String::new();
+        let mut sort = String::from("ip");
+        let mut show_password = false;
+        let mut import_from = String::new();
+        let mut map: Vec<String> = Vec::new();
+        let mut dry_run = false;
+        let mut merge = String::new();
+        let mut filter_host = String::new();
+        let mut plaintext = false;
+        let mut yes = false;
+        let mut length: usize = 0;
+        let mut cmd = String::new();
+        let mut classes = String::new();
+        let mut no_ambiguous = false;
+        let mut words: usize = 0;
+        let mut separator = String::new();
+        let mut wordlist = String::new();
+        let mut policy = String::new();
+        let mut count: usize = 1;
+        let mut generate = false;
+        let mut max_age: u64 = 0;
+        let mut expired = false;
+        let mut expiring: u64 = 0;
+        let mut stale = false;
+        let mut jobs = check::DEFAULT_JOBS;
+        let mut mark = false;
+        let mut jump = String::new();
+        let mut escalate = String::new();
+        let mut auth = String::new();
+        let mut record = String::new();
+        let mut background = false;
+        let mut vault_name = String::new();
+        let mut rest: Vec<String> = Vec::new();
+        while let Some(a) = args.next() {
+            if &a == "--" {
+                rest.extend(args.by_ref());
+                break;
+            } else if a.starts_with("--") {
+                // 支持 --name value 与 --name=value 两种写法
+                let (name, value) = match a.find('=') {
+                    Some(i) => (a[..i].to_string(), Some(a[i + 1..].to_string())),
+                    None => (a.clone(), None),
+                };
+                match name.as_str() {
+                    "--format" => match opt_value(value, args) {
+                        Some(f) => format = f,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--port" => match opt_value(value, args).map(|p| p.parse()) {
+                        Some(Ok(p)) => port = p,
+                        _ => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--alias" => match opt_value(value, args) {
+                        Some(a) => alias.extend(split_list(&a)),
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--tag" => match opt_value(value, args) {
+                        Some(t) => tags.extend(split_list(&t)),
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--user" => match opt_value(value, args) {
+                        Some(u) => filter_user = u,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--sort" => match opt_value(value, args) {
+                        Some(o) if ["ip", "user", "last"].contains(&o.as_str()) => sort = o,
+                        _ => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--show-password" => show_password = true,
+                    "--from" => match opt_value(value, args) {
+                        Some(f) => import_from = f,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--map" => match opt_value(value, args) {
+                        Some(m) => map.push(m),
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--dry-run" => dry_run = true,
+                    "--overwrite" => merge = String::from("overwrite"),
+                    "--skip-existing" => merge = String::from("skip"),
+                    "--keep-newer" => merge = String::from("newer"),
+                    "--interactive" => merge = String::from("interactive"),
+                    "--host" => match opt_value(value, args) {
+                        Some(h) => filter_host = h,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--plaintext" => plaintext = true,
+                    "--yes" => yes = true,
+                    "--length" => match opt_value(value, args).map(|l| l.parse()) {
+                        Some(Ok(l)) => length = l,
+                        _ => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--cmd" => match opt_value(value, args) {
+                        Some(c) => cmd = c,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--classes" => match opt_value(value, args) {
+                        Some(c) => classes = c,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--no-ambiguous" => no_ambiguous = true,
+                    "--words" => match opt_value(value, args).map(|w| w.parse()) {
+                        Some(Ok(w)) => words = w,
+                        _ => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--separator" => match opt_value(value, args) {
+                        Some(s) => separator = s,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--wordlist" => match opt_value(value, args) {
+                        Some(w) => wordlist = w,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--policy" => match opt_value(value, args) {
+                        Some(p) => policy = p,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--count" => match opt_value(value, args).map(|c| c.parse()) {
+                        Some(Ok(c)) => count = c,
+                        _ => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--generate" => generate = true,
+                    "--max-age" => match opt_value(value, args).and_then(|d| parse_days(&d)) {
+                        Some(d) => max_age = d,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--expired" => expired = true,
+                    "--stale" => stale = true,
+                    "--mark" => mark = true,
+                    "--background" => background = true,
+                    "--jump" => match opt_value(value, args) {
+                        Some(j) => jump = j,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--escalate" => match opt_value(value, args) {
+                        Some(e) => escalate = e,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--auth" => match opt_value(value, args) {
+                        Some(a) if ["password", "key", "none"].contains(&a.as_str()) => auth = a,
+                        _ => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--vault" => match opt_value(value, args) {
+                        Some(v) => vault_name = v,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--record" => match opt_value(value, args) {
+                        Some(r) => record = r,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--jobs" => match opt_value(value, args).map(|j| j.parse()) {
+                        Some(Ok(j)) if j > 0 => jobs = j,
+                        _ => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "--expiring" => match opt_value(value, args).and_then(|d| parse_days(&d)) {
+                        Some(d) => expiring = d,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    // 未知的长选项原样保留为位置参数, 例如远程命令中的 --color
+                    _ => rest.push(a),
+                }
+            } else {
+                rest.push(a);
+            }
+        }
+        if let Err(e) = output::check_format(&format) {
+            println!("{}", e.msg);
+            return Err(result::MyErr { msg: exe_name });
+        }
+        if vault_name.is_empty() {
+            vault_name = vault::current(&config);
+        } else if let Err(e) = vault::check_name(&vault_name) {
+            println!("{}", e.msg);
+            return Err(result::MyErr { msg: exe_name });
+        }
+        let args = &mut rest.into_iter();
+
         if args.len() < 1 {
             Err(result::MyErr { msg: exe_name })
         } else {
             let mut params = Params {
                 option: ' ',
                 ip: String::from(""),
-                user: String::from("root"),
+                user: config.user.clone(),
                 password: String::from(""),
                 in_file: String::from(""),
                 key: String::from(""),
-                cmd: String::from(""),
-                file_path: data_file,
+                cmd,
+                file_path: vault::path(&config, &exe_path, &vault_name),
+                exe_path,
                 pwd: String::from("init"),
                 version: 0,
+                format,
+                port,
+                alias,
+                tags,
+                filter_user,
+                sort,
+                show_password,
+                import_from,
+                map,
+                dry_run,
+                merge,
+                filter_host,
+                plaintext,
+                yes,
+                action: String::from(""),
+                args: vec![],
+                length,
+                classes,
+                no_ambiguous,
+                words,
+                separator,
+                wordlist,
+                policy,
+                count,
+                generate,
+                max_age,
+                expired,
+                expiring,
+                stale,
+                jobs,
+                mark,
+                jump,
+                escalate,
+                auth,
+                background,
+                record,
+                vault: vault_name,
+                config,
             };
 
             let temp = args.next().unwrap();
@@ -55,12 +392,13 @@ impl Params {
             if &temp == "-c" {
                 // 修改添加密码
                 params.option = 'c';
-                if args.len() < 3 {
+                // --generate 时不需要密码参数
+                if args.len() < 3 - params.generate as usize {
                     return Err(result::MyErr { msg: exe_name });
                 }
                 params.ip = args.next().unwrap();
                 params.user = args.next().unwrap();
-                params.password = args.next().unwrap();
+                params.password = args.next().unwrap_or_default();
             } else if &temp == "-l" {
                 // 导入密码
                 params.option = 'l';
@@ -80,7 +418,10 @@ impl Params {
                 params.option = 'g';
 
                 match args.next() {
-                    Some(p) => params.ip = p,
+                    Some(p) => {
+                        params.user = params.config.user_for(&p);
+                        params.ip = p;
+                    }
                     None => return Err(result::MyErr { msg: exe_name }),
                 }
 
@@ -149,6 +490,253 @@ impl Params {
                 }
             } else if &temp == "-v" {
                 params.option = 'v';
+            } else if &temp == "ls" {
+                // 列出所有条目
+                params.option = 'L';
+                if let Some(p) = args.next() {
+                    params.ip = p;
+                }
+            } else if &temp == "backup" {
+                // 备份管理
+                params.option = 'B';
+                params.action = args.next().unwrap_or_else(|| String::from("list"));
+                params.args = args.by_ref().collect();
+                if &params.action != "list" && &params.action != "config" {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "restore" {
+                // 从备份恢复
+                params.option = 'R';
+                match args.next() {
+                    Some(id) => params.action = id,
+                    None => return Err(result::MyErr { msg: exe_name }),
+                }
+            } else if &temp == "history" {
+                // 历史密码
+                params.option = 'H';
+                match args.next() {
+                    Some(p) => params.ip = p,
+                    None => return Err(result::MyErr { msg: exe_name }),
+                }
+                match args.next() {
+                    Some(u) => params.user = u,
+                    None => return Err(result::MyErr { msg: exe_name }),
+                }
+                params.action = args.next().unwrap_or_default();
+                params.args = args.by_ref().collect();
+                if !params.action.is_empty() && &params.action != "restore" {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "rotate" {
+                // 修改远程密码, 不指定用户时为批量模式
+                params.option = 'T';
+                params.ip = args.next().unwrap_or_default();
+                params.user = args.next().unwrap_or_default();
+            } else if &temp == "check" {
+                // 检查密码是否可用
+                params.option = 'C';
+                params.ip = args.next().unwrap_or_default();
+            } else if &temp == "__probe" {
+                // check 的子进程: __probe user@ip port proxy, 密码与主机公钥从标准输入读取
+                params.option = 'Q';
+                match (args.next(), args.next().map(|p| p.parse())) {
+                    (Some(u), Some(Ok(p))) => {
+                        params.ip = u;
+                        params.port = p;
+                    }
+                    _ => return Err(result::MyErr { msg: exe_name }),
+                }
+                params.cmd = args.next().unwrap_or_default();
+            } else if &temp == "__proxy" {
+                // 跳板机的 ProxyCommand: __proxy user@ip host port
+                params.option = 'J';
+                params.ip = args.next().unwrap_or_default();
+                params.args = args.by_ref().collect();
+                if params.ip.is_empty() || params.args.len() != 2 {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "hostkey" {
+                // 主机公钥管理
+                params.option = 'K';
+                params.action = args.next().unwrap_or_else(|| String::from("show"));
+                params.ip = args.next().unwrap_or_default();
+                if !["show", "accept", "forget"].contains(&params.action.as_str())
+                    || (&params.action != "show" && params.ip.is_empty())
+                {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "forward" || &temp == "__tunnel" {
+                // 端口转发: forward list | forward stop id | forward -L spec... host [user]
+                // __tunnel 为后台运行的子进程, 参数相同
+                params.option = if &temp == "forward" { 'F' } else { 'N' };
+                params.action = String::from("start");
+                while let Some(a) = args.next() {
+                    match a.as_str() {
+                        "list" | "stop" if params.ip.is_empty() && params.args.is_empty() => {
+                            params.action = a;
+                            if &params.action == "stop" {
+                                match args.next() {
+                                    Some(id) => params.ip = id,
+                                    None => return Err(result::MyErr { msg: exe_name }),
+                                }
+                            }
+                            break;
+                        }
+                        "-L" | "-R" | "-D" => match args.next() {
+                            Some(spec) => {
+                                if let Err(e) = tunnel::check_spec(&a, &spec) {
+                                    println!("{}", e.msg);
+                                    return Err(result::MyErr { msg: exe_name });
+                                }
+                                params.args.push(a);
+                                params.args.push(spec);
+                            }
+                            None => return Err(result::MyErr { msg: exe_name }),
+                        },
+                        _ if params.ip.is_empty() => {
+                            params.user = params.config.user_for(&a);
+                            params.ip = a;
+                        }
+                        _ => params.user = a,
+                    }
+                }
+                if &params.action == "start" && (params.ip.is_empty() || params.args.is_empty()) {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "record" {
+                // 会话录像设置
+                params.option = 'E';
+                params.action = args.next().unwrap_or_default();
+                if !params.action.is_empty() && !record::MODES.contains(&params.action.as_str()) {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "audit" {
+                // 审计日志
+                params.option = 'A';
+                params.action = args.next().unwrap_or_else(|| String::from("show"));
+                params.ip = args.next().unwrap_or_default();
+                if &params.action != "show" && &params.action != "verify" {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "config" {
+                // 查看配置
+                params.option = 'O';
+                params.action = args.next().unwrap_or_else(|| String::from("show"));
+                if &params.action != "show" {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "unlock" {
+                // 解锁策略
+                params.option = 'U';
+                params.action = args.next().unwrap_or_else(|| String::from("list"));
+                if &params.action != "list" {
+                    match args.next() {
+                        Some(n) => params.ip = n,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    }
+                }
+                if &params.action == "set" {
+                    match args.next() {
+                        Some(o) => params.args.push(o),
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    }
+                }
+                if !["list", "set", "rm"].contains(&params.action.as_str()) {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "vault" {
+                // vault 管理
+                params.option = 'V';
+                params.action = args.next().unwrap_or_else(|| String::from("list"));
+                match params.action.as_str() {
+                    "list" => {}
+                    "use" => match args.next() {
+                        Some(n) => params.ip = n,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    },
+                    "create" => {
+                        match (args.next(), args.next()) {
+                            (Some(n), Some(p)) => {
+                                params.ip = n;
+                                params.pwd = p;
+                            }
+                            _ => return Err(result::MyErr { msg: exe_name }),
+                        }
+                        match args.next().map(|f| f.parse()) {
+                            Some(Ok(f)) => params.version = f,
+                            Some(Err(_)) => return Err(result::MyErr { msg: exe_name }),
+                            None => {}
+                        }
+                    }
+                    "copy" => {
+                        params.args = args.by_ref().take(2).collect();
+                        params.ip = args.next().unwrap_or_default();
+                        if params.args.len() != 2 {
+                            return Err(result::MyErr { msg: exe_name });
+                        }
+                    }
+                    _ => return Err(result::MyErr { msg: exe_name }),
+                }
+            } else if &temp == "team" {
+                // team vault: 按成员公钥共享
+                params.option = 'W';
+                params.action = args.next().unwrap_or_default();
+                let n = match params.action.as_str() {
+                    "keygen" => 0,
+                    "members" | "push" | "pull" => 1,
+                    "init" | "rm" => 2,
+                    "add" => 3,
+                    _ => return Err(result::MyErr { msg: exe_name }),
+                };
+                let mut rest: Vec<String> = args.by_ref().collect();
+                let pattern = ["push", "pull"].contains(&params.action.as_str());
+                if rest.len() != n && !(pattern && rest.len() == n + 1) {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+                if n > 0 {
+                    params.in_file = rest.remove(0);
+                    params.ip = if rest.is_empty() {
+                        String::new()
+                    } else {
+                        rest.remove(0)
+                    };
+                    params.args = rest;
+                }
+            } else if &temp == "merge" {
+                // 合并另一份数据文件
+                params.option = 'M';
+                match args.next() {
+                    Some(f) => params.in_file = f,
+                    None => return Err(result::MyErr { msg: exe_name }),
+                }
+                params.args = args.by_ref().take(1).collect();
+            } else if &temp == "doctor" {
+                // 检查、恢复数据文件
+                params.option = 'D';
+                params.action = args.next().unwrap_or_else(|| String::from("check"));
+                if !["check", "repair"].contains(&params.action.as_str()) {
+                    return Err(result::MyErr { msg: exe_name });
+                }
+            } else if &temp == "replay" {
+                // 会话录像列表与回放
+                params.option = 'S';
+                params.action = args.next().unwrap_or_default();
+            } else if &temp == "gen" {
+                // 生成密码
+                params.option = 'G';
+            } else if &temp == "policy" {
+                // 密码策略
+                params.option = 'P';
+                params.action = args.next().unwrap_or_else(|| String::from("list"));
+                if &params.action != "list" {
+                    match args.next() {
+                        Some(n) => params.ip = n,
+                        None => return Err(result::MyErr { msg: exe_name }),
+                    }
+                }
+                if !["list", "set", "rm"].contains(&params.action.as_str()) {
+                    return Err(result::MyErr { msg: exe_name });
+                }
             } else if &temp == "-p" {
                 // 列出指定 ip 的用户
                 params.option = 'p';
@@ -158,33 +746,209 @@ impl Params {
                 }
             } else {
                 // 登录
+                params.user = params.config.user_for(&temp);
                 params.ip = temp;
                 match args.next() {
                     Some(u) => params.user = u,
                     None => {}
                 }
             };
+            // merge 缺省逐个询问冲突, 导入缺省覆盖
+            if params.merge.is_empty() {
+                params.merge = match params.option {
+                    'M' => String::from("interactive"),
+                    _ => String::from("overwrite"),
+                };
+            }
             Ok(params)
         }
     }
 
     pub fn help(exe_name: &str) {
         println!("使用说明: ");
-        println!("  {} ip [user]        远程连接, 默认用户 root", &exe_name);
-        println!("  {} -c ip user pwd   修改或新增密码", &exe_name);
+        println!(
+            "  {} ip [user]        远程连接, 默认用户见配置, 未配置时为 root",
+            &exe_name
+        );
+        println!(
+            "  {} -c ip user pwd   修改或新增密码, --generate 时按策略生成密码",
+            &exe_name
+        );
         println!("  {} -r ip user cmd   执行命令", &exe_name);
         println!(
-            "  {} -l file_name     导入用户密码, 格式: ip user pwd",
+            "  {} -l file_name     导入用户密码, 默认格式: ip user pwd",
+            &exe_name
+        );
+        println!(
+            "  {} -e file_name     导出用户密码到文件, 默认用口令加密",
             &exe_name
         );
-        println!("  {} -e file_name     导出用户密码导文件", &exe_name);
         println!("  {} -g ip [user]     获取密码", &exe_name);
+        println!("  {} -g ip user yikeyike   脚本调用: 不提示密码, 输出 \"user ip 密码\"; 解锁策略要求 get 验证时失败退出", &exe_name);
         println!("  {} -f pwd flag      初始化, pwd: 执行本程序所需密码; flag: 0 特权操作才需密码, 1 所有操作都需密码", &exe_name);
+        println!("                     已有数据时需要确认, --yes 跳过确认");
         println!("  {} -k pwd flag      修改认证密码", &exe_name);
         println!("  {} -d ip user       删除密码", &exe_name);
         //println!("  {} -v               验证密码", &exe_name);
         println!("  {} -p ip            列出所有用户", &exe_name);
+        println!(
+            "  {} ls [pattern]     列出所有条目, pattern 匹配 ip 或别名",
+            &exe_name
+        );
+        println!("  {} backup [list]    列出自动备份", &exe_name);
+        println!(
+            "  {} backup config keep days   设置备份保留份数与天数, 0 表示不限制",
+            &exe_name
+        );
+        println!("  {} restore id       恢复到指定备份", &exe_name);
+        println!(
+            "  {} history ip user [restore n]   查看或恢复历史密码",
+            &exe_name
+        );
+        println!(
+            "  {} rotate ip user    生成新密码并修改远程主机密码, 验证成功后更新",
+            &exe_name
+        );
+        println!(
+            "  {} rotate [pattern] --tag t [--user u]   批量修改并输出报告",
+            &exe_name
+        );
+        println!(
+            "  {} check [pattern] --tag t [--user u]    检查保存的密码是否可用",
+            &exe_name
+        );
+        println!(
+            "  {} hostkey show [pattern]   查看保存的主机公钥指纹",
+            &exe_name
+        );
+        println!(
+            "  {} hostkey accept|forget ip   重新获取并信任主机公钥; 删除主机公钥",
+            &exe_name
+        );
+        println!(
+            "  {} forward -L|-R spec -D port host [user]   端口转发, 断开后自动重连, --background 后台运行",
+            &exe_name
+        );
+        println!(
+            "  {} forward list|stop id   列出、结束运行中的隧道",
+            &exe_name
+        );
+        println!(
+            "  {} record [off|on|input]   查看、设置全局会话录像: 不录、录输出、同时录输入",
+            &exe_name
+        );
+        println!(
+            "  {} replay [id] [--host h]   列出会话录像; 回放指定录像",
+            &exe_name
+        );
+        println!(
+            "  {} audit show [pattern]|verify   查看审计日志; 校验日志是否被篡改",
+            &exe_name
+        );
+        println!(
+            "  {} config show      查看生效的配置: /etc/rssh/config.toml 与 ~/.config/rssh/config.toml",
+            &exe_name
+        );
+        println!(
+            "  {} unlock [list]    列出解锁策略: 各标签的主机哪些操作需要输入密码",
+            &exe_name
+        );
+        println!(
+            "  {} unlock set|rm name [ops]   设置或删除策略, name 为标签, default 为其他主机",
+            &exe_name
+        );
+        println!("      ops: login,exec,get,list,add,delete,import,export 或 all、none");
+        println!(
+            "  {} vault [list] | vault use name   列出 vault; 切换缺省 vault, default 为原有的数据文件",
+            &exe_name
+        );
+        println!(
+            "  {} vault create name pwd [flag]   新建 vault, pwd 与 flag 同 -f",
+            &exe_name
+        );
+        println!(
+            "  {} vault copy from to [pattern] --tag t --user u   在 vault 之间复制条目, 冲突处理同 -l",
+            &exe_name
+        );
+        println!(
+            "  {} team keygen      生成 team vault 的私钥并显示公钥, 私钥路径见配置 identity",
+            &exe_name
+        );
+        println!(
+            "  {} team init|members file [name]   新建 team vault(自己为成员 name); 列出成员",
+            &exe_name
+        );
+        println!(
+            "  {} team add|rm file name [pubkey]   添加成员; 删除成员并更换数据密钥",
+            &exe_name
+        );
+        println!(
+            "  {} team push|pull file [pattern] --tag t   当前 vault 的条目合并到 team vault; 导入当前 vault, 冲突处理同 -l",
+            &exe_name
+        );
+        println!(
+            "  {} merge file [base]   三方合并另一份数据文件, base 为共同的上一版本(如 git 中的合并基础); 冲突缺省逐个询问",
+            &exe_name
+        );
+        println!(
+            "  {} doctor [check|repair] --yes   检查数据文件; 从备份或残留的数据恢复, --yes 跳过确认",
+            &exe_name
+        );
+        println!("  {} gen              生成密码", &exe_name);
+        println!("  {} policy [list]    列出密码策略", &exe_name);
+        println!(
+            "  {} policy set|rm name   设置或删除策略, name 为标签, default 为全局缺省",
+            &exe_name
+        );
+        println!("选项: ");
+        println!("  --vault name             使用指定的 vault");
+        println!("  --format plain|json|tsv  输出格式, 作用于 -p -g -r ls 与明文 -e");
+        println!("  --port n --alias a --tag t   -c 时设置主机端口、别名、标签");
+        println!("  --jump user@ip           -c 时设置跳板机, 需要是已保存的条目, none 为清除");
+        println!("  --escalate sudo:u|su:u   -c 时设置提权: 以已保存的用户 u 登录后 sudo 或 su 到该用户, none 为清除");
+        println!("  --auth password|key      -c 时设置认证方式, ls 中显示, none 为清除");
+        println!("  --record off|on|input    -c 时设置主机的会话录像, none 为使用全局设置");
+        println!(
+            "  --tag t --user u --sort ip|user|last --show-password   ls 过滤、排序; ls 与 history 显示密码"
+        );
+        println!("  --from plain|csv|json|ssh|ini|yaml   -l 导入格式, 默认按文件名推断");
+        println!("  --map 列名=字段 --dry-run             csv 列映射; 只显示修改不保存");
+        println!("  --overwrite|--skip-existing|--keep-newer|--interactive   -l、merge 冲突时使用对方、保留本地、保留较新、逐条确认");
+        println!(
+            "  --host pattern --tag t --plaintext --yes   -e 按主机、标签过滤; 明文导出; 跳过确认"
+        );
+        println!("  --cmd 'cmd'              rotate 远程修改命令, 可用 {{user}} {{old}} {{new}}, 密码经标准输入传递");
+        println!("                           非 root 缺省用 passwd, 只从终端读密码的系统需另外指定,");
+        println!("                           如 RHEL: --cmd \"printf '%s\\n' {{new}} | passwd --stdin\"");
+        println!("  --length n --classes lower,upper,digit,symbol --no-ambiguous   生成字符密码");
+        println!("  --words n --separator s --wordlist file   生成口令短语");
+        println!("  --policy name --count n  使用指定策略; gen 生成个数");
+        println!("  --max-age 90d            -c 设置主机、policy set 设置标签的密码有效期");
+        println!("  --expired --expiring 14d   ls、rotate 只选已过期或 n 天内过期的条目");
+        println!("  --stale --jobs n --mark  ls、rotate 只选 stale 条目; check 并发数, 标记认证失败的条目");
     }
 }
 
+// 取长选项的值: --name=value 或下一个参数
+fn opt_value<I: Iterator<Item = String>>(value: Option<String>, args: &mut I) -> Option<String> {
+    match value {
+        Some(v) => Some(v),
+        None => args.next(),
+    }
+}
+
+// 逗号分隔的列表
+fn split_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
 
+// 天数, 支持 90、90d、12w 写法
+fn parse_days(s: &str) -> Option<u64> {
+    if let Some(w) = s.strip_suffix('w') {
+        return w.parse::<u64>().ok().map(|w| w * 7);
+    }
+    s.trim_end_matches('d').parse().ok()
+}
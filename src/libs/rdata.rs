@@ -1,12 +1,25 @@
+use super::backup;
+use super::gen;
+use super::hostkey;
+use super::import;
+use super::my_crypto;
 use super::my_crypto::my_crypto;
+use super::output;
 use super::params;
+use super::record;
 use super::result;
+use super::secret;
+use super::secret::Secret;
+use super::unlock;
+use super::utils;
 
 use rustc_serialize::json;
 
-use std::fs::File;
+use std::fs::OpenOptions;
 use std::io::prelude::*;
-use std::io::{BufRead, BufReader};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
 
 use crate::libs::result::MyErr;
 use libc::c_char;
@@ -21,81 +34,339 @@ struct CRe {
     re_value: *mut c_char,
 }
 
+// 文件尾 FILE_TAIL 的大小: 加密信息的偏移与大小
+pub const TAIL_SIZE: u64 = 2 * std::mem::size_of::<c_ulong>() as u64;
+
 #[link(name = "fillfile", kind = "static")]
 extern "C" {
-    // 由于正在运行的可执行文件不可写, 所有写入操作都会生成到 ${file_name}.bak 文件, 需要手动替换
+    // 由于正在运行的可执行文件不可写, 所有写入操作都会生成到 out_file, 需要手动替换
     // 初始化文件, 填充指定大小个 0, 添加 file_tail
-    fn init_file(file_name: *const c_char, data: *const c_char, size: c_ulong, pos: c_int)
-        -> c_int;
+    fn init_file(
+        file_name: *const c_char,
+        out_file: *const c_char,
+        data: *const c_char,
+        size: c_ulong,
+        pos: c_int,
+    ) -> c_int;
 
     // 读取 json -> aes -> base64
     fn read_info(file_name: *const c_char) -> CRe;
 
     // 写入 json -> aes -> base64
-    fn write_info(file_name: *const c_char, c_json: *mut c_char) -> c_int;
+    fn write_info(file_name: *const c_char, out_file: *const c_char, c_json: *mut c_char) -> c_int;
+}
+
+// 本进程读取数据文件时的原始数据, 保存前与文件中的比较, 不覆盖其他进程的修改
+static LOADED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+// 本进程已加锁的数据文件, 同一进程内重复加锁时直接返回
+static LOCKED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/* 数据文件的写锁, 释放时解锁
+ * 数据文件保存时会被整体替换, 锁加在同级的 ${file_name}.lock 上 */
+pub struct FileLock {
+    name: String,
+    file: Option<std::fs::File>,
 }
 
-/* 读取 json 字符串 */
-fn read_json(file_name: &str) -> Result<String, result::MyErr> {
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // 关闭文件时释放 flock
+        if self.file.take().is_some() {
+            let mut locked = LOCKED.lock().unwrap_or_else(|e| e.into_inner());
+            locked.retain(|n| n != &self.name);
+        }
+    }
+}
+
+/* 对数据文件加写锁, 其他进程持有锁时等待 */
+pub fn lock(file_name: &str) -> Result<FileLock, MyErr> {
+    let held = LOCKED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .any(|n| n == file_name);
+    if held {
+        return Ok(FileLock {
+            name: file_name.to_string(),
+            file: None,
+        });
+    }
+    let err = || MyErr {
+        msg: format!("锁定数据文件 {} 失败", file_name),
+    };
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .mode(0o600)
+        .open(format!("{}.lock", file_name))
+        .map_err(|_| err())?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(err());
+    }
+    LOCKED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(file_name.to_string());
+    Ok(FileLock {
+        name: file_name.to_string(),
+        file: Some(file),
+    })
+}
+
+// 本进程读取时的原始数据
+fn loaded(file_name: &str) -> Option<String> {
+    let loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    loaded
+        .iter()
+        .find(|(f, _)| f == file_name)
+        .map(|(_, raw)| raw.clone())
+}
+
+fn set_loaded(file_name: &str, raw: &str) {
+    let mut loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    loaded.retain(|(f, _)| f != file_name);
+    loaded.push((file_name.to_string(), raw.to_string()));
+}
+
+/* 读取加密后的原始数据 */
+fn read_raw(file_name: &str) -> String {
+    let file = match CString::new(file_name) {
+        Ok(f) => f,
+        Err(_) => return String::new(),
+    };
     let (c_size, c_buff) = unsafe {
-        let file = CString::new(file_name).unwrap();
         let c_ptr = read_info(file.as_ptr() as *const i8);
 
         let c_buf = CStr::from_ptr(c_ptr.re_value as *const i8);
         (c_ptr.re_size, c_buf)
     };
-    let hash_info = match c_size {
+    match c_size {
         0 => String::from(""),
         _ => c_buff.to_string_lossy().into_owned(),
-    };
+    }
+}
 
-    my_crypto(&hash_info, false)
+// 备份文件中现有的数据, 用执行密码 pwd 加密; 无法解密时原样保存
+fn backup_file(file_name: &str, pwd: &str, keep: usize, days: u64) -> Result<(), result::MyErr> {
+    let raw = read_raw(file_name);
+    if raw.is_empty() {
+        return Ok(());
+    }
+    match my_crypto(&raw, false) {
+        Ok(j) => backup::snapshot(file_name, &Secret::new(j), pwd, keep, days),
+        Err(_) => backup::snapshot_raw(file_name, &raw, keep, days),
+    }
 }
 
-/* 写入 json 字符串 */
-fn write_json(file_name: &str, content: &str) -> Result<(), result::MyErr> {
-    let info = match my_crypto(content, true) {
-        Ok(i) => i,
-        Err(e) => return Err(e),
-    };
-    let re = unsafe {
-        let file = CString::new(file_name).unwrap();
-        let con = CString::new(info).unwrap();
-        write_info(file.as_ptr() as *const i8, con.as_ptr() as *mut i8)
-    };
+/* 写入 json 字符串
+ * checked 为 true 时, 文件在本进程读取之后被其他进程修改过则不写入, 避免覆盖对方的修改 */
+fn write_json(file_name: &str, content: &str, checked: bool) -> Result<(), result::MyErr> {
+    let info = my_crypto(content, true)?;
+    let _lock = lock(file_name)?;
+    let current = read_raw(file_name);
+    let base = loaded(file_name);
+    if checked && base.as_ref().is_some_and(|b| b != &current) {
+        return Err(result::MyErr {
+            msg: format!("数据文件 {} 已被其他 rssh 进程修改, 请重新执行", file_name),
+        });
+    }
 
-    return match re {
-        0 => {
-            // 成功则替换旧文件
-            let new_file = format!("{}.bak", file_name);
-            std::fs::rename(new_file, file_name).expect("替换旧文件失败");
-            Ok(())
-        }
+    let (file, con) = match (CString::new(file_name), CString::new(info.clone())) {
+        (Ok(f), Ok(c)) => (f, c),
         _ => {
-            // 失败则删除生成的 .bak
-            match std::fs::remove_file(format!("{}.bak", file_name)) {
-                Ok(()) => {}
-                Err(_) => {}
-            };
-            Err(result::MyErr {
+            return Err(result::MyErr {
                 msg: String::from("更新 rdata 失败!"),
             })
         }
     };
+    let tmp = utils::temp_path(file_name);
+    let re = match CString::new(tmp.clone()) {
+        Ok(out) => unsafe { write_info(file.as_ptr(), out.as_ptr(), con.as_ptr() as *mut c_char) },
+        Err(_) => 1,
+    };
+    if re != 0 {
+        // 失败则删除生成的临时文件
+        let _ = std::fs::remove_file(&tmp);
+        return Err(result::MyErr {
+            msg: String::from("更新 rdata 失败!"),
+        });
+    }
+    replace(&tmp, file_name)?;
+    // 本进程的数据是最新的才更新; 否则之后用旧数据保存时仍会被拒绝
+    if base.is_none() || base.as_ref() == Some(&current) {
+        set_loaded(file_name, &info);
+    }
+    Ok(())
+}
+
+/* 用写好的临时文件替换数据文件, 保留原文件的权限, 并同步所在目录 */
+fn replace(tmp: &str, file_name: &str) -> Result<(), result::MyErr> {
+    if let Ok(m) = std::fs::metadata(file_name) {
+        let _ = std::fs::set_permissions(tmp, m.permissions());
+    }
+    if std::fs::rename(tmp, file_name).is_err() {
+        let _ = std::fs::remove_file(tmp);
+        return Err(result::MyErr {
+            msg: format!("替换数据文件 {} 失败", file_name),
+        });
+    }
+    // 改名后同步目录, 断电后不会丢失
+    let dir = match std::path::Path::new(file_name).parent() {
+        Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+        _ => std::path::PathBuf::from("."),
+    };
+    if let Ok(d) = std::fs::File::open(dir) {
+        let _ = d.sync_all();
+    }
+    Ok(())
+}
+
+/* 创建空的数据文件, 上级目录不存在时一并创建 */
+fn create_vault(file_name: &str) -> Result<(), result::MyErr> {
+    let err = || result::MyErr {
+        msg: format!("创建数据文件 {} 失败", file_name),
+    };
+    if let Some(dir) = std::path::Path::new(file_name).parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(|_| err())?;
+    }
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(file_name)
+        .map_err(|_| err())?;
+    Ok(())
 }
 
 // 用户 -> 密码
-#[derive(RustcDecodable, RustcEncodable, Debug)]
+// 新增字段均为 Option, 兼容旧版本写入的数据
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
 struct UserInfo {
+    id: Option<String>, // 条目的唯一标识, 合并时用于对应两份数据中的同一条目
     username: String,
     password: String,
+    auth: Option<String>,              // 认证方式, 缺省为 password
+    last_used: Option<u64>,            // 最近一次登录或执行命令的时间
+    created: Option<u64>,              // 条目创建时间
+    updated: Option<u64>,              // 密码最近修改时间
+    history: Option<Vec<OldPassword>>, // 历史密码, 最新的在前
+    checked: Option<u64>,              // 最近一次 check 的时间
+    stale: Option<bool>,               // check 认证失败, 密码可能已失效
+    escalate: Option<String>,          // 提权方式 sudo:登录用户 或 su:登录用户
+    modified: Option<u64>,             // 条目最近修改时间(密码、认证方式、提权)
+}
+
+// 历史密码
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+struct OldPassword {
+    password: String,
+    updated: Option<u64>, // 该密码的设置时间
+    replaced: u64,        // 被替换的时间
+}
+
+// 释放时清零密码
+impl Drop for UserInfo {
+    fn drop(&mut self) {
+        secret::wipe_string(&mut self.password);
+    }
+}
+
+impl Drop for OldPassword {
+    fn drop(&mut self) {
+        secret::wipe_string(&mut self.password);
+    }
+}
+
+// 每个用户保留的历史密码数
+const HISTORY_MAX: usize = 10;
+// 登录时提前提醒密码过期的天数
+const EXPIRE_WARN_DAYS: u64 = 7;
+// 最近使用时间的精度(秒), 间隔内再次使用不改写数据文件
+const TOUCH_INTERVAL: u64 = 60;
+
+impl UserInfo {
+    fn new(username: &str, password: &str) -> UserInfo {
+        UserInfo {
+            id: Some(new_id()),
+            username: username.to_string(),
+            password: password.to_string(),
+            auth: None,
+            last_used: None,
+            created: Some(utils::now()),
+            updated: Some(utils::now()),
+            history: None,
+            checked: None,
+            stale: None,
+            escalate: None,
+            modified: Some(utils::now()),
+        }
+    }
+
+    // 修改密码, 旧密码移入历史
+    fn change(&mut self, password: &str) {
+        if self.password == password {
+            return;
+        }
+        let now = utils::now();
+        let old = OldPassword {
+            password: std::mem::replace(&mut self.password, password.to_string()),
+            updated: self.updated,
+            replaced: now,
+        };
+        let history = self.history.get_or_insert_with(Vec::new);
+        history.insert(0, old);
+        history.truncate(HISTORY_MAX);
+        self.updated = Some(now);
+        self.modified = Some(now);
+        self.stale = None;
+    }
+
+    // 密码设置时间, 旧数据没有修改时间时用创建时间
+    fn changed(&self) -> u64 {
+        self.updated.or(self.created).unwrap_or(0)
+    }
+
+    // 条目修改时间, 旧数据没有记录时用密码设置时间
+    fn last_modified(&self) -> u64 {
+        self.modified.unwrap_or_else(|| self.changed())
+    }
+
+    // 合并时比较的内容, 不含最近使用、检查结果等状态
+    fn content(&self) -> String {
+        format!(
+            "{:?} {:?} {:?} {:?}",
+            self.username, self.password, self.auth, self.escalate
+        )
+    }
 }
 
 // IP -> [用户 -> 密码]
-#[derive(RustcDecodable, RustcEncodable, Debug)]
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
 struct HostInfo {
     ip: String,
     users: Vec<UserInfo>,
+    port: Option<u16>,             // ssh 端口, 缺省 22
+    alias: Option<Vec<String>>,    // 别名, 可代替 ip 使用
+    tags: Option<Vec<String>>,     // 标签(分组)
+    max_age: Option<u64>,          // 密码最长使用天数, 优先于标签策略
+    hostkeys: Option<Vec<String>>, // 信任的主机公钥 "类型 base64"
+    jump: Option<String>,          // 跳板机 user@ip, 需要是已保存的条目
+    record: Option<String>,        // 会话录像模式, 优先于全局设置
+    modified: Option<u64>,         // 主机信息最近修改时间
+}
+
+impl HostInfo {
+    // 合并时比较的主机信息
+    fn content(&self) -> String {
+        format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+            self.port, self.alias, self.tags, self.max_age, self.hostkeys, self.jump, self.record
+        )
+    }
 }
 
 // IP缩写 -> 序号
@@ -111,173 +382,1332 @@ pub struct Rdata {
     hosts: Vec<HostInfo>,
     pub pwd: String,
     pub version: u8,
+    backup_keep: Option<usize>, // 保留备份份数, 缺省 backup::DEFAULT_KEEP
+    backup_days: Option<u64>,   // 保留备份天数, 缺省 backup::DEFAULT_DAYS
+    policies: Option<Vec<gen::Policy>>, // 按标签的密码生成策略
+    record: Option<String>,     // 会话录像模式, 缺省 off
+    audit_count: Option<u64>,   // 审计日志的记录数
+    audit_head: Option<String>, // 审计日志最后一条的 hash
+    unlock: Option<Vec<unlock::Rule>>, // 按标签的解锁策略
 }
 
-impl Rdata {
-    /* 从文件读取 rdata */
-    pub fn get(file_name: &str) -> Result<Rdata, result::MyErr> {
-        let json_data = match read_json(&file_name) {
-            Ok(j) => j,
-            Err(e) => return Err(e),
-        };
-        let a: Rdata = match json::decode(&json_data) {
-            Ok(e) => e,
-            Err(_) => {
-                return Err(MyErr {
-                    msg: String::from("json解码出错"),
-                })
-            }
-        };
-        Ok(a)
+// 释放时清零执行密码, 各条目的密码由 UserInfo 清零
+impl Drop for Rdata {
+    fn drop(&mut self) {
+        secret::wipe_string(&mut self.pwd);
+    }
+}
+
+impl Rdata {
+    /* 从文件读取 rdata */
+    pub fn get(file_name: &str) -> Result<Rdata, result::MyErr> {
+        // 配置的数据文件可能还未创建, 没有文件尾时不能读取
+        match std::fs::metadata(file_name) {
+            Ok(m) if m.len() >= TAIL_SIZE => {}
+            _ => {
+                return Err(MyErr {
+                    msg: format!("数据文件 {} 不存在", file_name),
+                })
+            }
+        }
+        let raw = read_raw(file_name);
+        let json_data = Secret::new(my_crypto(&raw, false)?);
+        // 第一次读取时记录, 之后重新读取不改变, 见 write_json
+        if loaded(file_name).is_none() {
+            set_loaded(file_name, &raw);
+        }
+        Rdata::from_json(&json_data)
+    }
+
+    /* 解码已解密的 json 数据 */
+    pub fn from_json(json_data: &str) -> Result<Rdata, result::MyErr> {
+        let mut a: Rdata = match json::decode(json_data) {
+            Ok(e) => e,
+            Err(_) => {
+                return Err(MyErr {
+                    msg: String::from("json解码出错"),
+                })
+            }
+        };
+        // 旧数据的条目没有标识, 下次保存时写入
+        for u in a.hosts.iter_mut().flat_map(|h| h.users.iter_mut()) {
+            if u.id.is_none() {
+                u.id = Some(new_id());
+            }
+        }
+        Ok(a)
+    }
+
+    /* 空的数据 */
+    pub fn new(pwd: &str, version: u8) -> Rdata {
+        Rdata {
+            current: vec![],
+            hosts: vec![],
+            pwd: String::from(pwd),
+            version,
+            backup_keep: None,
+            backup_days: None,
+            policies: None,
+            record: None,
+            audit_count: None,
+            audit_head: None,
+            unlock: None,
+        }
+    }
+
+    /* 初始化文件 */
+    pub fn init(file_name: &str, pwd: &str, version: u8) -> Result<(), result::MyErr> {
+        let rdata = Rdata::new(pwd, version);
+
+        let _lock = lock(file_name)?;
+        let pos = match self::Rdata::get(&file_name) {
+            Ok(old) => {
+                // 重新初始化前备份已有数据, 用原来的密码加密
+                backup_file(
+                    file_name,
+                    &old.pwd,
+                    old.backup_keep.unwrap_or(backup::DEFAULT_KEEP),
+                    old.backup_days.unwrap_or(backup::DEFAULT_DAYS),
+                )?;
+                1
+            }
+            Err(_) => {
+                // 配置的数据文件不存在时创建空文件, 数据追加在其后
+                if std::fs::metadata(file_name).is_err() {
+                    create_vault(file_name)?;
+                }
+                0
+            }
+        };
+        let err = || result::MyErr {
+            msg: String::from("初始化文件失败!"),
+        };
+        let json_info = json::encode(&rdata).map(Secret::new).map_err(|_| err())?;
+        let hash_info = my_crypto(&json_info, true)?;
+
+        let tmp = utils::temp_path(file_name);
+        let re = match (
+            CString::new(file_name),
+            CString::new(tmp.clone()),
+            CString::new(hash_info.clone()),
+        ) {
+            (Ok(file), Ok(out), Ok(info)) => unsafe {
+                init_file(file.as_ptr(), out.as_ptr(), info.as_ptr(), 20, pos)
+            },
+            _ => 1,
+        };
+        if re != 0 {
+            // 失败则删除生成的临时文件
+            let _ = std::fs::remove_file(&tmp);
+            return Err(err());
+        }
+        replace(&tmp, file_name)?;
+        set_loaded(file_name, &hash_info);
+        Ok(())
+    }
+
+    /* 在写锁内读取最新的数据、修改并保存, 用于最近使用时间等与当前命令无关的状态
+     * 不会覆盖其他进程在本进程读取之后的修改 */
+    pub fn update<F>(file_name: &str, f: F) -> Result<(), result::MyErr>
+    where
+        F: FnOnce(&mut Rdata),
+    {
+        Rdata::update_if(file_name, |data| {
+            f(data);
+            true
+        })
+    }
+
+    /* 同 update, f 返回 false 时表示没有修改, 不保存 */
+    pub fn update_if<F>(file_name: &str, f: F) -> Result<(), result::MyErr>
+    where
+        F: FnOnce(&mut Rdata) -> bool,
+    {
+        let _lock = lock(file_name)?;
+        let mut data = Rdata::get(file_name)?;
+        if !f(&mut data) {
+            return Ok(());
+        }
+        data.write(file_name, false)
+    }
+
+    /* 从文件导入密码 */
+    pub fn import_pwd(self, file_name: &str, params: &params::Params) -> Result<(), result::MyErr> {
+        let (entries, errors) = import::parse(file_name, &params.import_from, &params.map)?;
+        for (line, msg) in &errors {
+            println!("第 {} 行: {}", line, msg);
+        }
+        self.merge(&entries, errors.len(), &params.file_path, params)
+    }
+
+    /* 合并条目并保存到 file_name, 密码冲突时按 params.merge 处理; errors 为来源中无法解析的条数 */
+    pub fn merge(
+        mut self,
+        entries: &[import::Entry],
+        errors: usize,
+        file_name: &str,
+        params: &params::Params,
+    ) -> Result<(), result::MyErr> {
+        let (mut added, mut updated, mut unchanged) = (0, 0, 0);
+        let mut conflicts: Vec<String> = Vec::new();
+        for e in entries {
+            let (i, j) = get_password(&self, &e.ip, &e.user);
+            let mut apply_pwd = true;
+            let action = if i == -1 || j == -1 {
+                added += 1;
+                "新增"
+            } else {
+                let local = &self.hosts[i as usize].users[j as usize];
+                let differs = e.password.as_ref().is_some_and(|p| p != &local.password);
+                if differs {
+                    // 密码不同即为冲突, 按合并策略决定是否覆盖
+                    apply_pwd = match params.merge.as_str() {
+                        "skip" => false,
+                        "newer" => e.updated.unwrap_or(0) > local.updated.unwrap_or(0),
+                        // dry-run 时同样询问, 显示的结果即为实际导入时的选择
+                        "interactive" => ask_overwrite(e, local),
+                        _ => true,
+                    };
+                    conflicts.push(format!(
+                        "冲突 {} {}: 本地修改于 {}, 导入修改于 {}, {}",
+                        &e.ip,
+                        &e.user,
+                        utils::fmt_time(local.updated.unwrap_or(0)),
+                        utils::fmt_time(e.updated.unwrap_or(0)),
+                        if apply_pwd { "覆盖" } else { "保留本地" }
+                    ));
+                }
+                if (differs && apply_pwd)
+                    || host_info_differs(&self.hosts[i as usize], e.port, &e.alias, &e.tags)
+                {
+                    updated += 1;
+                    "更新"
+                } else {
+                    unchanged += 1;
+                    "不变"
+                }
+            };
+            if params.dry_run {
+                println!("{} {} {}", action, &e.ip, &e.user);
+            }
+
+            match &e.password {
+                Some(p) if apply_pwd => {
+                    set_password(&mut self, &e.ip, &e.user, p);
+                    // 保留来源的修改时间, 便于之后按新旧合并
+                    if let Some(t) = e.updated {
+                        set_updated(&mut self, &e.ip, &e.user, t);
+                    }
+                }
+                Some(_) => {}
+                // 没有密码的记录不覆盖已有密码
+                None if j == -1 => set_password(&mut self, &e.ip, &e.user, ""),
+                None => {}
+            }
+            set_host_info(&mut self, &e.ip, e.port, &e.alias, &e.tags);
+        }
+
+        for c in &conflicts {
+            println!("{}", c);
+        }
+        println!(
+            "新增 {}, 更新 {}, 不变 {}, 冲突 {}, 错误 {}",
+            added,
+            updated,
+            unchanged,
+            conflicts.len(),
+            errors
+        );
+        if params.dry_run {
+            println!("dry-run: 未保存");
+            return Ok(());
+        }
+        self.save(file_name)
+    }
+
+    /* 三方合并另一份数据文件并保存到 file_name, base 为双方共同的上一版本
+     * 只有一方修改的取修改的一方, 双方修改不同时为冲突, 按 params.merge 处理
+     * 没有 base 时相当于共同版本为空: 合并新增与修改, 不传播删除 */
+    pub fn merge_vault(
+        mut self,
+        other: &Rdata,
+        base: Option<&Rdata>,
+        file_name: &str,
+        params: &params::Params,
+    ) -> Result<(), result::MyErr> {
+        let m = self.merge3(other, base, &params.merge, params.dry_run);
+        if params.dry_run {
+            for c in &m.changes {
+                println!("{}", c);
+            }
+        }
+        for c in &m.conflicts {
+            println!("{}", c);
+        }
+        println!(
+            "新增 {}, 更新 {}, 删除 {}, 冲突 {}",
+            m.added,
+            m.updated,
+            m.deleted,
+            m.conflicts.len()
+        );
+        if params.dry_run {
+            println!("dry-run: 未保存");
+            return Ok(());
+        }
+        self.save(file_name)
+    }
+
+    // 三方合并到本地数据, strategy 与 dry_run 用于处理冲突
+    fn merge3(
+        &mut self,
+        other: &Rdata,
+        base: Option<&Rdata>,
+        strategy: &str,
+        dry_run: bool,
+    ) -> Merged {
+        let (mut added, mut updated, mut deleted) = (0, 0, 0);
+        let mut changes: Vec<String> = Vec::new();
+        let mut conflicts: Vec<String> = Vec::new();
+
+        // 主机信息, 对方没有的主机在删除其用户后处理
+        for t in &other.hosts {
+            let i = self.hosts.iter().position(|h| h.ip == t.ip);
+            let o = i.map(|i| &self.hosts[i]);
+            let b = base.and_then(|b| b.hosts.iter().find(|h| h.ip == t.ip));
+            let take = match pick(
+                o.map(|h| h.content()),
+                Some(t.content()),
+                b.map(|h| h.content()),
+            ) {
+                Pick::Ours => false,
+                Pick::Theirs => true,
+                Pick::Conflict => resolve(
+                    strategy,
+                    dry_run,
+                    &format!("主机 {}", &t.ip),
+                    o.map(|h| h.modified.unwrap_or(0)),
+                    Some(t.modified.unwrap_or(0)),
+                    &mut conflicts,
+                ),
+            };
+            if !take {
+                continue;
+            }
+            match i {
+                Some(i) => {
+                    let users = std::mem::take(&mut self.hosts[i].users);
+                    self.hosts[i] = HostInfo { users, ..t.clone() };
+                    updated += 1;
+                    changes.push(format!("更新 主机 {}", &t.ip));
+                }
+                // 新主机, 用户在下面加入
+                None => self.hosts.push(HostInfo {
+                    users: vec![],
+                    ..t.clone()
+                }),
+            }
+        }
+
+        // 用户条目: 先按标识对应, 没有标识的旧数据按 ip 与用户名
+        let mut matched: Vec<Option<String>> = Vec::new();
+        let mut puts: Vec<Put> = Vec::new();
+        for th in &other.hosts {
+            for tu in &th.users {
+                let o = find_user(self, &th.ip, tu);
+                let ou = o.map(|(i, j)| &self.hosts[i].users[j]);
+                let bu =
+                    base.and_then(|b| find_user(b, &th.ip, tu).map(|(i, j)| &b.hosts[i].users[j]));
+                if let Some(u) = ou {
+                    matched.push(u.id.clone());
+                }
+                let take = match pick(
+                    ou.map(|u| u.content()),
+                    Some(tu.content()),
+                    bu.map(|u| u.content()),
+                ) {
+                    Pick::Ours => false,
+                    Pick::Theirs => true,
+                    Pick::Conflict => resolve(
+                        strategy,
+                        dry_run,
+                        &format!("{} {}", &th.ip, &tu.username),
+                        ou.map(|u| u.last_modified()),
+                        Some(tu.last_modified()),
+                        &mut conflicts,
+                    ),
+                };
+                if take {
+                    puts.push((th.ip.clone(), o, tu.clone()));
+                }
+            }
+        }
+        let mut removes: Vec<Option<String>> = Vec::new();
+        for h in &self.hosts {
+            for u in h.users.iter().filter(|u| !matched.contains(&u.id)) {
+                let bu =
+                    base.and_then(|b| find_user(b, &h.ip, u).map(|(i, j)| &b.hosts[i].users[j]));
+                let take = match pick(Some(u.content()), None, bu.map(|u| u.content())) {
+                    Pick::Ours => false,
+                    Pick::Theirs => true,
+                    Pick::Conflict => resolve(
+                        strategy,
+                        dry_run,
+                        &format!("{} {}", &h.ip, &u.username),
+                        Some(u.last_modified()),
+                        None,
+                        &mut conflicts,
+                    ),
+                };
+                if take {
+                    removes.push(u.id.clone());
+                    deleted += 1;
+                    changes.push(format!("删除 {} {}", &h.ip, &u.username));
+                }
+            }
+        }
+
+        for (ip, o, mut u) in puts {
+            match o {
+                Some((i, j)) => {
+                    // 最近使用时间是本地状态, 保留较新的
+                    u.last_used = u.last_used.max(self.hosts[i].users[j].last_used);
+                    self.hosts[i].users[j] = u;
+                    updated += 1;
+                    changes.push(format!("更新 {} {}", &ip, &self.hosts[i].users[j].username));
+                }
+                None => {
+                    if !self.hosts.iter().any(|h| h.ip == ip) {
+                        // 本地已删除该主机, 对方在其中新增了用户
+                        if let Some(t) = other.hosts.iter().find(|h| h.ip == ip) {
+                            self.hosts.push(HostInfo {
+                                users: vec![],
+                                ..t.clone()
+                            });
+                        }
+                    }
+                    added += 1;
+                    changes.push(format!("新增 {} {}", &ip, &u.username));
+                    if let Some(h) = self.hosts.iter_mut().find(|h| h.ip == ip) {
+                        h.users.push(u);
+                    }
+                }
+            }
+        }
+        for h in self.hosts.iter_mut() {
+            h.users.retain(|u| !removes.contains(&u.id));
+        }
+        // 没有用户的主机不保留, 与删除最后一个用户时一致
+        self.hosts.retain(|h| !h.users.is_empty());
+
+        Merged {
+            added,
+            updated,
+            deleted,
+            changes,
+            conflicts,
+        }
+    }
+
+    /* 按 ip 或别名、标签、用户过滤出的条目, 用于复制到其他 vault */
+    pub fn entries(&self, params: &params::Params) -> Vec<import::Entry> {
+        self.matches(params)
+            .into_iter()
+            .map(|(h, u)| import::Entry {
+                line: 0,
+                ip: h.ip.clone(),
+                user: u.username.clone(),
+                password: Some(u.password.clone()),
+                port: h.port.unwrap_or(0),
+                alias: h.alias.clone().unwrap_or_default(),
+                tags: h.tags.clone().unwrap_or_default(),
+                updated: u.updated,
+            })
+            .collect()
+    }
+
+    /* 主机数与条目数 */
+    pub fn count(&self) -> (usize, usize) {
+        let users = self.hosts.iter().map(|h| h.users.len()).sum();
+        (self.hosts.len(), users)
+    }
+
+    /* 导出密码, 默认用口令加密, --plaintext 时确认后明文导出 */
+    pub fn export_pwd(
+        &self,
+        file_name: &str,
+        params: &params::Params,
+    ) -> Result<(), result::MyErr> {
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        for host in &self.hosts {
+            if !params.filter_host.is_empty() && !host_match(host, &params.filter_host) {
+                continue;
+            }
+            if !params.tags.is_empty() && !params.tags.iter().all(|t| has_tag(host, t)) {
+                continue;
+            }
+            for user in &host.users {
+                let mut row = vec![
+                    host.ip.clone(),
+                    user.username.clone(),
+                    user.password.clone(),
+                ];
+                // plain 格式保持 ip user pwd, 其他格式带上修改时间与主机信息供导入使用
+                if !params.plaintext || &params.format != "plain" {
+                    row.push(user.updated.unwrap_or(0).to_string());
+                    row.push(host.port.map(|p| p.to_string()).unwrap_or_default());
+                    row.push(host.alias.as_ref().map(|a| a.join(",")).unwrap_or_default());
+                    row.push(host.tags.as_ref().map(|t| t.join(",")).unwrap_or_default());
+                }
+                rows.push(row);
+            }
+        }
+        // 明文密码用完即清零
+        let rows = secret::rows(rows);
+        let header = ["ip", "user", "password", "updated", "port", "alias", "tags"];
+
+        let content = if params.plaintext {
+            if !params.yes
+                && !utils::confirm(&format!(
+                    "将以明文写入 {} 条密码到 {}, 确认? [y/N]: ",
+                    rows.len(),
+                    file_name
+                ))
+            {
+                return Err(result::MyErr {
+                    msg: String::from("已取消"),
+                });
+            }
+            Secret::new(output::render(&params.format, &header, &rows))
+        } else {
+            let pass = utils::read_passphrase("请设置导出口令: ", true)?;
+            let plain = Secret::new(output::render("json", &header, &rows));
+            Secret::new(my_crypto::encrypt_bundle(&plain, &pass)?)
+        };
+
+        // 只有当前用户可读写
+        let mut f = match OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(file_name)
+        {
+            Ok(f) => f,
+            Err(_e) => {
+                return Err(result::MyErr {
+                    msg: format!("创建文件 {} 失败", &file_name),
+                })
+            }
+        };
+        let _ = f.set_permissions(std::fs::Permissions::from_mode(0o600));
+        match f.write_all(content.as_bytes()) {
+            Ok(()) => {
+                println!("已导出 {} 条", rows.len());
+                Ok(())
+            }
+            Err(_) => Err(result::MyErr {
+                msg: format!("写入文件 {} 失败", &file_name),
+            }),
+        }
+    }
+
+    /* 保存到文件, 保存前备份当前数据 */
+    pub fn save(&self, file_name: &str) -> Result<(), result::MyErr> {
+        backup_file(
+            file_name,
+            &self.pwd,
+            self.backup_keep.unwrap_or(backup::DEFAULT_KEEP),
+            self.backup_days.unwrap_or(backup::DEFAULT_DAYS),
+        )?;
+        self.save_usage(file_name)
+    }
+
+    /* 保存到文件, 不备份; 用于最近使用时间等非密码数据的更新 */
+    pub fn save_usage(&self, file_name: &str) -> Result<(), result::MyErr> {
+        self.write(file_name, true)
+    }
+
+    fn write(&self, file_name: &str, checked: bool) -> Result<(), result::MyErr> {
+        // 明文的 json 用完即清零
+        let json_data = match json::encode(&self) {
+            Ok(j) => Secret::new(j),
+            Err(_) => {
+                return Err(MyErr {
+                    msg: String::from("json编码出错"),
+                })
+            }
+        };
+        write_json(file_name, &json_data, checked)
+    }
+
+    /* 重写损坏的数据文件: prefix 为加密数据之前的内容(可执行文件及填充), 之后写入数据与文件尾 */
+    pub fn rebuild(&self, file_name: &str, prefix: &[u8]) -> Result<(), result::MyErr> {
+        let err = || result::MyErr {
+            msg: format!("重写数据文件 {} 失败", file_name),
+        };
+        let json_data = json::encode(&self).map(Secret::new).map_err(|_| err())?;
+        let info = my_crypto(&json_data, true)?;
+
+        let _lock = lock(file_name)?;
+        let mut content = prefix.to_vec();
+        content.extend_from_slice(info.as_bytes());
+        // FILE_TAIL: 偏移与大小
+        content.extend_from_slice(&(prefix.len() as c_ulong).to_ne_bytes());
+        content.extend_from_slice(&(info.len() as c_ulong).to_ne_bytes());
+
+        let tmp = utils::temp_path(file_name);
+        let written = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp)
+            .and_then(|mut f| f.write_all(&content).and_then(|_| f.sync_all()));
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(err());
+        }
+        replace(&tmp, file_name)?;
+        set_loaded(file_name, &info);
+        Ok(())
+    }
+
+    /* 检查数据内容: 重复的主机、用户和标识, 空的用户列表等 */
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut ips: Vec<&str> = Vec::new();
+        let mut ids: Vec<&str> = Vec::new();
+        for host in &self.hosts {
+            if host.ip.is_empty() {
+                problems.push(String::from("存在 ip 为空的主机"));
+            } else if ips.contains(&host.ip.as_str()) {
+                problems.push(format!("主机 {} 重复", host.ip));
+            } else {
+                ips.push(&host.ip);
+            }
+            if host.users.is_empty() {
+                problems.push(format!("主机 {} 没有用户", host.ip));
+            }
+            let mut names: Vec<&str> = Vec::new();
+            for u in &host.users {
+                if u.username.is_empty() {
+                    problems.push(format!("主机 {} 存在用户名为空的条目", host.ip));
+                } else if names.contains(&u.username.as_str()) {
+                    problems.push(format!("主机 {} 的用户 {} 重复", host.ip, u.username));
+                } else {
+                    names.push(&u.username);
+                }
+                if let Some(id) = &u.id {
+                    if ids.contains(&id.as_str()) {
+                        problems.push(format!("条目标识 {} 重复", id));
+                    } else {
+                        ids.push(id);
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    /* 修复 check 发现的问题, 返回修复的数量
+     * 重复的主机合并到第一个, 重复的用户保留最近修改的 */
+    pub fn fix(&mut self) -> usize {
+        let before = self.check().len();
+        let mut hosts: Vec<HostInfo> = Vec::new();
+        for host in std::mem::take(&mut self.hosts) {
+            if host.ip.is_empty() {
+                continue;
+            }
+            match hosts.iter_mut().find(|h| h.ip == host.ip) {
+                Some(h) => h.users.extend(host.users),
+                None => hosts.push(host),
+            }
+        }
+        let mut ids: Vec<String> = Vec::new();
+        for host in hosts.iter_mut() {
+            let mut users: Vec<UserInfo> = Vec::new();
+            for u in std::mem::take(&mut host.users) {
+                if u.username.is_empty() {
+                    continue;
+                }
+                match users.iter_mut().find(|x| x.username == u.username) {
+                    Some(x) if x.last_modified() < u.last_modified() => *x = u,
+                    Some(_) => {}
+                    None => users.push(u),
+                }
+            }
+            for u in users.iter_mut() {
+                let id = match &u.id {
+                    Some(id) if !ids.contains(id) => id.clone(),
+                    _ => new_id(),
+                };
+                u.id = Some(id.clone());
+                ids.push(id);
+            }
+            host.users = users;
+        }
+        hosts.retain(|h| !h.users.is_empty());
+        self.hosts = hosts;
+        self.current.retain(|c| !c.ip.is_empty());
+        before
+    }
+
+    /* 从被截断的 json 中找回完整的主机, 已有的主机被替换; 返回找回的主机数 */
+    pub fn salvage(&mut self, partial: &str) -> usize {
+        let start = match partial.find("\"hosts\":[") {
+            Some(p) => p + "\"hosts\":[".len(),
+            None => return 0,
+        };
+        let mut count = 0;
+        for obj in json_objects(&partial[start..]) {
+            let host: HostInfo = match json::decode(obj) {
+                Ok(h) => h,
+                Err(_) => continue,
+            };
+            match self.hosts.iter_mut().find(|h| h.ip == host.ip) {
+                Some(h) => *h = host,
+                None => self.hosts.push(host),
+            }
+            count += 1;
+        }
+        for u in self.hosts.iter_mut().flat_map(|h| h.users.iter_mut()) {
+            if u.id.is_none() {
+                u.id = Some(new_id());
+            }
+        }
+        count
+    }
+
+    /* 旧版本记录在数据文件中的审计日志锚点 (记录数, 最后的 hash), 现在记录在锚点文件中 */
+    pub fn audit_anchor(&self) -> Option<(u64, String)> {
+        match (self.audit_count, &self.audit_head) {
+            (Some(c), Some(h)) => Some((c, h.clone())),
+            _ => None,
+        }
+    }
+
+    /* 列出备份: id, 时间, 条目数 */
+    pub fn list_backup(&self, file_name: &str) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        for s in backup::list(file_name) {
+            // 用当前密码能解密的备份显示条目数; 修改密码之前的备份或损坏的备份无法解密
+            let count = match backup::read(file_name, &s.id)
+                .and_then(|raw| backup::decrypt(&raw, &self.pwd))
+                .map(|j| json::decode::<Rdata>(&Secret::new(j)))
+            {
+                Ok(Ok(r)) => r
+                    .hosts
+                    .iter()
+                    .map(|h| h.users.len())
+                    .sum::<usize>()
+                    .to_string(),
+                _ => String::from("无法解密"),
+            };
+            rows.push(vec![
+                s.id,
+                utils::fmt_time(s.time),
+                count,
+                s.size.to_string(),
+            ]);
+        }
+        rows
+    }
+
+    /* 设置备份保留份数与天数, 0 表示不限制 */
+    pub fn set_backup(&mut self, file_name: &str, keep: usize, days: u64) -> Result<(), MyErr> {
+        self.backup_keep = Some(keep);
+        self.backup_days = Some(days);
+        self.save(file_name)
+    }
+
+    /* 从备份恢复, 恢复前的数据同样会被备份
+     * 执行密码、解锁策略与审计状态保持当前的, 不随备份回退 */
+    pub fn restore(&self, file_name: &str, id: &str) -> Result<(), result::MyErr> {
+        let raw = backup::read(file_name, id)?;
+        let json_data = match backup::decrypt(&raw, &self.pwd) {
+            Ok(j) => Secret::new(j),
+            // 备份之后修改过密码
+            Err(_) if backup::encrypted(&raw) => {
+                let pass = utils::read_passphrase("备份时的密码: ", false)?;
+                Secret::new(backup::decrypt(&raw, &pass)?)
+            }
+            Err(e) => return Err(e),
+        };
+        let mut old: Rdata = match json::decode(&json_data) {
+            Ok(r) => r,
+            Err(_) => {
+                return Err(MyErr {
+                    msg: format!("备份 {} 已损坏", id),
+                })
+            }
+        };
+        old.pwd.clone_from(&self.pwd);
+        old.version = self.version;
+        old.unlock.clone_from(&self.unlock);
+        old.audit_count = self.audit_count;
+        old.audit_head.clone_from(&self.audit_head);
+        old.save(file_name)
+    }
+
+    /* 删除密码 */
+    pub fn delete_pwd(&mut self, params: &params::Params) -> Result<(), result::MyErr> {
+        let (x, y) = get_password(&self, &params.ip, &params.user);
+
+        if x != -1 && y != -1 {
+            match &self.hosts[x as usize].users.len() {
+                1 => {
+                    &self.hosts.remove(x as usize);
+                }
+                _ => {
+                    &self.hosts[x as usize].users.remove(y as usize);
+                }
+            }
+        }
+        //save_to_file(&params.file_path, &rdata)
+        self.save(&params.file_path)
+    }
+
+    /* 列出指定 ip 所有用户 */
+    pub fn get_user(&self, ip: &str) -> Vec<String> {
+        let mut users = Vec::new();
+        for host in &self.hosts {
+            if &host.ip == ip {
+                for u in &host.users {
+                    users.push(u.username.clone());
+                }
+            }
+        }
+        users
+    }
+
+    /* 列出所有条目, 按 pattern(ip/别名/标签) 与选项过滤排序 */
+    pub fn list(&self, params: &params::Params) -> Vec<Vec<Secret>> {
+        let mut items = self.matches(params);
+
+        match params.sort.as_str() {
+            "user" => items.sort_by(|a, b| (&a.1.username, &a.0.ip).cmp(&(&b.1.username, &b.0.ip))),
+            // 最近使用的排在前面
+            "last" => items.sort_by_key(|x| std::cmp::Reverse(x.1.last_used)),
+            _ => items.sort_by(|a, b| (&a.0.ip, &a.1.username).cmp(&(&b.0.ip, &b.1.username))),
+        }
+
+        items
+            .iter()
+            .map(|(h, u)| {
+                let mut row = vec![
+                    h.ip.clone(),
+                    h.port.unwrap_or(22).to_string(),
+                    h.alias.as_ref().map(|a| a.join(",")).unwrap_or_default(),
+                    h.tags.as_ref().map(|t| t.join(",")).unwrap_or_default(),
+                    h.jump.clone().unwrap_or_default(),
+                    u.username.clone(),
+                    u.auth.clone().unwrap_or_else(|| String::from("password")),
+                    utils::fmt_time(u.last_used.unwrap_or(0)),
+                    utils::fmt_time(u.changed()),
+                    if u.stale.unwrap_or(false) {
+                        String::from("yes")
+                    } else {
+                        String::new()
+                    },
+                    match self.expires(h, u) {
+                        Some(t) => utils::fmt_time(t.max(1)),
+                        None => String::from("-"),
+                    },
+                ];
+                if params.show_password {
+                    row.push(u.password.clone());
+                }
+                row.into_iter().map(Secret::new).collect()
+            })
+            .collect()
+    }
+
+    /* 历史密码: 序号, 设置时间, 替换时间, [密码] */
+    pub fn history(&self, ip: &str, user: &str, show_password: bool) -> Vec<Vec<Secret>> {
+        let (i, j) = get_password(self, ip, user);
+        if i == -1 || j == -1 {
+            return vec![];
+        }
+        let u = &self.hosts[i as usize].users[j as usize];
+        let mut rows = Vec::new();
+        for (n, h) in u.history.iter().flatten().enumerate() {
+            let mut row = vec![
+                (n + 1).to_string(),
+                utils::fmt_time(h.updated.unwrap_or(0)),
+                utils::fmt_time(h.replaced),
+            ];
+            if show_password {
+                row.push(h.password.clone());
+            }
+            rows.push(row);
+        }
+        secret::rows(rows)
+    }
+
+    /* 恢复第 n 个历史密码, 当前密码移入历史 */
+    pub fn restore_history(
+        &mut self,
+        file_name: &str,
+        ip: &str,
+        user: &str,
+        n: usize,
+    ) -> Result<(), MyErr> {
+        let (i, j) = get_password(self, ip, user);
+        if i == -1 || j == -1 {
+            return Err(MyErr {
+                msg: String::from("not found"),
+            });
+        }
+        let u = &mut self.hosts[i as usize].users[j as usize];
+        let old = match u.history.as_ref().and_then(|h| h.get(n.wrapping_sub(1))) {
+            Some(h) => h.password.clone(),
+            None => {
+                return Err(MyErr {
+                    msg: format!("历史密码 {} 不存在", n),
+                })
+            }
+        };
+        u.change(&old);
+        self.save(file_name)
+    }
+
+    /* 按 pattern(ip/别名)、--tag、--user 选出多个条目, 用于批量操作 */
+    pub fn select(&self, params: &params::Params) -> Vec<Pwd> {
+        self.matches(params)
+            .iter()
+            .map(|(h, u)| Pwd {
+                ip: h.ip.clone(),
+                user: u.username.clone(),
+                password: Secret::from(u.password.as_str()),
+            })
+            .collect()
+    }
+
+    // 过滤条目, 顺序为 ip、用户
+    fn matches(&self, params: &params::Params) -> Vec<(&HostInfo, &UserInfo)> {
+        let mut items: Vec<(&HostInfo, &UserInfo)> = Vec::new();
+        for host in &self.hosts {
+            if !params.ip.is_empty() && !host_match(host, &params.ip) {
+                continue;
+            }
+            if !params.tags.is_empty() && !params.tags.iter().all(|t| has_tag(host, t)) {
+                continue;
+            }
+            for u in &host.users {
+                if !params.filter_user.is_empty() && !u.username.contains(&params.filter_user) {
+                    continue;
+                }
+                if params.stale && !u.stale.unwrap_or(false) {
+                    continue;
+                }
+                // --expired 只要已过期的, --expiring 包括 n 天内过期的
+                if params.expired || params.expiring != 0 {
+                    let limit = utils::now() + params.expiring * 86400;
+                    match self.expires(host, u) {
+                        Some(t) if t <= limit => {}
+                        _ => continue,
+                    }
+                }
+                items.push((host, u));
+            }
+        }
+        items
+    }
+
+    // 密码过期时间, 没有设置有效期时为 None
+    fn expires(&self, host: &HostInfo, user: &UserInfo) -> Option<u64> {
+        let days = host.max_age.or_else(|| self.policy_for(&host.ip).max_age)?;
+        Some(user.changed() + days * 86400)
+    }
+
+    /* 登录时的过期提醒, 已过期或 EXPIRE_WARN_DAYS 天内过期 */
+    pub fn expiry_warning(&self, ip: &str, user: &str) -> Option<String> {
+        let (i, j) = get_password(self, ip, user);
+        if i == -1 || j == -1 {
+            return None;
+        }
+        let (h, u) = (
+            &self.hosts[i as usize],
+            &self.hosts[i as usize].users[j as usize],
+        );
+        let t = self.expires(h, u)?;
+        let now = utils::now();
+        if u.changed() == 0 {
+            Some(format!(
+                "警告: {}@{} 密码修改时间未知, 请尽快修改",
+                user, ip
+            ))
+        } else if t <= now {
+            Some(format!(
+                "警告: {}@{} 密码已于 {} 过期, 请尽快修改",
+                user,
+                ip,
+                utils::fmt_time(t)
+            ))
+        } else if t <= now + EXPIRE_WARN_DAYS * 86400 {
+            Some(format!(
+                "警告: {}@{} 密码将于 {} 过期",
+                user,
+                ip,
+                utils::fmt_time(t)
+            ))
+        } else {
+            None
+        }
+    }
+
+    /* 更新或新增密码 */
+    pub fn set_pwd(&mut self, ip: &str, user: &str, password: &str) {
+        set_password(self, ip, user, password);
+    }
+
+    /* 记录一个未确认的密码到历史, 当前密码不变 */
+    pub fn remember(&mut self, ip: &str, user: &str, password: &str) {
+        let (i, j) = get_password(self, ip, user);
+        if i != -1 && j != -1 {
+            let u = &mut self.hosts[i as usize].users[j as usize];
+            let history = u.history.get_or_insert_with(Vec::new);
+            history.insert(
+                0,
+                OldPassword {
+                    password: password.to_string(),
+                    updated: None,
+                    replaced: utils::now(),
+                },
+            );
+            history.truncate(HISTORY_MAX);
+        }
+    }
+
+    /* 主机的密码策略: 第一个有策略的标签, 否则 default, 否则内置策略 */
+    pub fn policy_for(&self, ip: &str) -> gen::Policy {
+        let policies = match &self.policies {
+            Some(p) => p,
+            None => return gen::Policy::builtin(),
+        };
+        let mut names: Vec<String> = Vec::new();
+        for host in &self.hosts {
+            if host.ip == ip {
+                names.extend(host.tags.iter().flatten().cloned());
+            }
+        }
+        names.push(String::from("default"));
+        for n in &names {
+            if let Some(p) = policies.iter().find(|p| &p.name == n) {
+                return p.clone();
+            }
+        }
+        gen::Policy::builtin()
+    }
+
+    /* 主机的会话录像模式: 主机设置优先, 否则全局设置, 否则 off */
+    pub fn record_mode(&self, ip: &str) -> String {
+        let host = self
+            .hosts
+            .iter()
+            .find(|h| h.ip == ip)
+            .and_then(|h| h.record.clone());
+        host.or_else(|| self.record.clone())
+            .unwrap_or_else(|| String::from("off"))
+    }
+
+    /* 录像设置: 全局(主机为 *)与单独设置了的主机 */
+    pub fn record_settings(&self) -> Vec<(String, String)> {
+        let mut settings = vec![(
+            String::from("*"),
+            self.record.clone().unwrap_or_else(|| String::from("off")),
+        )];
+        for host in &self.hosts {
+            if let Some(r) = &host.record {
+                settings.push((host.ip.clone(), r.clone()));
+            }
+        }
+        settings
+    }
+
+    /* 设置全局录像模式 */
+    pub fn set_record(&mut self, file_name: &str, mode: &str) -> Result<(), MyErr> {
+        check_record(mode)?;
+        self.record = Some(mode.to_string());
+        self.save(file_name)
+    }
+
+    /* 所有策略 */
+    pub fn policies(&self) -> Vec<gen::Policy> {
+        self.policies.clone().unwrap_or_default()
+    }
+
+    /* 新增或替换策略 */
+    pub fn set_policy(&mut self, file_name: &str, policy: gen::Policy) -> Result<(), MyErr> {
+        let policies = self.policies.get_or_insert_with(Vec::new);
+        policies.retain(|p| p.name != policy.name);
+        policies.push(policy);
+        self.save(file_name)
+    }
+
+    /* 删除策略 */
+    pub fn remove_policy(&mut self, file_name: &str, name: &str) -> Result<(), MyErr> {
+        let policies = self.policies.get_or_insert_with(Vec::new);
+        let before = policies.len();
+        policies.retain(|p| p.name != name);
+        if policies.len() == before {
+            return Err(MyErr {
+                msg: format!("策略 {} 不存在", name),
+            });
+        }
+        self.save(file_name)
+    }
+
+    /* 操作是否需要验证密码
+     * 主机的标签有解锁策略时按这些策略(任一要求即需要), 否则按 default 策略, 没有时按 version
+     * ip 不是已保存的主机(为空、模糊匹配或新增)时, 任一策略要求即需要 */
+    pub fn need_unlock(&self, op: &str, ip: &str) -> bool {
+        match self.hosts.iter().find(|h| h.ip == ip) {
+            Some(h) => self.need_unlock_tags(op, h.tags.as_deref().unwrap_or(&[])),
+            None => self
+                .unlock_rules()
+                .iter()
+                .any(|r| r.ops.iter().any(|o| o == op)),
+        }
     }
 
-    /* 初始化文件 */
-    pub fn init(file_name: &str, pwd: &str, version: u8) -> Result<(), result::MyErr> {
-        let rdata = Rdata {
-            current: vec![],
-            hosts: vec![],
-            pwd: String::from(pwd),
-            version,
+    /* 带有这些标签的主机, 操作是否需要验证密码 */
+    pub fn need_unlock_tags(&self, op: &str, tags: &[String]) -> bool {
+        let rules = self.unlock_rules();
+        let matched: Vec<&unlock::Rule> = rules.iter().filter(|r| tags.contains(&r.name)).collect();
+        let ops = match matched.is_empty() {
+            true => rules
+                .iter()
+                .filter(|r| &r.name == "default")
+                .flat_map(|r| r.ops.iter())
+                .collect::<Vec<&String>>(),
+            false => matched.iter().flat_map(|r| r.ops.iter()).collect(),
         };
+        ops.iter().any(|o| o.as_str() == op)
+    }
 
-        let pos = match self::Rdata::get(&file_name) {
-            Ok(_) => 1,
-            Err(_) => 0,
-        };
-        let json_info = json::encode(&rdata).unwrap();
-        let hash_info = my_crypto(&json_info, true).unwrap();
-
-        let re = unsafe {
-            let file = CString::new(file_name).unwrap();
-            let info = CString::new(hash_info).unwrap();
-            init_file(
-                file.as_ptr() as *const i8,
-                info.as_ptr() as *const i8,
-                20,
-                pos,
-            )
-        };
+    /* 所有解锁策略, default 在最前; 没有设置 default 时按 version 补上 */
+    pub fn unlock_rules(&self) -> Vec<unlock::Rule> {
+        let mut rules = self.unlock.clone().unwrap_or_default();
+        if !rules.iter().any(|r| &r.name == "default") {
+            rules.push(unlock::Rule {
+                name: String::from("default"),
+                ops: unlock::legacy(self.version),
+            });
+        }
+        rules.sort_by_key(|r| r.name != "default");
+        rules
+    }
 
-        return match re {
-            0 => {
-                // 成功则替换旧文件
-                let new_file = format!("{}.bak", file_name);
-                std::fs::rename(new_file, file_name).expect("替换旧文件失败");
-                Ok(())
-            }
-            _ => {
-                // 失败则删除生成的 .bak
-                match std::fs::remove_file(format!("{}.bak", file_name)) {
-                    Ok(()) => {}
-                    Err(_) => {}
-                };
-                Err(result::MyErr {
-                    msg: String::from("初始化文件失败!"),
-                })
-            }
-        };
+    /* 新增或替换解锁策略 */
+    pub fn set_unlock(&mut self, file_name: &str, rule: unlock::Rule) -> Result<(), MyErr> {
+        let rules = self.unlock.get_or_insert_with(Vec::new);
+        rules.retain(|r| r.name != rule.name);
+        rules.push(rule);
+        self.save(file_name)
     }
 
-    /* 从文件导入密码 */
-    pub fn import_pwd(
-        mut self,
-        file_name: &str,
-        params: &params::Params,
-    ) -> Result<(), result::MyErr> {
-        let file = match File::open(&file_name) {
-            Ok(f) => f,
-            Err(_e) => {
-                return Err(result::MyErr {
-                    msg: format!("打开文件: {} 失败", &file_name),
-                })
-            }
-        };
+    /* 删除解锁策略, 删除 default 后按 version */
+    pub fn remove_unlock(&mut self, file_name: &str, name: &str) -> Result<(), MyErr> {
+        let rules = self.unlock.get_or_insert_with(Vec::new);
+        let before = rules.len();
+        rules.retain(|r| r.name != name);
+        if rules.len() == before {
+            return Err(MyErr {
+                msg: format!("解锁策略 {} 不存在", name),
+            });
+        }
+        self.save(file_name)
+    }
 
-        let file = BufReader::new(file);
-        for line in file.lines() {
-            let str_temp = match line {
-                Ok(s) => s,
-                Err(e) => return Err(result::MyErr { msg: e.to_string() }),
+    /* 是否设置了解锁策略, 未设置的 default 按 version */
+    pub fn unlock_is_set(&self, name: &str) -> bool {
+        self.unlock.iter().flatten().any(|r| r.name == name)
+    }
+
+    /* ip 或别名对应的主机 ip, 模糊匹配时必须唯一 */
+    pub fn resolve_host(&self, pattern: &str) -> Result<String, MyErr> {
+        if let Some(h) = self.hosts.iter().find(|h| h.ip == pattern) {
+            return Ok(h.ip.clone());
+        }
+        let found: Vec<&HostInfo> = self
+            .hosts
+            .iter()
+            .filter(|h| host_match(h, pattern))
+            .collect();
+        match found.len() {
+            0 => Err(MyErr {
+                msg: format!("主机 {} 不存在", pattern),
+            }),
+            1 => Ok(found[0].ip.clone()),
+            _ => Err(MyErr {
+                msg: format!("{} 匹配到多个主机, 请使用完整 ip", pattern),
+            }),
+        }
+    }
+
+    /* 精确查找条目, 不交互也不记录使用时间 */
+    pub fn find(&self, ip: &str, user: &str) -> Option<Pwd> {
+        let (i, j) = get_password(self, ip, user);
+        if i == -1 || j == -1 {
+            return None;
+        }
+        let u = &self.hosts[i as usize].users[j as usize];
+        Some(Pwd {
+            ip: ip.to_string(),
+            user: u.username.clone(),
+            password: Secret::from(u.password.as_str()),
+        })
+    }
+
+    /* 主机的跳板机 (user, ip), 检查整条链上的条目存在且没有循环 */
+    pub fn jump(&self, ip: &str) -> Result<Option<(String, String)>, MyErr> {
+        let mut chain = vec![ip.to_string()];
+        let mut first = None;
+        let mut current = ip.to_string();
+        loop {
+            let spec = match self.hosts.iter().find(|h| h.ip == current) {
+                Some(HostInfo { jump: Some(j), .. }) => j.clone(),
+                _ => return Ok(first),
             };
-            let ip_user: Vec<&str> = str_temp.split(" ").collect();
-            if ip_user.len() < 3 {
-                return Err(result::MyErr {
-                    msg: String::from("格式错误"),
+            let (user, jip) = match spec.find('@') {
+                Some(n) => (spec[..n].to_string(), spec[n + 1..].to_string()),
+                None => (String::from("root"), spec.clone()),
+            };
+            if self.find(&jip, &user).is_none() {
+                return Err(MyErr {
+                    msg: format!("跳板机 {}@{} 不存在", user, jip),
+                });
+            }
+            if chain.contains(&jip) {
+                return Err(MyErr {
+                    msg: format!("跳板机循环引用: {} -> {}", chain.join(" -> "), jip),
                 });
             }
-            set_password(&mut self, ip_user[0], ip_user[1], ip_user[2]);
+            chain.push(jip.clone());
+            if first.is_none() {
+                first = Some((user, jip.clone()));
+            }
+            current = jip;
         }
-
-        self.save(&params.file_path)
     }
 
-    /* 导出密码 */
-    pub fn export_pwd(&self, file_name: &str) -> Result<(), result::MyErr> {
-        let mut f = match File::create(file_name) {
-            Ok(f) => f,
-            Err(_e) => {
-                return Err(result::MyErr {
-                    msg: format!("创建文件 {} 失败", &file_name),
-                })
-            }
+    /* 条目的提权方式, 返回 (sudo 或 su, 登录用的条目), 没有设置时为 None */
+    pub fn escalation(&self, ip: &str, user: &str) -> Result<Option<(String, Pwd)>, MyErr> {
+        let (i, j) = get_password(self, ip, user);
+        if i == -1 || j == -1 {
+            return Ok(None);
+        }
+        let spec = match &self.hosts[i as usize].users[j as usize].escalate {
+            Some(s) => s.clone(),
+            None => return Ok(None),
         };
+        let (method, login) = parse_escalate(&spec)?;
+        match self.find(ip, &login) {
+            Some(p) => Ok(Some((method, p))),
+            None => Err(MyErr {
+                msg: format!("提权登录用户 {}@{} 不存在", login, ip),
+            }),
+        }
+    }
 
+    /* 主机保存的公钥, 没有时为空 */
+    pub fn host_keys(&self, ip: &str) -> Vec<String> {
         for host in &self.hosts {
-            for user in &host.users {
-                writeln!(f, "{} {} {}", &host.ip, &user.username, &user.password).unwrap();
+            if host.ip == ip {
+                return host.hostkeys.clone().unwrap_or_default();
             }
         }
-        Ok(())
+        vec![]
     }
 
-    /* 保存到文件 */
-    pub fn save(&self, file_name: &str) -> Result<(), result::MyErr> {
-        let json_data = match json::encode(&self) {
-            Ok(j) => j,
-            Err(_) => {
+    /* 保存主机公钥, 为空时删除 */
+    pub fn set_host_keys(
+        &mut self,
+        file_name: &str,
+        ip: &str,
+        keys: &[String],
+    ) -> Result<(), MyErr> {
+        let host = match self.hosts.iter_mut().find(|h| h.ip == ip) {
+            Some(h) => h,
+            None => {
                 return Err(MyErr {
-                    msg: String::from("json编码出错"),
+                    msg: format!("主机 {} 不存在", ip),
                 })
             }
         };
-        write_json(file_name, &json_data)
+        host.hostkeys = if keys.is_empty() {
+            None
+        } else {
+            Some(keys.to_vec())
+        };
+        host.modified = Some(utils::now());
+        self.save(file_name)
     }
 
-    /* 删除密码 */
-    pub fn delete_pwd(&mut self, params: &params::Params) -> Result<(), result::MyErr> {
-        let (x, y) = get_password(&self, &params.ip, &params.user);
+    /* 按 pattern 列出主机公钥: ip, port, type, fingerprint */
+    pub fn list_host_keys(&self, pattern: &str) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        for host in &self.hosts {
+            if !pattern.is_empty() && !host_match(host, pattern) {
+                continue;
+            }
+            for k in host.hostkeys.iter().flatten() {
+                rows.push(vec![
+                    host.ip.clone(),
+                    host.port.unwrap_or(22).to_string(),
+                    hostkey::key_type(k).to_string(),
+                    hostkey::fingerprint(k),
+                ]);
+            }
+        }
+        rows
+    }
 
-        if x != -1 && y != -1 {
-            match &self.hosts[x as usize].users.len() {
-                1 => {
-                    &self.hosts.remove(x as usize);
-                }
-                _ => {
-                    &self.hosts[x as usize].users.remove(y as usize);
-                }
+    /* 记录 check 结果, stale 为 None 时只更新检查时间 */
+    pub fn mark_checked(&mut self, ip: &str, user: &str, stale: Option<bool>) {
+        let (i, j) = get_password(self, ip, user);
+        if i != -1 && j != -1 {
+            let u = &mut self.hosts[i as usize].users[j as usize];
+            u.checked = Some(utils::now());
+            if let Some(s) = stale {
+                u.stale = if s { Some(true) } else { None };
             }
         }
-        //save_to_file(&params.file_path, &rdata)
-        self.save(&params.file_path)
     }
 
-    /* 列出指定 ip 所有用户 */
-    pub fn get_user(&self, ip: &str) -> Result<(), result::MyErr> {
+    /* 记录最近使用时间, 返回是否修改; 条目不存在或 TOUCH_INTERVAL 内已记录过时不修改 */
+    pub fn touch(&mut self, ip: &str, user: &str) -> bool {
+        let (i, j) = get_password(self, ip, user);
+        if i == -1 || j == -1 {
+            return false;
+        }
+        let u = &mut self.hosts[i as usize].users[j as usize];
+        let now = utils::now();
+        if u.last_used
+            .is_some_and(|t| now.saturating_sub(t) < TOUCH_INTERVAL)
+        {
+            return false;
+        }
+        u.last_used = Some(now);
+        true
+    }
+
+    /* 主机端口, 0 表示默认 */
+    pub fn get_port(&self, ip: &str) -> u16 {
         for host in &self.hosts {
-            if &host.ip == ip {
-                for u in &host.users {
-                    println!("{} ", u.username);
-                }
+            if host.ip == ip {
+                return host.port.unwrap_or(0);
             }
         }
-        Ok(())
+        0
     }
 }
 
 pub struct Pwd {
     pub ip: String,
     pub user: String,
-    pub password: String,
+    pub password: Secret, // 释放时清零
 }
 impl Pwd {
     // 获取密码
@@ -297,34 +1727,20 @@ impl Pwd {
                 1 => {
                     let (i, j) = re[0];
 
-                    return Ok(Pwd {
-                        ip: password_info.hosts[i as usize].ip.clone(),
-                        user: password_info.hosts[i as usize].users[j as usize]
-                            .username
-                            .clone(),
-                        password: password_info.hosts[i as usize].users[j as usize]
-                            .password
-                            .clone(),
-                    });
+                    return Ok(Pwd::at(password_info, i as usize, j as usize));
                 }
                 _ => {
-                    // 缓存到 vector 用于排序
-                    let mut temp_str: Vec<String> = Vec::new();
-                    for r in &re {
-                        let (i, j) = *r;
-                        temp_str.push(format!(
-                            "{} {} {}",
-                            &password_info.hosts[i as usize].ip,
-                            &password_info.hosts[i as usize].users[j as usize].username,
-                            password_info.hosts[i as usize].users[j as usize].password
-                        ));
-                    }
-                    temp_str.sort();
+                    // 按 ip、用户排序; 只保存位置, 不复制密码
+                    let mut temp_str = re.clone();
+                    temp_str.sort_by_key(|&(i, j)| {
+                        let host = &password_info.hosts[i as usize];
+                        (host.ip.clone(), host.users[j as usize].username.clone())
+                    });
 
                     println!("找到多个符合条件的 IP: ");
                     let mut idx = 1;
-                    for r in &temp_str {
-                        println!("    {}. {}", idx, &r[..r.find(' ').unwrap()]);
+                    for &(i, _) in &temp_str {
+                        println!("    {}. {}", idx, &password_info.hosts[i as usize].ip);
                         idx += 1;
                     }
 
@@ -379,45 +1795,89 @@ impl Pwd {
                         });
                     }
 
-                    // 更新配置文件
+                    // 更新配置文件, 保存失败不影响登录
                     if old_idx > 0 && choose != old_idx {
                         // 配置文件中存在，但不相等
                         password_info.current[last_index].index = choose;
-                        password_info.save(&params.file_path).unwrap();
+                        let _ =
+                            Rdata::update(&params.file_path, |r| set_choice(r, &params.ip, choose));
                     } else if old_idx <= 0 {
                         // 配置文件中不存在
                         password_info.current.push(LastChoose {
                             ip: params.ip.clone(),
                             index: choose,
                         });
-                        password_info.save(&params.file_path).unwrap();
+                        let _ =
+                            Rdata::update(&params.file_path, |r| set_choice(r, &params.ip, choose));
                     }
 
-                    let re: Vec<&str> = temp_str[choose - 1].split(" ").collect();
+                    let (i, j) = temp_str[choose - 1];
                     // 返回结果
-                    return Ok(Pwd {
-                        ip: re[0].to_string(),
-                        user: re[1].to_string(),
-                        password: re[2].to_string(),
-                    });
+                    return Ok(Pwd::at(password_info, i as usize, j as usize));
                 }
             }
         } else {
-            return Ok(Pwd {
-                ip: password_info.hosts[i as usize].ip.clone(),
-                user: password_info.hosts[i as usize].users[j as usize]
-                    .username
-                    .clone(),
-                password: password_info.hosts[i as usize].users[j as usize]
-                    .password
-                    .clone(),
-            });
+            return Ok(Pwd::at(password_info, i as usize, j as usize));
+        }
+    }
+
+    // 第 i 个主机的第 j 个用户
+    fn at(data: &Rdata, i: usize, j: usize) -> Pwd {
+        let host = &data.hosts[i];
+        Pwd {
+            ip: host.ip.clone(),
+            user: host.users[j].username.clone(),
+            password: Secret::from(host.users[j].password.as_str()),
         }
     }
 
     // 更改密码, 有则更新, 没有则添加
     pub fn change_password(data: &mut Rdata, params: &params::Params) -> Result<(), MyErr> {
         set_password(data, &params.ip, &params.user, &params.password);
+        set_host_info(data, &params.ip, params.port, &params.alias, &params.tags);
+        if params.max_age != 0 {
+            set_max_age(data, &params.ip, params.max_age);
+        }
+        match params.jump.as_str() {
+            "" => {}
+            "none" => set_jump(data, &params.ip, None),
+            j => {
+                set_jump(data, &params.ip, Some(j.to_string()));
+                // 检查跳板机是否存在、是否循环引用
+                data.jump(&params.ip)?;
+            }
+        }
+        match params.record.as_str() {
+            "" => {}
+            "none" => set_record(data, &params.ip, None),
+            r => {
+                check_record(r)?;
+                set_record(data, &params.ip, Some(r.to_string()));
+            }
+        }
+        match params.escalate.as_str() {
+            "" => {}
+            "none" => set_escalate(data, &params.ip, &params.user, None),
+            e => {
+                let (_, login) = parse_escalate(e)?;
+                if login == params.user {
+                    return Err(MyErr {
+                        msg: String::from("提权登录用户不能是目标用户本身"),
+                    });
+                }
+                if data.find(&params.ip, &login).is_none() {
+                    return Err(MyErr {
+                        msg: format!("提权登录用户 {}@{} 不存在, 请先保存", login, &params.ip),
+                    });
+                }
+                set_escalate(data, &params.ip, &params.user, Some(e.to_string()));
+            }
+        }
+        match params.auth.as_str() {
+            "" => {}
+            "none" => set_auth(data, &params.ip, &params.user, None),
+            a => set_auth(data, &params.ip, &params.user, Some(a.to_string())),
+        }
         // 保存
         data.save(&params.file_path)
     }
@@ -443,8 +1903,8 @@ fn get_password_fuzzy(data: &Rdata, ip: &str, user: &str) -> Vec<(i32, i32)> {
     let mut re: Vec<(i32, i32)> = Vec::new();
 
     for i in 0..data.hosts.len() {
-        // 匹配到 ip
-        if data.hosts[i].ip.find(ip) >= Some(0) {
+        // 匹配到 ip 或别名
+        if host_match(&data.hosts[i], ip) {
             for j in 0..data.hosts[i].users.len() {
                 // 匹配到用户
                 if user == data.hosts[i].users[j].username {
@@ -463,20 +1923,448 @@ fn set_password(data: &mut Rdata, ip: &str, user: &str, password: &str) {
     if i == -1 {
         data.hosts.push(HostInfo {
             ip: ip.to_string(),
-            users: vec![UserInfo {
-                username: user.to_string(),
-                password: password.to_string(),
-            }],
+            users: vec![UserInfo::new(user, password)],
+            port: None,
+            alias: None,
+            tags: None,
+            max_age: None,
+            hostkeys: None,
+            jump: None,
+            record: None,
+            modified: Some(utils::now()),
         });
     } else {
         if j == -1 {
-            data.hosts[i as usize].users.push(UserInfo {
-                username: user.to_string(),
-                password: password.to_string(),
-            });
+            data.hosts[i as usize]
+                .users
+                .push(UserInfo::new(user, password));
         } else {
-            data.hosts[i as usize].users[j as usize].password = password.to_string();
+            data.hosts[i as usize].users[j as usize].change(password);
+        }
+    }
+}
+
+// 更新主机端口、别名、标签, 未指定的保持不变
+fn set_host_info(data: &mut Rdata, ip: &str, port: u16, alias: &[String], tags: &[String]) {
+    for host in &mut data.hosts {
+        if host.ip == ip {
+            if host_info_differs(host, port, alias, tags) {
+                host.modified = Some(utils::now());
+            }
+            if port != 0 {
+                host.port = Some(port);
+            }
+            if !alias.is_empty() {
+                host.alias = Some(alias.to_vec());
+            }
+            if !tags.is_empty() {
+                host.tags = Some(tags.to_vec());
+            }
+        }
+    }
+}
+
+// 设置主机密码最长使用天数
+fn set_max_age(data: &mut Rdata, ip: &str, days: u64) {
+    for host in &mut data.hosts {
+        if host.ip == ip {
+            host.max_age = Some(days);
+            host.modified = Some(utils::now());
+        }
+    }
+}
+
+// 设置或清除跳板机
+fn set_jump(data: &mut Rdata, ip: &str, jump: Option<String>) {
+    for host in &mut data.hosts {
+        if host.ip == ip {
+            host.jump.clone_from(&jump);
+            host.modified = Some(utils::now());
+        }
+    }
+}
+
+// 设置或清除主机的录像模式
+fn set_record(data: &mut Rdata, ip: &str, mode: Option<String>) {
+    for host in &mut data.hosts {
+        if host.ip == ip {
+            host.record.clone_from(&mode);
+            host.modified = Some(utils::now());
+        }
+    }
+}
+
+// 检查录像模式
+fn check_record(mode: &str) -> Result<(), MyErr> {
+    if record::MODES.contains(&mode) {
+        Ok(())
+    } else {
+        Err(MyErr {
+            msg: format!("录像模式错误: {}, 应为 {}", mode, record::MODES.join("|")),
+        })
+    }
+}
+
+// 设置或清除提权方式
+fn set_escalate(data: &mut Rdata, ip: &str, user: &str, escalate: Option<String>) {
+    let (i, j) = get_password(data, ip, user);
+    if i != -1 && j != -1 {
+        let u = &mut data.hosts[i as usize].users[j as usize];
+        u.escalate = escalate;
+        u.modified = Some(utils::now());
+    }
+}
+
+// 设置或清除认证方式
+fn set_auth(data: &mut Rdata, ip: &str, user: &str, auth: Option<String>) {
+    let (i, j) = get_password(data, ip, user);
+    if i != -1 && j != -1 {
+        let u = &mut data.hosts[i as usize].users[j as usize];
+        u.auth = auth;
+        u.modified = Some(utils::now());
+    }
+}
+
+// 解析提权方式 sudo:user 或 su:user
+fn parse_escalate(spec: &str) -> Result<(String, String), MyErr> {
+    let mut parts = spec.splitn(2, ':');
+    let method = parts.next().unwrap_or("");
+    let login = parts.next().unwrap_or("");
+    if (method != "sudo" && method != "su") || login.is_empty() {
+        return Err(MyErr {
+            msg: format!("提权方式错误: {}, 应为 sudo:用户 或 su:用户", spec),
+        });
+    }
+    Ok((method.to_string(), login.to_string()))
+}
+
+// 设置密码修改时间
+fn set_updated(data: &mut Rdata, ip: &str, user: &str, t: u64) {
+    let (i, j) = get_password(data, ip, user);
+    if i != -1 && j != -1 {
+        data.hosts[i as usize].users[j as usize].updated = Some(t);
+    }
+}
+
+// 记录多个匹配时的选择
+fn set_choice(data: &mut Rdata, ip: &str, index: usize) {
+    match data.current.iter_mut().find(|c| c.ip == ip) {
+        Some(c) => c.index = index,
+        None => data.current.push(LastChoose {
+            ip: ip.to_string(),
+            index,
+        }),
+    }
+}
+
+// 依次取出 json 数组中完整的对象, 遇到数组结束或数据截断时停止
+fn json_objects(s: &str) -> Vec<&str> {
+    let mut objs = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    let (mut in_str, mut escaped) = (false, false);
+    for (i, c) in s.char_indices() {
+        if in_str {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_str = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_str = true,
+            '{' | '[' => {
+                if depth == 0 {
+                    start = i;
+                }
+                depth += 1;
+            }
+            '}' | ']' if depth == 0 => break,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    objs.push(&s[start..=i]);
+                }
+            }
+            _ => {}
+        }
+    }
+    objs
+}
+
+// 新条目的标识
+fn new_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+// 另一份数据中的同一用户条目: 先按标识, 没有时按 ip 与用户名
+fn find_user(data: &Rdata, ip: &str, u: &UserInfo) -> Option<(usize, usize)> {
+    for (i, h) in data.hosts.iter().enumerate() {
+        if let Some(j) = h.users.iter().position(|x| x.id.is_some() && x.id == u.id) {
+            return Some((i, j));
         }
     }
+    match get_password(data, ip, &u.username) {
+        (i, j) if i != -1 && j != -1 => Some((i as usize, j as usize)),
+        _ => None,
+    }
+}
+
+// 三方合并的统计与修改、冲突说明
+struct Merged {
+    added: usize,
+    updated: usize,
+    deleted: usize,
+    changes: Vec<String>,
+    conflicts: Vec<String>,
+}
+
+// 合并时写入的用户条目: ip, 本地条目的位置(新增时为 None), 对方的条目
+type Put = (String, Option<(usize, usize)>, UserInfo);
+
+// 三方合并中一项的结果
+enum Pick {
+    Ours,
+    Theirs,
+    Conflict,
+}
+
+// 比较本地、对方、共同版本的内容, None 表示该版本中不存在
+fn pick(ours: Option<String>, theirs: Option<String>, base: Option<String>) -> Pick {
+    if ours == theirs || theirs == base {
+        Pick::Ours
+    } else if ours == base {
+        Pick::Theirs
+    } else {
+        Pick::Conflict
+    }
+}
+
+// 双方都修改的冲突按合并策略处理, 返回是否使用对方的版本; 修改时间为 None 表示已删除
+fn resolve(
+    strategy: &str,
+    dry_run: bool,
+    what: &str,
+    ours: Option<u64>,
+    theirs: Option<u64>,
+    conflicts: &mut Vec<String>,
+) -> bool {
+    let time = |t: Option<u64>| match t {
+        Some(t) => format!("修改于 {}", utils::fmt_time(t)),
+        None => String::from("已删除"),
+    };
+    let take = match strategy {
+        "skip" => false,
+        "newer" => theirs.unwrap_or(0) > ours.unwrap_or(0),
+        "interactive" if !dry_run => utils::confirm(&format!(
+            "冲突 {}: 本地{}, 对方{}, 使用对方的版本? [y/N]: ",
+            what,
+            time(ours),
+            time(theirs)
+        )),
+        // dry-run 时不询问, 按保留本地统计
+        "interactive" => false,
+        _ => true,
+    };
+    let action = match take {
+        true => "使用对方",
+        false if strategy == "interactive" && dry_run => "待确认",
+        false => "保留本地",
+    };
+    conflicts.push(format!(
+        "冲突 {}: 本地{}, 对方{}, {}",
+        what,
+        time(ours),
+        time(theirs),
+        action
+    ));
+    take
+}
+
+// 交互式确认是否用导入的密码覆盖本地密码
+fn ask_overwrite(e: &import::Entry, local: &UserInfo) -> bool {
+    utils::confirm(&format!(
+        "{} {} 密码不同, 本地修改于 {}, 导入修改于 {}, 覆盖? [y/N]: ",
+        &e.ip,
+        &e.user,
+        utils::fmt_time(local.updated.unwrap_or(0)),
+        utils::fmt_time(e.updated.unwrap_or(0))
+    ))
+}
+
+// 端口、别名、标签是否会被更新
+fn host_info_differs(host: &HostInfo, port: u16, alias: &[String], tags: &[String]) -> bool {
+    (port != 0 && host.port != Some(port))
+        || (!alias.is_empty() && host.alias.as_deref() != Some(alias))
+        || (!tags.is_empty() && host.tags.as_deref() != Some(tags))
+}
+
+// ip 或别名包含 pattern
+fn host_match(host: &HostInfo, pattern: &str) -> bool {
+    if host.ip.contains(pattern) {
+        return true;
+    }
+    match &host.alias {
+        Some(a) => a.iter().any(|x| x.contains(pattern)),
+        None => false,
+    }
 }
 
+// 主机是否带有指定标签
+fn has_tag(host: &HostInfo, tag: &str) -> bool {
+    match &host.tags {
+        Some(t) => t.iter().any(|x| x == tag),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 同一份数据的副本, 条目标识相同
+    fn copy(d: &Rdata) -> Rdata {
+        json::decode(&json::encode(d).unwrap()).unwrap()
+    }
+
+    fn base() -> Rdata {
+        let mut d = Rdata::new("pw", 0);
+        set_password(&mut d, "10.0.0.1", "root", "a");
+        set_password(&mut d, "10.0.0.1", "admin", "b");
+        set_password(&mut d, "10.0.0.2", "root", "c");
+        d
+    }
+
+    fn pw(d: &Rdata, ip: &str, user: &str) -> Option<String> {
+        match get_password(d, ip, user) {
+            (i, j) if i != -1 && j != -1 => {
+                Some(d.hosts[i as usize].users[j as usize].password.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn remove(d: &mut Rdata, ip: &str, user: &str) {
+        for h in d.hosts.iter_mut().filter(|h| h.ip == ip) {
+            h.users.retain(|u| u.username != user);
+        }
+        d.hosts.retain(|h| !h.users.is_empty());
+    }
+
+    #[test]
+    fn merge_takes_the_side_that_changed() {
+        let b = base();
+        let mut ours = copy(&b);
+        let mut theirs = copy(&b);
+        set_password(&mut ours, "10.0.0.1", "root", "ours");
+        set_password(&mut theirs, "10.0.0.2", "root", "theirs");
+        set_password(&mut theirs, "10.0.0.3", "root", "new");
+
+        let m = ours.merge3(&theirs, Some(&b), "overwrite", false);
+        assert_eq!(pw(&ours, "10.0.0.1", "root").as_deref(), Some("ours"));
+        assert_eq!(pw(&ours, "10.0.0.1", "admin").as_deref(), Some("b"));
+        assert_eq!(pw(&ours, "10.0.0.2", "root").as_deref(), Some("theirs"));
+        assert_eq!(pw(&ours, "10.0.0.3", "root").as_deref(), Some("new"));
+        assert_eq!((m.added, m.updated, m.deleted), (1, 1, 0));
+        assert!(m.conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_conflicts_follow_strategy() {
+        let b = base();
+        let mut theirs = copy(&b);
+        set_password(&mut theirs, "10.0.0.1", "root", "theirs");
+
+        let mut ours = copy(&b);
+        set_password(&mut ours, "10.0.0.1", "root", "ours");
+        let m = ours.merge3(&theirs, Some(&b), "overwrite", false);
+        assert_eq!(pw(&ours, "10.0.0.1", "root").as_deref(), Some("theirs"));
+        assert_eq!(m.conflicts.len(), 1);
+
+        let mut ours = copy(&b);
+        set_password(&mut ours, "10.0.0.1", "root", "ours");
+        let m = ours.merge3(&theirs, Some(&b), "skip", false);
+        assert_eq!(pw(&ours, "10.0.0.1", "root").as_deref(), Some("ours"));
+        assert!(m.conflicts[0].ends_with("保留本地"));
+
+        // newer 按条目修改时间
+        let mut ours = copy(&b);
+        set_password(&mut ours, "10.0.0.1", "root", "ours");
+        let (i, j) = get_password(&ours, "10.0.0.1", "root");
+        ours.hosts[i as usize].users[j as usize].modified = Some(1);
+        ours.merge3(&theirs, Some(&b), "newer", false);
+        assert_eq!(pw(&ours, "10.0.0.1", "root").as_deref(), Some("theirs"));
+
+        // dry-run 时不询问, 冲突待确认
+        let mut ours = copy(&b);
+        set_password(&mut ours, "10.0.0.1", "root", "ours");
+        let m = ours.merge3(&theirs, Some(&b), "interactive", true);
+        assert_eq!(pw(&ours, "10.0.0.1", "root").as_deref(), Some("ours"));
+        assert!(m.conflicts[0].ends_with("待确认"));
+    }
+
+    #[test]
+    fn merge_propagates_deletes_only_with_base() {
+        let b = base();
+        let ours = copy(&b);
+        let mut theirs = copy(&b);
+        remove(&mut theirs, "10.0.0.2", "root");
+        remove(&mut theirs, "10.0.0.1", "admin");
+
+        let mut merged = copy(&ours);
+        let m = merged.merge3(&theirs, Some(&b), "overwrite", false);
+        assert_eq!(m.deleted, 2);
+        assert_eq!(pw(&merged, "10.0.0.1", "admin"), None);
+        assert!(!merged.hosts.iter().any(|h| h.ip == "10.0.0.2"));
+
+        // 没有 base 时不传播删除
+        let mut merged = copy(&ours);
+        let m = merged.merge3(&theirs, None, "overwrite", false);
+        assert_eq!(m.deleted, 0);
+        assert_eq!(pw(&merged, "10.0.0.2", "root").as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn merge_delete_against_local_change_is_conflict() {
+        let b = base();
+        let mut ours = copy(&b);
+        let mut theirs = copy(&b);
+        set_password(&mut ours, "10.0.0.2", "root", "ours");
+        remove(&mut theirs, "10.0.0.2", "root");
+
+        let m = ours.merge3(&theirs, Some(&b), "skip", false);
+        assert_eq!(pw(&ours, "10.0.0.2", "root").as_deref(), Some("ours"));
+        assert_eq!((m.deleted, m.conflicts.len()), (0, 1));
+    }
+
+    #[test]
+    fn touch_only_changes_once_per_interval() {
+        let mut d = base();
+        assert!(d.touch("10.0.0.1", "root"));
+        assert!(!d.touch("10.0.0.1", "root"));
+        assert!(!d.touch("10.0.0.9", "root"));
+        let (i, j) = get_password(&d, "10.0.0.1", "root");
+        d.hosts[i as usize].users[j as usize].last_used = Some(utils::now() - TOUCH_INTERVAL);
+        assert!(d.touch("10.0.0.1", "root"));
+    }
+
+    #[test]
+    fn merge_keeps_newer_last_used() {
+        let b = base();
+        let mut ours = copy(&b);
+        let mut theirs = copy(&b);
+        set_password(&mut theirs, "10.0.0.1", "root", "theirs");
+        let (i, j) = get_password(&ours, "10.0.0.1", "root");
+        ours.hosts[i as usize].users[j as usize].last_used = Some(100);
+        theirs.hosts[i as usize].users[j as usize].last_used = Some(50);
+
+        ours.merge3(&theirs, Some(&b), "overwrite", false);
+        let (i, j) = get_password(&ours, "10.0.0.1", "root");
+        assert_eq!(
+            ours.hosts[i as usize].users[j as usize].last_used,
+            Some(100)
+        );
+        assert_eq!(ours.hosts[i as usize].users[j as usize].password, "theirs");
+    }
+}
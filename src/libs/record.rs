@@ -0,0 +1,388 @@
+use super::result;
+use super::utils;
+
+use rustc_serialize::json::{self, Json};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 回放时最长的停顿(秒), 超过的空闲时间被压缩
+const IDLE_MAX: f64 = 2.0;
+
+// 录像模式: off 不录, on 只录输出, input 同时录输入(密码提示后的输入被遮盖)
+pub const MODES: [&str; 3] = ["off", "on", "input"];
+
+// 索引中的一次会话
+pub struct Session {
+    pub id: String,
+    pub started: u64,
+    pub duration: u64,
+    pub ip: String,
+    pub user: String,
+    pub status: String,
+}
+
+/* 录像目录: 数据文件同级的 ${file_name}.sessions */
+pub fn session_dir(file_name: &str) -> String {
+    format!("{}.sessions", file_name)
+}
+
+fn cast_path(file_name: &str, id: &str) -> String {
+    format!("{}/{}.cast", session_dir(file_name), id)
+}
+
+fn index_path(file_name: &str) -> String {
+    format!("{}/index", session_dir(file_name))
+}
+
+// 一次会话的录像, asciicast v2 格式
+pub struct Recorder {
+    file_name: String,
+    id: String,
+    ip: String,
+    user: String,
+    started: u64,
+    start: Instant,
+    out: File,
+    input: bool,
+    masking: bool,    // 最后的输出是密码提示, 输入以 * 代替
+    tail: String,     // 输出的最后一行, 用于判断密码提示
+    pending: Vec<u8>, // 输出中不完整的 UTF-8 字符
+}
+
+impl Recorder {
+    /* 创建录像文件并写入头部, input 为 true 时同时记录输入 */
+    pub fn start(
+        file_name: &str,
+        ip: &str,
+        user: &str,
+        input: bool,
+    ) -> Result<Recorder, result::MyErr> {
+        let dir = session_dir(file_name);
+        if fs::metadata(&dir).is_err() && fs::DirBuilder::new().mode(0o700).create(&dir).is_err() {
+            return Err(result::MyErr {
+                msg: format!("创建录像目录 {} 失败", &dir),
+            });
+        }
+
+        let started = utils::now();
+        let id = format!("{}-{}", started, std::process::id());
+        let out = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(cast_path(file_name, &id));
+        let mut out = match out {
+            Ok(f) => f,
+            Err(_) => {
+                return Err(result::MyErr {
+                    msg: format!("创建录像 {} 失败", &id),
+                })
+            }
+        };
+
+        let (width, height) = window_size();
+        let header = format!(
+            "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}, \"title\": {}, \"env\": {{\"TERM\": {}}}}}\n",
+            width,
+            height,
+            started,
+            encode(&format!("{}@{}", user, ip)),
+            encode(&std::env::var("TERM").unwrap_or_default())
+        );
+        if out.write_all(header.as_bytes()).is_err() {
+            return Err(result::MyErr {
+                msg: format!("写入录像 {} 失败", &id),
+            });
+        }
+
+        Ok(Recorder {
+            file_name: file_name.to_string(),
+            id,
+            ip: ip.to_string(),
+            user: user.to_string(),
+            started,
+            start: Instant::now(),
+            out,
+            input,
+            masking: false,
+            tail: String::new(),
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /* 记录终端输出 */
+    pub fn output(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        let text = take_utf8(&mut self.pending);
+        if text.is_empty() {
+            return;
+        }
+        // 只保留最后一行
+        match text.rfind('\n') {
+            Some(n) => self.tail = text[n + 1..].to_string(),
+            None => self.tail.push_str(&text),
+        }
+        let n = self.tail.chars().count();
+        if n > 256 {
+            self.tail = self.tail.chars().skip(n - 64).collect();
+        }
+        // 提示之后又有输出时(如自动应答的 ssh 密码提示)不再遮盖
+        self.masking = is_password_prompt(&self.tail);
+        self.event("o", &text);
+    }
+
+    /* 记录键盘输入, 密码提示之后到回车之前的输入被遮盖 */
+    pub fn input(&mut self, data: &[u8]) {
+        if !self.input {
+            return;
+        }
+        let mut text = String::new();
+        for c in String::from_utf8_lossy(data).chars() {
+            if c == '\r' || c == '\n' {
+                self.masking = false;
+                self.tail.clear();
+                text.push(c);
+            } else if self.masking {
+                text.push('*');
+            } else {
+                text.push(c);
+            }
+        }
+        self.event("i", &text);
+    }
+
+    fn event(&mut self, kind: &str, text: &str) {
+        let t = self.start.elapsed().as_secs_f64();
+        let _ = writeln!(self.out, "[{:.6}, \"{}\", {}]", t, kind, encode(text));
+    }
+
+    /* 会话结束, 写入索引; status 为 ok 或错误信息 */
+    pub fn finish(mut self, status: &str) {
+        if !self.pending.is_empty() {
+            let rest = String::from_utf8_lossy(&self.pending).into_owned();
+            self.event("o", &rest);
+        }
+        let _ = self.out.sync_all();
+        let line = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            self.id,
+            self.started,
+            self.start.elapsed().as_secs(),
+            self.ip,
+            self.user,
+            status.replace(['\t', '\n'], " ")
+        );
+        let _ = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(index_path(&self.file_name))
+            .and_then(|mut f| f.write_all(line.as_bytes()));
+    }
+}
+
+/* 列出录像, 按时间从新到旧; pattern 非空时只列出 ip 或用户包含它的会话 */
+pub fn list(file_name: &str, pattern: &str) -> Vec<Session> {
+    let content = fs::read_to_string(index_path(file_name)).unwrap_or_default();
+    let mut sessions: Vec<Session> = content
+        .lines()
+        .filter_map(|l| {
+            let f: Vec<&str> = l.splitn(6, '\t').collect();
+            if f.len() != 6 {
+                return None;
+            }
+            Some(Session {
+                id: f[0].to_string(),
+                started: f[1].parse().unwrap_or(0),
+                duration: f[2].parse().unwrap_or(0),
+                ip: f[3].to_string(),
+                user: f[4].to_string(),
+                status: f[5].to_string(),
+            })
+        })
+        .filter(|s| pattern.is_empty() || s.ip.contains(pattern) || s.user.contains(pattern))
+        .collect();
+    sessions.sort_by(|a, b| (b.started, &b.id).cmp(&(a.started, &a.id)));
+    sessions
+}
+
+/* 按录制时的节奏回放输出, 过长的停顿压缩为 IDLE_MAX */
+pub fn replay(file_name: &str, id: &str) -> Result<(), result::MyErr> {
+    // id 只能是录像文件名, 不能指向其他目录
+    if id.is_empty() || id.contains('/') {
+        return Err(result::MyErr {
+            msg: format!("录像 {} 不存在", id),
+        });
+    }
+    let file = match File::open(cast_path(file_name, id)) {
+        Ok(f) => f,
+        Err(_) => {
+            return Err(result::MyErr {
+                msg: format!("录像 {} 不存在", id),
+            })
+        }
+    };
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let mut last = 0.0;
+    // 第一行为头部
+    for line in BufReader::new(file).lines().skip(1) {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        let event = match Json::from_str(&line) {
+            Ok(Json::Array(a)) if a.len() == 3 => a,
+            _ => {
+                return Err(result::MyErr {
+                    msg: format!("录像 {} 格式错误", id),
+                })
+            }
+        };
+        if event[1].as_string() != Some("o") {
+            continue;
+        }
+        let t = event[0].as_f64().unwrap_or(last);
+        let wait = (t - last).clamp(0.0, IDLE_MAX);
+        last = t;
+        if wait > 0.0 {
+            thread::sleep(Duration::from_secs_f64(wait));
+        }
+        let _ = stdout.write_all(event[2].as_string().unwrap_or("").as_bytes());
+        let _ = stdout.flush();
+    }
+    Ok(())
+}
+
+// 字符串编码为 json 字符串
+fn encode(s: &str) -> String {
+    json::encode(&s).unwrap_or_else(|_| String::from("\"\""))
+}
+
+// 取出 buf 中完整的 UTF-8 字符, 末尾不完整的字节留到下次
+fn take_utf8(buf: &mut Vec<u8>) -> String {
+    let valid = match std::str::from_utf8(buf) {
+        Ok(_) => buf.len(),
+        // 末尾的字符不完整
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => {
+            let s = String::from_utf8_lossy(buf).into_owned();
+            buf.clear();
+            return s;
+        }
+    };
+    let rest = buf.split_off(valid);
+    let s = String::from_utf8_lossy(buf).into_owned();
+    *buf = rest;
+    s
+}
+
+// 输出的最后一行是否为密码提示
+fn is_password_prompt(tail: &str) -> bool {
+    let t = tail.trim_end();
+    (t.ends_with(':') || t.ends_with('：'))
+        && (t.contains("assword") || t.contains("assphrase") || t.contains("密码"))
+}
+
+// 当前终端大小, 不是终端时为 80x24
+fn window_size() -> (u16, u16) {
+    let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == 0 && ws.ws_col > 0
+    {
+        (ws.ws_col, ws.ws_row)
+    } else {
+        (80, 24)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_utf8_keeps_split_character() {
+        // "密" 为 e5 af 86, 分两次到达
+        let mut buf = vec![b'a', 0xe5, 0xaf];
+        assert_eq!(take_utf8(&mut buf), "a");
+        assert_eq!(buf, vec![0xe5, 0xaf]);
+        buf.push(0x86);
+        assert_eq!(take_utf8(&mut buf), "密");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn take_utf8_replaces_invalid_bytes() {
+        let mut buf = vec![b'a', 0xff, b'b'];
+        assert_eq!(take_utf8(&mut buf), "a\u{fffd}b");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn password_prompts() {
+        assert!(is_password_prompt("root@10.0.0.1's password: "));
+        assert!(is_password_prompt("[sudo] password for root:"));
+        assert!(is_password_prompt(
+            "Enter passphrase for key '/root/.ssh/id_rsa':"
+        ));
+        assert!(is_password_prompt("请输入密码："));
+        assert!(!is_password_prompt("Last password change: Jan 01"));
+        assert!(!is_password_prompt("root@host:~# "));
+        assert!(!is_password_prompt("Username:"));
+    }
+
+    #[test]
+    fn input_masked_after_prompt() {
+        let dir = std::env::temp_dir().join(format!("rssh-record-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_name = dir.join("data").to_string_lossy().into_owned();
+
+        let mut r = Recorder::start(&file_name, "10.0.0.1", "root", true).unwrap();
+        r.input(b"ls\r");
+        r.output(b"[sudo] password for root: ");
+        r.input(b"secret");
+        r.input(b"\rid\r");
+        let path = cast_path(&file_name, r.id());
+        r.finish("ok");
+
+        let cast = fs::read_to_string(&path).unwrap();
+        let inputs: Vec<String> = cast
+            .lines()
+            .skip(1)
+            .filter_map(|l| match Json::from_str(l) {
+                Ok(Json::Array(a)) if a[1].as_string() == Some("i") => {
+                    a[2].as_string().map(String::from)
+                }
+                _ => None,
+            })
+            .collect();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(inputs, vec!["ls\r", "******", "\rid\r"]);
+        assert!(!cast.contains("secret"));
+    }
+
+    #[test]
+    fn input_not_recorded_by_default() {
+        let dir = std::env::temp_dir().join(format!("rssh-record-off-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_name = dir.join("data").to_string_lossy().into_owned();
+
+        let mut r = Recorder::start(&file_name, "10.0.0.1", "root", false).unwrap();
+        r.input(b"ls\r");
+        let path = cast_path(&file_name, r.id());
+        r.finish("ok");
+
+        let cast = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(cast.lines().count(), 1);
+    }
+}
@@ -0,0 +1,122 @@
+use super::rdata;
+use super::result;
+use super::secret::Secret;
+use super::ssh;
+use super::utils;
+
+// 缺省修改密码的命令: root 用 chpasswd, 其他用户用 passwd 从标准输入读取新旧密码
+// printf 是 shell 内建命令, 密码不会出现在远程的进程列表中
+// 注意: 很多系统的 passwd 只从 /dev/tty 读取密码, 远程命令没有终端, 非 root 用户的缺省命令会失败
+// 这时需要用 --cmd 指定从标准输入读取密码的命令, 如 RHEL 的 printf '%s\n' {new} | passwd --stdin
+const ROOT_CMD: &str = "printf '%s:%s\\n' {user} {new} | chpasswd";
+const USER_CMD: &str = "printf '%s\\n%s\\n%s\\n' {old} {new} {new} | passwd";
+
+// 修改结果
+pub enum Outcome {
+    Ok,
+    ChangeFailed(String), // 远程修改失败, 密码未变
+    VerifyFailed(String), // 修改命令成功但新密码无法登录, 远程密码状态未知
+}
+
+/* 登录远程主机修改密码, 并用新密码重新登录验证
+ * 新旧密码通过 ssh 的标准输入传给远程命令, 不出现在本机与远程的命令行参数中 */
+pub fn rotate(pwd: &rdata::Pwd, new: &str, template: &str, opts: &ssh::Options) -> Outcome {
+    let ip_user = format!("{}@{}", pwd.user, pwd.ip);
+    let input = match input(&pwd.password, new) {
+        Ok(i) => i,
+        Err(e) => return Outcome::ChangeFailed(e.msg),
+    };
+    let cmd = command(template, &pwd.user);
+
+    if let Err(e) = ssh::ssh_input(&ip_user, &pwd.password, &cmd, &input, opts) {
+        return Outcome::ChangeFailed(e.msg);
+    }
+    match ssh::ssh(&ip_user, new, "true", opts) {
+        Ok(()) => Outcome::Ok,
+        Err(e) => Outcome::VerifyFailed(e.msg),
+    }
+}
+
+/* 生成远程命令: 先从标准输入读出旧密码与新密码两行, 模板中的 {old} {new} 替换为对应的变量
+ * {user} 替换为转义后的用户名 */
+pub fn command(template: &str, user: &str) -> String {
+    let template = match template {
+        "" if user == "root" => ROOT_CMD,
+        "" => USER_CMD,
+        t => t,
+    };
+    let script = format!(
+        "IFS= read -r old && IFS= read -r new && {}",
+        template
+            .replace("{user}", &utils::shell_quote(user))
+            .replace("{old}", "\"$old\"")
+            .replace("{new}", "\"$new\"")
+    );
+    format!("sh -c {}", utils::shell_quote(&script))
+}
+
+// 远程命令的标准输入, 每行一个密码, 因此密码中不能有换行
+fn input(old: &str, new: &str) -> Result<Secret, result::MyErr> {
+    if old.contains('\n') || new.contains('\n') {
+        return Err(result::MyErr {
+            msg: String::from("密码中包含换行, 不能远程修改"),
+        });
+    }
+    Ok(Secret::new(format!("{}\n{}\n", old, new)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    // 在本机用 sh 执行生成的命令, 返回标准输出
+    fn run(cmd: &str, input: &str) -> String {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let out = child.wait_with_output().unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    }
+
+    #[test]
+    fn user_substitution() {
+        assert_eq!(
+            command("passwd {user}", "bob"),
+            r#"sh -c 'IFS= read -r old && IFS= read -r new && passwd '\''bob'\'''"#
+        );
+        assert!(command("", "root").contains("chpasswd"));
+        assert!(command("", "bob").ends_with("| passwd'"));
+    }
+
+    #[test]
+    fn user_with_shell_metacharacters() {
+        let user = r#"a'b; touch /tmp/x $(id) `id` "c""#;
+        let out = run(&command("printf '%s' {user}", user), "old\nnew\n");
+        assert_eq!(out, user);
+    }
+
+    #[test]
+    fn passwords_read_from_stdin() {
+        let cmd = command("printf '%s|%s|%s' {user} {old} {new}", "root");
+        assert_eq!(run(&cmd, "o' $x\nn;`id`\n"), "root|o' $x|n;`id`");
+    }
+
+    #[test]
+    fn input_layout() {
+        assert_eq!(&*input("old", "new").unwrap(), "old\nnew\n");
+        assert!(input("o\nld", "new").is_err());
+        assert!(input("old", "ne\nw").is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{compiler_fence, AtomicBool, Ordering};
+use std::sync::Mutex;
+
+// mlockall 锁定了之后分配的内存时, Secret 不再单独锁定与解锁
+static LOCKED_ALL: AtomicBool = AtomicBool::new(false);
+// Secret 锁定的内存页 -> 页中的 Secret 个数; mlock 不计数, 同一页的最后一个 Secret 释放时才解锁
+static PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/* 进程加固: 禁止 core dump 与 ptrace 读取内存, 锁定内存页避免被换出
+ * 有锁定限制且没有 CAP_IPC_LOCK 时 MCL_FUTURE 会使超过限制的内存分配失败, 只锁定当前的内存页
+ * mlockall 失败(超过锁定限制)时由 Secret 锁定各自的内存页 */
+pub fn harden() {
+    unsafe {
+        let zero = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        libc::setrlimit(libc::RLIMIT_CORE, &zero);
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+
+        let mut memlock = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        let unlimited = libc::geteuid() == 0
+            || (libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut memlock) == 0
+                && memlock.rlim_cur == libc::RLIM_INFINITY);
+        if unlimited && libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) == 0 {
+            LOCKED_ALL.store(true, Ordering::SeqCst);
+            return;
+        }
+        // 只锁定当前的内存页; 超过锁定限制失败时不影响运行, LOCKED_ALL 为 false, 密码由 Secret 各自锁定
+        libc::mlockall(libc::MCL_CURRENT);
+    }
+}
+
+/* 清零内存, 不会被编译器优化掉 */
+pub fn wipe(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { std::ptr::write_volatile(b, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/* 清零字符串的整个缓冲区(包括未使用的容量)并清空 */
+pub fn wipe_string(s: &mut String) {
+    unsafe { wipe_vec(s.as_mut_vec()) };
+}
+
+/* 清零字节数组的整个缓冲区并清空 */
+pub fn wipe_vec(v: &mut Vec<u8>) {
+    let ptr = v.as_mut_ptr();
+    for i in 0..v.capacity() {
+        unsafe { std::ptr::write_volatile(ptr.add(i), 0) };
+    }
+    v.clear();
+    compiler_fence(Ordering::SeqCst);
+}
+
+// 内存所在的各页的起始地址
+fn pages(ptr: *const u8, len: usize) -> impl Iterator<Item = usize> {
+    let size = page_size();
+    let start = ptr as usize / size * size;
+    (start..ptr as usize + len).step_by(size)
+}
+
+fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        n if n > 0 => n as usize,
+        _ => 4096,
+    }
+}
+
+// 尽量锁定内存页, 避免被换出; 失败(超过锁定限制)时忽略
+fn lock(ptr: *const u8, len: usize) {
+    if len == 0 || LOCKED_ALL.load(Ordering::SeqCst) {
+        return;
+    }
+    let mut locked = PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for p in pages(ptr, len) {
+        let n = locked.entry(p).or_insert(0);
+        if *n == 0 {
+            unsafe { libc::mlock(p as *const libc::c_void, page_size()) };
+        }
+        *n += 1;
+    }
+}
+
+// 释放时解锁, 页中没有其他 Secret 时才解锁, 避免占满锁定限制
+fn unlock(ptr: *const u8, len: usize) {
+    if len == 0 || LOCKED_ALL.load(Ordering::SeqCst) {
+        return;
+    }
+    let mut locked = PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for p in pages(ptr, len) {
+        if let Some(n) = locked.get_mut(&p) {
+            *n -= 1;
+            if *n == 0 {
+                locked.remove(&p);
+                unsafe { libc::munlock(p as *const libc::c_void, page_size()) };
+            }
+        }
+    }
+}
+
+/* 密码等敏感字符串: 内存页锁定, 释放时清零
+ * 不提供修改操作, 缓冲区不会重新分配而留下副本 */
+#[derive(Default)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(s: String) -> Secret {
+        lock(s.as_ptr(), s.capacity());
+        Secret(s)
+    }
+}
+
+impl std::ops::Deref for Secret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Clone for Secret {
+    fn clone(&self) -> Secret {
+        Secret::new(self.0.clone())
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(s: &str) -> Secret {
+        Secret::new(s.to_string())
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl AsRef<str> for Secret {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        let (ptr, len) = (self.0.as_ptr(), self.0.capacity());
+        wipe_string(&mut self.0);
+        unlock(ptr, len);
+    }
+}
+
+/* 表格的每个单元转为 Secret, 用于含密码的输出, 用完即清零 */
+pub fn rows(rows: Vec<Vec<String>>) -> Vec<Vec<Secret>> {
+    rows.into_iter()
+        .map(|r| r.into_iter().map(Secret::new).collect())
+        .collect()
+}
+
+/* 释放时清零的字节数组, 用于派生的密钥等 */
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    pub fn new(v: Vec<u8>) -> SecretBytes {
+        lock(v.as_ptr(), v.capacity());
+        SecretBytes(v)
+    }
+}
+
+impl std::ops::Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        let (ptr, len) = (self.0.as_ptr(), self.0.capacity());
+        wipe_vec(&mut self.0);
+        unlock(ptr, len);
+    }
+}
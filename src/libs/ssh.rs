@@ -1,28 +1,197 @@
 use libc::c_char;
 use libc::c_int;
+use libc::c_void;
+use libc::size_t;
 use std::ffi::CString;
 
+use super::hostkey;
+use super::record;
 use super::result;
+use super::secret;
+
+// run_pty 的录像回调
+type RecordCb = extern "C" fn(*mut c_void, c_int, *const c_char, size_t);
 
 #[link(name = "sshpass", kind = "static")]
 extern "C" {
-    fn run_main(ip_user: *const c_char, password: *const c_char, cmd: *const c_char) -> c_int;
+    fn run_args(password: *const c_char, argc: c_int, argv: *const *const c_char) -> c_int;
+    fn run_pty(
+        password: *const c_char,
+        esc_password: *const c_char,
+        argc: c_int,
+        argv: *const *const c_char,
+        cb: Option<RecordCb>,
+        ctx: *mut c_void,
+    ) -> c_int;
+}
+
+// 认证成功后由 LocalCommand 输出, 用于 probe 判断结果
+pub const PROBE_OK: &str = "RSSH-PROBE-OK";
+
+// 连接选项
+#[derive(Default)]
+pub struct Options {
+    pub port: u16,                                // 为 0 时使用 ssh 默认端口
+    pub proxy: String,                            // ProxyCommand, 为空时直接连接
+    pub known_hosts: Option<hostkey::KnownHosts>, // 有值时只信任其中的主机 Key
+    pub program: String,                          // ssh 程序, 为空时为 ssh
+    pub timeout: u64,                             // ConnectTimeout(秒), 0 时使用各命令的缺省值
 }
 
-pub fn ssh(ip_user: &str, password: &str, cmd: &str) -> Result<(), result::MyErr> {
-    let re;
-    unsafe {
-        let c_ip_user = CString::new(ip_user).unwrap();
-        let c_password = CString::new(password).unwrap();
-        let c_cmd = CString::new(cmd).unwrap();
+impl Options {
+    fn known_hosts_path(&self) -> &str {
+        self.known_hosts.as_ref().map(|k| k.path.as_str()).unwrap_or("")
+    }
 
-        re = run_main(
-            c_ip_user.as_ptr() as *const i8,
-            c_password.as_ptr() as *const i8,
-            c_cmd.as_ptr() as *const i8,
-        );
+    /* ssh 程序, 没有配置时为 ssh */
+    pub fn program(&self) -> &str {
+        match self.program.is_empty() {
+            true => "ssh",
+            false => self.program.as_str(),
+        }
     }
 
+    /* ConnectTimeout 选项, 没有配置时为 default 秒 */
+    pub fn connect_timeout(&self, default: u64) -> String {
+        match self.timeout {
+            0 => format!("ConnectTimeout={}", default),
+            t => format!("ConnectTimeout={}", t),
+        }
+    }
+}
+
+pub fn ssh(
+    ip_user: &str,
+    password: &str,
+    cmd: &str,
+    opts: &Options,
+) -> Result<(), result::MyErr> {
+    let mut args = base_args(ip_user, opts);
+    args.push(String::from("-o"));
+    args.push(opts.connect_timeout(2));
+    if !cmd.is_empty() {
+        args.push(cmd.to_string());
+    }
+    let re = run(password, &args);
+
+    to_result(re)
+}
+
+/* 执行远程命令, input 作为远程命令的标准输入
+ * 用于传递不能出现在命令行参数中的密码; input 需小于管道缓冲区 */
+pub fn ssh_input(
+    ip_user: &str,
+    password: &str,
+    cmd: &str,
+    input: &str,
+    opts: &Options,
+) -> Result<(), result::MyErr> {
+    let mut args = base_args(ip_user, opts);
+    args.push(String::from("-o"));
+    args.push(opts.connect_timeout(2));
+    args.push(cmd.to_string());
+
+    // 先把 input 写入管道, 再把管道接到标准输入上, ssh 继承后转发给远程命令
+    let mut fds: [c_int; 2] = [0; 2];
+    let re = unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(result::MyErr {
+                msg: String::from("创建管道失败"),
+            });
+        }
+        let n = libc::write(fds[1], input.as_ptr() as *const c_void, input.len());
+        libc::close(fds[1]);
+        let saved = libc::dup(0);
+        if n != input.len() as isize || saved < 0 {
+            libc::close(fds[0]);
+            if saved >= 0 {
+                libc::close(saved);
+            }
+            return Err(result::MyErr {
+                msg: String::from("写入管道失败"),
+            });
+        }
+        libc::dup2(fds[0], 0);
+        libc::close(fds[0]);
+        let re = run(password, &args);
+        libc::dup2(saved, 0);
+        libc::close(saved);
+        re
+    };
+
+    to_result(re)
+}
+
+/* 经过伪终端转发的会话, 用于提权与录像
+ * esc_password 非空时 cmd 需先输出 "[rssh] " 标记行, 之后的 sudo/su 密码提示用 esc_password 应答
+ * rec 非空时记录会话的输入输出 */
+pub fn session(
+    ip_user: &str,
+    password: &str,
+    esc_password: &str,
+    cmd: &str,
+    opts: &Options,
+    rec: Option<&mut record::Recorder>,
+) -> Result<(), result::MyErr> {
+    let mut args = base_args(ip_user, opts);
+    args.push(String::from("-o"));
+    args.push(opts.connect_timeout(2));
+    // 只用保存的密码认证, 与 probe 相同: 密钥、agent 登录不出现密码提示, 伪终端会一直等待提示而不转发输入,
+    // 之后输出中的 "assword" (如密码过期提醒、远程执行 passwd) 还会被当作提示而输入密码
+    for o in &[
+        "PubkeyAuthentication=no",
+        "GSSAPIAuthentication=no",
+        "PreferredAuthentications=password,keyboard-interactive",
+    ] {
+        args.push(String::from("-o"));
+        args.push(o.to_string());
+    }
+    args.push(String::from("-tt"));
+    if !cmd.is_empty() {
+        args.push(cmd.to_string());
+    }
+
+    let c_args: Vec<CString> = args.iter().map(|a| CString::new(a.as_str()).unwrap()).collect();
+    let c_argv: Vec<*const c_char> = c_args.iter().map(|a| a.as_ptr()).collect();
+    let c_password = CString::new(password).unwrap();
+    let c_esc = CString::new(esc_password).unwrap();
+    let ctx = match rec {
+        Some(r) => r as *mut record::Recorder as *mut c_void,
+        None => std::ptr::null_mut(),
+    };
+    let cb: Option<RecordCb> = match ctx.is_null() {
+        true => None,
+        false => Some(on_record),
+    };
+    let re = unsafe {
+        run_pty(
+            c_password.as_ptr(),
+            c_esc.as_ptr(),
+            c_argv.len() as c_int,
+            c_argv.as_ptr(),
+            cb,
+            ctx,
+        )
+    };
+    wipe_c(c_password);
+    wipe_c(c_esc);
+
+    to_result(re)
+}
+
+// run_pty 的录像回调, ctx 为 session 传入的 Recorder
+extern "C" fn on_record(ctx: *mut c_void, kind: c_int, buf: *const c_char, len: size_t) {
+    let rec = unsafe { &mut *(ctx as *mut record::Recorder) };
+    let data = unsafe { std::slice::from_raw_parts(buf as *const u8, len) };
+    if kind == 'i' as c_int {
+        rec.input(data);
+    } else {
+        rec.output(data);
+    }
+}
+
+// 返回码转换为错误信息
+fn to_result(re: i32) -> Result<(), result::MyErr> {
     match re {
         0 => Ok(()),
         1 => Err( result::MyErr{
@@ -46,6 +215,9 @@ pub fn ssh(ip_user: &str, password: &str, cmd: &str) -> Result<(), result::MyErr
         7=>Err(result::MyErr{
             msg: String::from("主机Key已改变")
         }),
+        8 => Err(result::MyErr {
+            msg: String::from("提权密码错误"),
+        }),
         255 => Err(result::MyErr {
             msg: String::from("连接超时"),
         }),
@@ -55,3 +227,90 @@ pub fn ssh(ip_user: &str, password: &str, cmd: &str) -> Result<(), result::MyErr
     }
 }
 
+/* 只做认证, 不执行命令也不打开 shell, 返回 ssh 的退出码
+ * 认证成功后 LocalCommand 输出 PROBE_OK 并结束 ssh */
+pub fn probe(ip_user: &str, password: &str, opts: &Options) -> i32 {
+    let mut args = base_args(ip_user, opts);
+    args.extend(vec![
+        String::from("-N"),
+        String::from("-o"),
+        opts.connect_timeout(5),
+        String::from("-o"),
+        String::from("NumberOfPasswordPrompts=1"),
+        String::from("-o"),
+        String::from("PubkeyAuthentication=no"),
+        String::from("-o"),
+        String::from("PermitLocalCommand=yes"),
+        String::from("-o"),
+        format!("LocalCommand=echo {}; kill $PPID", PROBE_OK),
+    ]);
+    run(password, &args)
+}
+
+/* 作为跳板机把标准输入输出转发到 target(host:port), 供 ProxyCommand 使用 */
+pub fn forward_stdio(ip_user: &str, password: &str, target: &str, opts: &Options) -> i32 {
+    let mut args = base_args(ip_user, opts);
+    args.push(String::from("-W"));
+    args.push(target.to_string());
+    run(password, &args)
+}
+
+/* 端口转发, 不执行命令; 转发失败或连接无响应时退出, 由调用方重连 */
+pub fn tunnel(ip_user: &str, password: &str, specs: &[String], opts: &Options) -> i32 {
+    let mut args = base_args(ip_user, opts);
+    for o in &[
+        opts.connect_timeout(5),
+        String::from("ExitOnForwardFailure=yes"),
+        String::from("ServerAliveInterval=15"),
+        String::from("ServerAliveCountMax=3"),
+    ] {
+        args.push(String::from("-o"));
+        args.push(o.clone());
+    }
+    args.push(String::from("-N"));
+    args.extend_from_slice(specs);
+    run(password, &args)
+}
+
+// ssh 公共参数
+fn base_args(ip_user: &str, opts: &Options) -> Vec<String> {
+    let known_hosts = opts.known_hosts_path();
+    let mut args = vec![opts.program().to_string(), ip_user.to_string()];
+    // 只使用保存的主机 Key, 不读写用户和系统的 known_hosts; 没有保存时连接失败, 由调用方先确认
+    let known_hosts = match known_hosts.is_empty() {
+        true => "/dev/null",
+        false => known_hosts,
+    };
+    for o in &[
+        String::from("StrictHostKeyChecking=yes"),
+        format!("UserKnownHostsFile={}", known_hosts),
+        String::from("GlobalKnownHostsFile=/dev/null"),
+        String::from("UpdateHostKeys=no"),
+    ] {
+        args.push(String::from("-o"));
+        args.push(o.clone());
+    }
+    if opts.port != 0 {
+        args.push(String::from("-p"));
+        args.push(opts.port.to_string());
+    }
+    if !opts.proxy.is_empty() {
+        args.push(String::from("-o"));
+        args.push(format!("ProxyCommand={}", opts.proxy));
+    }
+    args
+}
+
+fn run(password: &str, args: &[String]) -> i32 {
+    let c_args: Vec<CString> = args.iter().map(|a| CString::new(a.as_str()).unwrap()).collect();
+    let c_argv: Vec<*const c_char> = c_args.iter().map(|a| a.as_ptr()).collect();
+    let c_password = CString::new(password).unwrap();
+    let re = unsafe { run_args(c_password.as_ptr(), c_argv.len() as c_int, c_argv.as_ptr()) };
+    wipe_c(c_password);
+    re
+}
+
+// 传给 C 的密码用完即清零
+fn wipe_c(s: CString) {
+    secret::wipe_vec(&mut s.into_bytes());
+}
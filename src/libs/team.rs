@@ -0,0 +1,373 @@
+use super::import;
+use super::my_crypto;
+use super::output;
+use super::result;
+use super::secret;
+use super::secret::{Secret, SecretBytes};
+use super::utils;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use rand::RngCore;
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+// team vault 文件的第一行
+pub const HEADER: &str = "RSSH-TEAM-1";
+
+// 数据密钥: aes 密钥 32 字节 + hmac 密钥 32 字节
+const DATA_KEY_SIZE: usize = 64;
+
+// 一个成员: 名称、X25519 公钥, 以及用其公钥加密的数据密钥
+pub struct Member {
+    pub name: String,
+    pub key: [u8; 32],
+    epk: [u8; 32],
+    wrapped: Vec<u8>,
+}
+
+/* team vault: 条目用数据密钥加密, 数据密钥分别用每个成员的公钥加密
+ * 文本格式, 便于用 git 同步:
+ *   RSSH-TEAM-1
+ *   member 名称 公钥 临时公钥 加密的数据密钥
+ *   data 加密的条目 */
+pub struct Team {
+    pub members: Vec<Member>,
+    data: Vec<u8>,
+}
+
+/* 成员名称不能为空, 不能含空白 */
+pub fn check_name(name: &str) -> Result<(), result::MyErr> {
+    match !name.is_empty() && !name.chars().any(|c| c.is_whitespace()) {
+        true => Ok(()),
+        false => Err(result::MyErr {
+            msg: format!("成员名称 {} 无效", name),
+        }),
+    }
+}
+
+/* 解析 base64 的公钥 */
+pub fn parse_key(s: &str) -> Result<[u8; 32], result::MyErr> {
+    let raw = base64::decode(s).unwrap_or_default();
+    if raw.len() != 32 {
+        return Err(result::MyErr {
+            msg: format!("公钥 {} 无效", s),
+        });
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&raw);
+    Ok(key)
+}
+
+/* base64 的公钥 */
+pub fn encode_key(key: &[u8]) -> String {
+    base64::encode(key)
+}
+
+/* 公钥的指纹, 格式与 ssh-keygen -l 相同 */
+pub fn fingerprint(key: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(key);
+    let mut digest = [0u8; 32];
+    hasher.result(&mut digest);
+    format!(
+        "SHA256:{}",
+        base64::encode_config(digest, base64::STANDARD_NO_PAD)
+    )
+}
+
+/* 读取私钥文件 */
+pub fn read_identity(path: &str) -> Result<SecretBytes, result::MyErr> {
+    let err = || result::MyErr {
+        msg: format!("读取私钥 {} 失败, 请先执行 team keygen", path),
+    };
+    let text = fs::read_to_string(path)
+        .map(Secret::new)
+        .map_err(|_| err())?;
+    let raw = base64::decode(text.trim())
+        .map(SecretBytes::new)
+        .map_err(|_| err())?;
+    if raw.len() != 32 {
+        return Err(err());
+    }
+    Ok(raw)
+}
+
+/* 生成私钥文件, 已存在时不覆盖; 返回是否新生成与公钥 */
+pub fn keygen(path: &str) -> Result<(bool, [u8; 32]), result::MyErr> {
+    if fs::metadata(path).is_ok() {
+        let secret = read_identity(path)?;
+        return Ok((false, my_crypto::x25519_public(&secret)));
+    }
+    let err = || result::MyErr {
+        msg: format!("写入私钥 {} 失败", path),
+    };
+    if let Some(i) = path.rfind('/') {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&path[..i.max(1)])
+            .map_err(|_| err())?;
+    }
+    let mut key = my_crypto::x25519_secret();
+    let secret = SecretBytes::new(key.to_vec());
+    secret::wipe(&mut key);
+    let mut f = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|_| err())?;
+    let text = Secret::new(base64::encode(&*secret));
+    writeln!(f, "{}", &*text).map_err(|_| err())?;
+    Ok((true, my_crypto::x25519_public(&secret)))
+}
+
+// 条目按导出的 json 格式保存, 用 import::parse_json 读取
+fn render(entries: &[import::Entry]) -> Secret {
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|e| {
+            vec![
+                e.ip.clone(),
+                e.user.clone(),
+                e.password.clone().unwrap_or_default(),
+                e.updated.unwrap_or(0).to_string(),
+                match e.port {
+                    0 => String::new(),
+                    p => p.to_string(),
+                },
+                e.alias.join(","),
+                e.tags.join(","),
+            ]
+        })
+        .collect();
+    let header = ["ip", "user", "password", "updated", "port", "alias", "tags"];
+    Secret::new(output::render("json", &header, &secret::rows(rows)))
+}
+
+impl Team {
+    /* 新建只有自己一个成员、没有条目的 team vault */
+    pub fn create(name: &str, secret: &[u8]) -> Result<Team, result::MyErr> {
+        check_name(name)?;
+        let mut team = Team {
+            members: vec![Member {
+                name: name.to_string(),
+                key: my_crypto::x25519_public(secret),
+                epk: [0u8; 32],
+                wrapped: vec![],
+            }],
+            data: vec![],
+        };
+        team.rekey(&[])?;
+        Ok(team)
+    }
+
+    /* 读取 team vault 文件 */
+    pub fn load(file_name: &str) -> Result<Team, result::MyErr> {
+        let content = match fs::read_to_string(file_name) {
+            Ok(c) => c,
+            Err(_) => {
+                return Err(result::MyErr {
+                    msg: format!("打开文件: {} 失败", file_name),
+                })
+            }
+        };
+        let bad = |n: usize| result::MyErr {
+            msg: format!("{} 第 {} 行格式错误", file_name, n),
+        };
+        let mut lines = content.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(result::MyErr {
+                msg: format!("{} 不是 team vault 文件", file_name),
+            });
+        }
+        let mut team = Team {
+            members: vec![],
+            data: vec![],
+        };
+        for (i, line) in lines.enumerate() {
+            let n = i + 2;
+            let f: Vec<&str> = line.split_whitespace().collect();
+            match f.first() {
+                Some(&"member") if f.len() == 5 => team.members.push(Member {
+                    name: f[1].to_string(),
+                    key: parse_key(f[2]).map_err(|_| bad(n))?,
+                    epk: parse_key(f[3]).map_err(|_| bad(n))?,
+                    wrapped: base64::decode(f[4]).map_err(|_| bad(n))?,
+                }),
+                Some(&"data") if f.len() == 2 => {
+                    team.data = base64::decode(f[1]).map_err(|_| bad(n))?
+                }
+                None => {}
+                _ => return Err(bad(n)),
+            }
+        }
+        if team.members.is_empty() || team.data.is_empty() {
+            return Err(result::MyErr {
+                msg: format!("{} 不完整", file_name),
+            });
+        }
+        Ok(team)
+    }
+
+    /* 写入文件: 先写临时文件再改名, 同步目录中不会出现写了一半的文件 */
+    pub fn save(&self, file_name: &str) -> Result<(), result::MyErr> {
+        let mut content = format!("{}\n", HEADER);
+        for m in &self.members {
+            content.push_str(&format!(
+                "member {} {} {} {}\n",
+                &m.name,
+                encode_key(&m.key),
+                base64::encode(m.epk),
+                base64::encode(&m.wrapped)
+            ));
+        }
+        content.push_str(&format!("data {}\n", base64::encode(&self.data)));
+
+        let tmp = utils::temp_path(file_name);
+        let err = || result::MyErr {
+            msg: format!("写入文件 {} 失败", file_name),
+        };
+        let mut f = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .map_err(|_| err())?;
+        if f.write_all(content.as_bytes())
+            .and_then(|_| f.sync_all())
+            .is_err()
+        {
+            let _ = fs::remove_file(&tmp);
+            return Err(err());
+        }
+        fs::rename(&tmp, file_name).map_err(|_| {
+            let _ = fs::remove_file(&tmp);
+            err()
+        })
+    }
+
+    /* 用自己的私钥取出数据密钥, 不是成员时返回错误 */
+    fn data_key(&self, secret: &[u8]) -> Result<SecretBytes, result::MyErr> {
+        let public = my_crypto::x25519_public(secret);
+        let me = match self.members.iter().find(|m| m.key == public) {
+            Some(m) => m,
+            None => {
+                return Err(result::MyErr {
+                    msg: format!("不是成员, 公钥 {}", fingerprint(&public)),
+                })
+            }
+        };
+        match my_crypto::unwrap_key(secret, &me.epk, &me.wrapped) {
+            Some(k) if k.len() == DATA_KEY_SIZE => Ok(SecretBytes::new(k)),
+            _ => Err(result::MyErr {
+                msg: format!("成员 {} 的数据密钥无法解密, 文件已损坏", &me.name),
+            }),
+        }
+    }
+
+    /* 解密全部条目 */
+    pub fn entries(&self, secret: &[u8]) -> Result<Vec<import::Entry>, result::MyErr> {
+        let key = self.data_key(secret)?;
+        let plain = my_crypto::open(&self.data, &key[..32], &key[32..])
+            .and_then(|d| String::from_utf8(d).ok())
+            .map(Secret::new)
+            .ok_or_else(|| result::MyErr {
+                msg: String::from("数据无法解密, 文件已损坏"),
+            })?;
+        let (entries, errors) = import::parse_json(&plain)?;
+        if let Some((n, e)) = errors.first() {
+            return Err(result::MyErr {
+                msg: format!("第 {} 条: {}", n, e),
+            });
+        }
+        Ok(entries)
+    }
+
+    // 生成新的数据密钥加密条目, 并为每个成员重新加密数据密钥
+    fn rekey(&mut self, entries: &[import::Entry]) -> Result<(), result::MyErr> {
+        let mut raw = vec![0u8; DATA_KEY_SIZE];
+        rand::thread_rng().fill_bytes(&mut raw);
+        let key = SecretBytes::new(raw);
+        for m in self.members.iter_mut() {
+            let (epk, wrapped) = my_crypto::wrap_key(&key, &m.key)?;
+            m.epk = epk;
+            m.wrapped = wrapped;
+        }
+        self.data = my_crypto::seal(render(entries).as_bytes(), &key[..32], &key[32..])?;
+        Ok(())
+    }
+
+    /* 添加成员: 只需为新成员加密现有的数据密钥 */
+    pub fn add(&mut self, secret: &[u8], name: &str, key: [u8; 32]) -> Result<(), result::MyErr> {
+        check_name(name)?;
+        if let Some(m) = self.members.iter().find(|m| m.name == name || m.key == key) {
+            return Err(result::MyErr {
+                msg: format!("成员 {} 已存在或使用了相同的公钥", &m.name),
+            });
+        }
+        let data_key = self.data_key(secret)?;
+        let (epk, wrapped) = my_crypto::wrap_key(&data_key, &key)?;
+        self.members.push(Member {
+            name: name.to_string(),
+            key,
+            epk,
+            wrapped,
+        });
+        Ok(())
+    }
+
+    /* 删除成员并更换数据密钥, 被删除的成员无法解密之后的版本
+     * 之前已同步到的旧版本仍可解密, 需要时应同时修改其中的密码 */
+    pub fn remove(&mut self, secret: &[u8], name: &str) -> Result<(), result::MyErr> {
+        let i = match self.members.iter().position(|m| m.name == name) {
+            Some(i) => i,
+            None => {
+                return Err(result::MyErr {
+                    msg: format!("成员 {} 不存在", name),
+                })
+            }
+        };
+        if self.members.len() == 1 {
+            return Err(result::MyErr {
+                msg: String::from("不能删除最后一个成员"),
+            });
+        }
+        let entries = self.entries(secret)?;
+        self.members.remove(i);
+        self.rekey(&entries)
+    }
+
+    /* 合并条目到 team vault, 同一 ip 与用户以修改时间较新的为准
+     * 返回 (新增, 更新, 保留较新的) 条数 */
+    pub fn push(
+        &mut self,
+        secret: &[u8],
+        entries: Vec<import::Entry>,
+    ) -> Result<(usize, usize, usize), result::MyErr> {
+        let mut current = self.entries(secret)?;
+        let (mut added, mut updated, mut kept) = (0, 0, 0);
+        for e in entries {
+            match current
+                .iter()
+                .position(|c| c.ip == e.ip && c.user == e.user)
+            {
+                Some(i) if current[i].updated.unwrap_or(0) > e.updated.unwrap_or(0) => kept += 1,
+                Some(i) => {
+                    updated += 1;
+                    current[i] = e;
+                }
+                None => {
+                    added += 1;
+                    current.push(e);
+                }
+            }
+        }
+        let key = self.data_key(secret)?;
+        self.data = my_crypto::seal(render(&current).as_bytes(), &key[..32], &key[32..])?;
+        Ok((added, updated, kept))
+    }
+}
@@ -0,0 +1,176 @@
+use super::rdata;
+use super::result;
+use super::ssh;
+use super::utils;
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 重连等待时间(秒), 连接失败时翻倍, 不超过 MAX_WAIT
+const MIN_WAIT: u64 = 5;
+const MAX_WAIT: u64 = 60;
+
+// 一个运行中的隧道, id 为进程号
+pub struct Tunnel {
+    pub id: u32,
+    pub started: u64,
+    pub target: String,
+    pub specs: String,
+}
+
+/* 隧道状态目录: 数据文件同级的 ${file_name}.tunnels */
+pub fn tunnel_dir(file_name: &str) -> String {
+    format!("{}.tunnels", file_name)
+}
+
+/* 检查转发参数: -L/-R 为 [bind:]port:host:hostport 或 [bind:]port:socket, -D 为 [bind:]port */
+pub fn check_spec(flag: &str, spec: &str) -> Result<(), result::MyErr> {
+    let n = spec.split(':').count();
+    let ok = match flag {
+        "-L" | "-R" => (2..=4).contains(&n),
+        "-D" => n <= 2 && spec.rsplit(':').next().unwrap().parse::<u16>().is_ok(),
+        _ => false,
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(result::MyErr {
+            msg: format!("转发参数错误: {} {}", flag, spec),
+        })
+    }
+}
+
+/* 建立隧道并在断开后重连, 密码错误或主机 Key 不符时退出
+ * specs 为 ssh 参数, 如 ["-L", "5432:db:5432"]; 每次连接成功保持后调用 used */
+pub fn run(
+    file_name: &str,
+    pwd: &rdata::Pwd,
+    specs: &[String],
+    opts: &ssh::Options,
+    used: &mut dyn FnMut(),
+) -> i32 {
+    let ip_user = format!("{}@{}", pwd.user, pwd.ip);
+    let _state = register(file_name, &ip_user, specs);
+
+    let mut wait = MIN_WAIT;
+    loop {
+        let started = Instant::now();
+        let code = ssh::tunnel(&ip_user, &pwd.password, specs, opts);
+        match code {
+            5 => {
+                println!("{} 密码错误", &ip_user);
+                return code;
+            }
+            6 | 7 => {
+                println!("{} 主机Key错误", &ip_user);
+                return code;
+            }
+            _ => {}
+        }
+        // 正常结束或保持过一段时间说明连接成功过
+        if code == 0 || started.elapsed() > Duration::from_secs(MIN_WAIT) {
+            used();
+        }
+        // 连接保持过一段时间说明不是持续失败, 重新计算等待时间
+        if started.elapsed() > Duration::from_secs(MAX_WAIT) {
+            wait = MIN_WAIT;
+        }
+        println!("{} 隧道断开({}), {} 秒后重连", &ip_user, code, wait);
+        thread::sleep(Duration::from_secs(wait));
+        wait = (wait * 2).min(MAX_WAIT);
+    }
+}
+
+/* 列出运行中的隧道, 顺便删除已退出进程的记录 */
+pub fn list(file_name: &str) -> Vec<Tunnel> {
+    let mut tunnels = Vec::new();
+    let entries = match fs::read_dir(tunnel_dir(file_name)) {
+        Ok(e) => e,
+        Err(_) => return tunnels,
+    };
+    for entry in entries.flatten() {
+        let id: u32 = match entry.file_name().to_string_lossy().parse() {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        if !is_tunnel(id) {
+            let _ = fs::remove_file(entry.path());
+            continue;
+        }
+        let content = fs::read_to_string(entry.path()).unwrap_or_default();
+        let fields: Vec<&str> = content.trim_end().splitn(3, '\t').collect();
+        if fields.len() != 3 {
+            continue;
+        }
+        tunnels.push(Tunnel {
+            id,
+            started: fields[0].parse().unwrap_or(0),
+            target: fields[1].to_string(),
+            specs: fields[2].to_string(),
+        });
+    }
+    tunnels.sort_by_key(|t| t.started);
+    tunnels
+}
+
+/* 结束隧道进程, ssh 在伪终端关闭后随之退出 */
+pub fn stop(file_name: &str, id: u32) -> Result<(), result::MyErr> {
+    // 只能结束 list 列出的进程, list 已确认进程仍是隧道, 不会误杀重用了 pid 的其他进程
+    if !list(file_name).iter().any(|t| t.id == id) {
+        return Err(result::MyErr {
+            msg: format!("隧道 {} 不存在", id),
+        });
+    }
+    if unsafe { libc::kill(id as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(result::MyErr {
+            msg: format!("结束隧道 {} 失败", id),
+        });
+    }
+    let _ = fs::remove_file(format!("{}/{}", tunnel_dir(file_name), id));
+    Ok(())
+}
+
+// 进程存在且是隧道: 命令行参数中有 forward 或后台运行的 __tunnel
+// 隧道异常退出后 pid 可能被其他进程重用, 只检查进程存在会误杀
+fn is_tunnel(pid: u32) -> bool {
+    match fs::read(format!("/proc/{}/cmdline", pid)) {
+        Ok(cmdline) => cmdline
+            .split(|b| *b == 0)
+            .any(|arg| arg == b"__tunnel" || arg == b"forward"),
+        Err(_) => false,
+    }
+}
+
+// 记录当前进程的隧道, 正常退出时删除; 被结束时由 list 清理
+struct State {
+    path: String,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn register(file_name: &str, ip_user: &str, specs: &[String]) -> Option<State> {
+    let dir = tunnel_dir(file_name);
+    if fs::metadata(&dir).is_err() && fs::DirBuilder::new().mode(0o700).create(&dir).is_err() {
+        return None;
+    }
+    let path = format!("{}/{}", dir, std::process::id());
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut f| writeln!(f, "{}\t{}\t{}", utils::now(), ip_user, specs.join(" ")));
+    match written {
+        Ok(()) => Some(State { path }),
+        Err(_) => None,
+    }
+}
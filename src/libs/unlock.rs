@@ -0,0 +1,122 @@
+use super::result;
+
+// 可以设置解锁策略的操作
+pub const OPS: [&str; 8] = [
+    "login", "exec", "get", "list", "add", "delete", "import", "export",
+];
+
+// 解锁策略: name 为标签名, default 为没有策略的标签的主机; ops 为需要验证密码的操作
+#[derive(RustcDecodable, RustcEncodable, Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub ops: Vec<String>,
+}
+
+// 解锁凭证的环境变量: __proxy 由 ssh 启动, ssh 会关闭继承的其他文件描述符, 只能经环境变量传递
+// 只在使用 __proxy 的 ssh 启动前设置; __tunnel 的凭证经标准输入传递
+pub const PROOF_ENV: &str = "RSSH_UNLOCK_PROOF";
+
+// 解锁凭证的有效期(秒), 每次启动使用 __proxy 的 ssh 前重新生成
+pub const PROOF_TTL: u64 = 60;
+
+/* pid 是否为当前进程或其祖先进程: 凭证只对生成时绑定的进程及其子孙有效 */
+pub fn ancestor(pid: u32) -> bool {
+    let mut p = std::process::id();
+    // 进程链不会太长, 防止 /proc 异常时死循环
+    for _ in 0..64 {
+        if p == pid {
+            return true;
+        }
+        p = match parent(p) {
+            Some(pp) if pp > 0 => pp,
+            _ => return false,
+        };
+    }
+    false
+}
+
+// /proc/<pid>/stat 的第 4 个字段为父进程; 进程名在括号中, 可能含空格
+fn parent(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/* 没有设置 default 策略时, 按 -f/-k 的 flag: 0 特权操作才需要密码, 1 大部分操作都需要 */
+pub fn legacy(version: u8) -> Vec<String> {
+    let ops: &[&str] = match version {
+        1 => &["login", "get", "list", "add", "delete", "import", "export"],
+        _ => &["get", "add", "delete", "import", "export"],
+    };
+    ops.iter().map(|o| o.to_string()).collect()
+}
+
+/* 解析逗号分隔的操作, all 为全部, none 为都不需要 */
+pub fn parse_ops(s: &str) -> Result<Vec<String>, result::MyErr> {
+    match s {
+        "all" => return Ok(OPS.iter().map(|o| o.to_string()).collect()),
+        "none" => return Ok(vec![]),
+        _ => {}
+    }
+    let mut ops: Vec<String> = Vec::new();
+    for o in s.split(',').filter(|o| !o.is_empty()) {
+        if !OPS.contains(&o) {
+            return Err(result::MyErr {
+                msg: format!("未知操作 {}, 可选: {}, all, none", o, OPS.join(",")),
+            });
+        }
+        if !ops.iter().any(|x| x == o) {
+            ops.push(o.to_string());
+        }
+    }
+    if ops.is_empty() {
+        return Err(result::MyErr {
+            msg: String::from("未指定操作"),
+        });
+    }
+    // 按 OPS 的顺序保存, 便于查看
+    ops.sort_by_key(|o| OPS.iter().position(|x| x == o));
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ops_keywords() {
+        assert_eq!(parse_ops("all").unwrap(), OPS.to_vec());
+        assert!(parse_ops("none").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_ops_sorted_and_deduplicated() {
+        assert_eq!(
+            parse_ops("export,get,login,get").unwrap(),
+            vec!["login", "get", "export"]
+        );
+        assert_eq!(parse_ops("list,").unwrap(), vec!["list"]);
+    }
+
+    #[test]
+    fn parse_ops_errors() {
+        assert!(parse_ops("").is_err());
+        assert!(parse_ops(",").is_err());
+        assert!(parse_ops("get,forward").is_err());
+        assert!(parse_ops("ALL").is_err());
+    }
+
+    #[test]
+    fn ancestor_chain() {
+        assert!(ancestor(std::process::id()));
+        assert!(ancestor(parent(std::process::id()).unwrap()));
+        assert!(!ancestor(u32::MAX));
+    }
+
+    #[test]
+    fn legacy_includes_add() {
+        assert!(legacy(0).iter().any(|o| o == "add"));
+        assert!(legacy(1).iter().any(|o| o == "add"));
+        assert!(!legacy(0).iter().any(|o| o == "login"));
+    }
+}
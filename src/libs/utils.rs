@@ -0,0 +1,75 @@
+use super::result;
+use super::secret::Secret;
+
+use std::io::prelude::*;
+
+/* 当前时间, unix 秒 */
+pub fn now() -> u64 {
+    time::get_time().sec as u64
+}
+
+/* 格式化 unix 秒为本地时间, 0 表示从未记录 */
+pub fn fmt_time(sec: u64) -> String {
+    if sec == 0 {
+        return String::from("-");
+    }
+    let tm = time::at(time::Timespec::new(sec as i64, 0));
+    match time::strftime("%Y-%m-%d %H:%M", &tm) {
+        Ok(s) => s,
+        Err(_) => sec.to_string(),
+    }
+}
+
+/* 询问确认, 只有输入 y 才返回 true */
+pub fn confirm(prompt: &str) -> bool {
+    print!("{}", prompt);
+    std::io::stdout().flush().unwrap();
+    let mut cho = String::new();
+    match std::io::stdin().read_line(&mut cho) {
+        Ok(_) => cho.trim() == "y" || cho.trim() == "Y",
+        Err(_) => false,
+    }
+}
+
+/* 读取口令, twice 为 true 时需要输入两次 */
+pub fn read_passphrase(prompt: &str, twice: bool) -> Result<Secret, result::MyErr> {
+    let first = match rpassword::prompt_password_stdout(prompt) {
+        Ok(p) => Secret::new(p),
+        Err(_) => {
+            return Err(result::MyErr {
+                msg: String::from("读取口令失败"),
+            })
+        }
+    };
+    if first.is_empty() {
+        return Err(result::MyErr {
+            msg: String::from("口令不能为空"),
+        });
+    }
+    if twice {
+        match rpassword::prompt_password_stdout("请再次输入: ").map(Secret::new) {
+            Ok(p) if *p == *first => {}
+            _ => {
+                return Err(result::MyErr {
+                    msg: String::from("两次输入不一致"),
+                })
+            }
+        }
+    }
+    Ok(first)
+}
+
+/* 同目录下的临时文件名, 包含进程号与随机数, 多个进程同时写入时不会冲突 */
+pub fn temp_path(file_name: &str) -> String {
+    format!(
+        "{}.{}.{:08x}.tmp",
+        file_name,
+        std::process::id(),
+        rand::random::<u32>()
+    )
+}
+
+/* 单引号转义, 用于拼接 shell 命令 */
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
@@ -0,0 +1,87 @@
+use super::config::Config;
+use super::result;
+
+use std::fs;
+use std::os::unix::fs::DirBuilderExt;
+
+// 原有的数据文件: 配置的 vault, 未配置时为可执行文件自身
+pub const BASE: &str = "default";
+
+// 记录当前 vault 名称的文件, 在 vault 目录下
+const CURRENT_FILE: &str = ".current";
+
+/* vault 名称只能由字母、数字、- 与 _ 组成, 同目录下的 .audit、.bak 等文件不会被当作 vault */
+pub fn check_name(name: &str) -> Result<(), result::MyErr> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(result::MyErr {
+            msg: format!("vault 名称 {} 无效, 只能使用字母、数字、- 与 _", name),
+        }),
+    }
+}
+
+/* vault 的数据文件路径, exe 为可执行文件路径 */
+pub fn path(config: &Config, exe: &str, name: &str) -> String {
+    match name {
+        BASE if config.vault.is_empty() => exe.to_string(),
+        BASE => config.vault.clone(),
+        _ => format!("{}/{}", config.vault_dir, name),
+    }
+}
+
+/* vault use 设置的当前 vault, 没有设置时为 default */
+pub fn current(config: &Config) -> String {
+    let name = fs::read_to_string(format!("{}/{}", config.vault_dir, CURRENT_FILE))
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    match check_name(&name) {
+        Ok(()) => name,
+        Err(_) => String::from(BASE),
+    }
+}
+
+/* 设置当前 vault, default 为恢复使用原有的数据文件 */
+pub fn set_current(config: &Config, name: &str) -> Result<(), result::MyErr> {
+    let file = format!("{}/{}", config.vault_dir, CURRENT_FILE);
+    if name == BASE {
+        let _ = fs::remove_file(&file);
+        return Ok(());
+    }
+    create_dir(config)?;
+    fs::write(&file, format!("{}\n", name)).map_err(|_| result::MyErr {
+        msg: format!("写入 {} 失败", &file),
+    })
+}
+
+/* 创建 vault 目录, 只有当前用户可访问 */
+pub fn create_dir(config: &Config) -> Result<(), result::MyErr> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&config.vault_dir)
+        .map_err(|_| result::MyErr {
+            msg: format!("创建目录 {} 失败", &config.vault_dir),
+        })
+}
+
+/* 所有 vault 名称, default 在最前, 其余按名称排序 */
+pub fn list(config: &Config) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(&config.vault_dir)
+        .map(|dir| {
+            dir.flatten()
+                .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|n| n != BASE && check_name(n).is_ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names.insert(0, String::from(BASE));
+    names
+}
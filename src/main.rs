@@ -1,19 +1,62 @@
 mod libs;
+use libs::audit;
+use libs::check;
+use libs::config;
+use libs::doctor;
+use libs::gen;
+use libs::hostkey;
+use libs::my_crypto;
+use libs::output;
 use libs::params;
 use libs::rdata;
+use libs::record;
+use libs::result;
+use libs::rotate;
+use libs::secret;
+use libs::secret::Secret;
 use libs::ssh;
+use libs::team;
+use libs::tunnel;
+use libs::unlock;
+use libs::utils;
+use libs::vault;
 
 use std::io::prelude::*;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use rpassword::read_password;
 
+// 本进程已验证口令, 或持有父进程的有效解锁凭证; 启动 __proxy/__tunnel 子进程时据此生成凭证
+static UNLOCKED: AtomicBool = AtomicBool::new(false);
+// 环境变量中本进程凭证的过期时间
+static PROOF_EXPIRY: AtomicU64 = AtomicU64::new(0);
+
 fn main() {
-    // 二进制文件 路径
-    let path_buf = std::env::current_exe().unwrap();
-    let path = path_buf.into_os_string().into_string().unwrap();
+    // 禁止 core dump, 锁定内存, 密码不会写入转储文件或交换分区
+    secret::harden();
+
+    // 二进制文件 路径; 启动后文件被其他进程保存替换时, 链接目标带有 " (deleted)"
+    let path = match std::env::current_exe().map(|p| p.into_os_string().into_string()) {
+        Ok(Ok(p)) => p.trim_end_matches(" (deleted)").to_string(),
+        _ => {
+            println!("无法获取程序路径");
+            std::process::exit(1);
+        }
+    };
+
+    // 配置文件
+    let config = match config::load() {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}", e.msg);
+            std::process::exit(1);
+        }
+    };
 
     // 命令行参数解析
-    let params = match params::Params::parse(&mut std::env::args(), path) {
+    let mut params = match params::Params::parse(&mut std::env::args(), path, config) {
         Ok(p) => p,
         Err(e) => {
             params::Params::help(&e.msg);
@@ -24,8 +67,22 @@ fn main() {
     /* 初始化 */
     if params.option == 'f' {
         if verify("init") {
+            // 已有数据时需要确认, 避免误操作清空
+            match doctor::guard_init(&params) {
+                Ok(true) => {}
+                Ok(false) => {
+                    println!("已取消");
+                    return;
+                }
+                Err(e) => {
+                    println!("{}", e.msg);
+                    return;
+                }
+            }
             match rdata::Rdata::init(&params.file_path, &params.pwd, params.version) {
-                Ok(()) => {}
+                Ok(()) => {
+                    audit(&params, "init", "", "ok");
+                }
                 Err(e) => println!("{}", e.msg),
             }
         } else {
@@ -34,8 +91,84 @@ fn main() {
         return;
     }
 
+    /* check 的子进程, 不读取数据文件 */
+    if params.option == 'Q' {
+        std::process::exit(check::probe_main(
+            &params.ip,
+            params.port,
+            &params.cmd,
+            params.config.ssh_options(),
+        ));
+    }
+
+    /* 查看配置, 不读取数据文件 */
+    if params.option == 'O' {
+        let rows = params.config.settings(&params.file_path);
+        let header = ["setting", "value", "source"];
+        if &params.format == "plain" {
+            output::print_aligned(&header, &rows);
+        } else {
+            output::print(&params.format, &header, &rows);
+        }
+        return;
+    }
+
+    /* vault 管理, 读取各自的数据文件 */
+    if params.option == 'V' {
+        if let Err(e) = vault_command(&params) {
+            println!("{}", e.msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    /* 检查、恢复数据文件, 数据文件可能无法读取 */
+    if params.option == 'D' {
+        if let Err(e) = doctor::run(&params) {
+            println!("{}", e.msg);
+            std::process::exit(1);
+        }
+        if &params.action == "repair" {
+            audit(&params, "doctor repair", "", "ok");
+        }
+        return;
+    }
+
+    /* team vault 的私钥与成员管理, 不读取数据文件; push/pull 在下面处理 */
+    if params.option == 'W' && &params.action != "push" && &params.action != "pull" {
+        if let Err(e) = team_command(&params) {
+            println!("{}", e.msg);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // 修改数据的命令在读取前加写锁, 直到退出, 多个进程的修改依次进行
+    let _lock = match writes(&params) {
+        true => match rdata::lock(&params.file_path) {
+            Ok(l) => Some(l),
+            Err(e) => {
+                println!("{}", e.msg);
+                std::process::exit(1);
+            }
+        },
+        false => None,
+    };
+
     let mut rdata = match rdata::Rdata::get(&params.file_path) {
         Ok(r) => r,
+        Err(e) if doctor::damaged(&params.file_path) => {
+            // 数据还在, 不要提示 -f
+            println!(
+                "数据文件 {} 无法读取: {}, 请执行 rssh doctor 检查或恢复",
+                &params.file_path, e.msg
+            );
+            std::process::exit(1);
+        }
+        Err(_) if params.vault != vault::BASE => {
+            println!("vault {} 不存在, 请先执行 vault create", &params.vault);
+            return;
+        }
         Err(_) => {
             println!("请先使用 -f 选项执行初始化");
             return;
@@ -44,39 +177,98 @@ fn main() {
 
     match params.option {
         'c' => {
-            // 非特权：修改密码
-            if rdata.version > 0 {
-                if !verify(&rdata.pwd) {
-                    println!("密码错误");
-                    return;
-                }
+            // 修改密码, 修改标签时也按新标签的策略
+            let target = format!("{}@{}", &params.user, &params.ip);
+            if (need_unlock(&rdata, &params, "add", &params.ip)
+                || (!params.tags.is_empty() && rdata.need_unlock_tags("add", &params.tags)))
+                && !verify(&rdata.pwd)
+            {
+                println!("密码错误");
+                audit(&params, "set", &target, "密码错误");
+                return;
+            }
+            if params.generate {
+                params.password = match policy_for(&rdata, &params.ip, &params).generate() {
+                    Ok(p) => p,
+                    Err(e) => {
+                        println!("{}", e.msg);
+                        return;
+                    }
+                };
             }
             match rdata::Pwd::change_password(&mut rdata, &params) {
-                Ok(()) => {}
-                Err(e) => println!("{}", e.msg),
+                Ok(()) => {
+                    if params.generate {
+                        println!("{}", &params.password);
+                    }
+                    audit(&params, "set", &target, "ok");
+                }
+                Err(e) => {
+                    println!("{}", e.msg);
+                    audit(&params, "set", &target, &e.msg);
+                }
             }
         }
         'l' => {
-            // 特权：批量导入
-            if verify(&rdata.pwd) {
-                match rdata.import_pwd(&params.in_file, &params) {
-                    Ok(()) => {}
-                    Err(e) => println!("{}", e.msg),
-                };
-            } else {
-                println!("口令错误");
+            // 批量导入
+            let re = match !need_unlock(&rdata, &params, "import", "") || verify(&rdata.pwd) {
+                true => rdata.import_pwd(&params.in_file, &params),
+                false => Err(result::MyErr {
+                    msg: String::from("口令错误"),
+                }),
+            };
+            if let Err(e) = &re {
+                println!("{}", e.msg);
+            }
+            let status = re.map(|_| String::from("ok")).unwrap_or_else(|e| e.msg);
+            audit(&params, "import", &params.in_file, &status);
+        }
+        'W' => {
+            // team vault 与当前 vault 之间同步条目
+            let op = match params.action.as_str() {
+                "push" => "export",
+                _ => "import",
+            };
+            let re = match !need_unlock(&rdata, &params, op, "") || verify(&rdata.pwd) {
+                true => team_sync(rdata, &params),
+                false => Err(result::MyErr {
+                    msg: String::from("口令错误"),
+                }),
+            };
+            if let Err(e) = &re {
+                println!("{}", e.msg);
             }
+            let status = re.map(|_| String::from("ok")).unwrap_or_else(|e| e.msg);
+            let command = format!("team {}", &params.action);
+            audit(&params, &command, &params.in_file, &status);
+        }
+        'M' => {
+            // 三方合并另一份数据文件
+            let re = match !need_unlock(&rdata, &params, "import", "") || verify(&rdata.pwd) {
+                true => merge_vault(rdata, &params),
+                false => Err(result::MyErr {
+                    msg: String::from("口令错误"),
+                }),
+            };
+            if let Err(e) = &re {
+                println!("{}", e.msg);
+            }
+            let status = re.map(|_| String::from("ok")).unwrap_or_else(|e| e.msg);
+            audit(&params, "merge", &params.in_file, &status);
         }
         'e' => {
-            // 特权：导出用户密码
-            if verify(&rdata.pwd) {
-                match rdata.export_pwd(&params.in_file) {
-                    Ok(()) => {}
-                    Err(e) => println!("{}", e.msg),
-                };
-            } else {
-                println!("口令错误");
+            // 导出用户密码
+            let re = match !need_unlock(&rdata, &params, "export", "") || verify(&rdata.pwd) {
+                true => rdata.export_pwd(&params.in_file, &params),
+                false => Err(result::MyErr {
+                    msg: String::from("口令错误"),
+                }),
+            };
+            if let Err(e) = &re {
+                println!("{}", e.msg);
             }
+            let status = re.map(|_| String::from("ok")).unwrap_or_else(|e| e.msg);
+            audit(&params, "export", &params.in_file, &status);
         }
         'g' => {
             // 半特权：获取密码
@@ -87,16 +279,40 @@ fn main() {
                     std::process::exit(1);
                 }
             };
+            let target = format!("{}@{}", pwd.user, pwd.ip);
 
+            let row = vec![
+                Secret::from(pwd.ip.as_str()),
+                Secret::from(pwd.user.as_str()),
+                pwd.password.clone(),
+            ];
+            let locked = need_unlock(&rdata, &params, "get", &pwd.ip);
             if &params.key == "yikeyike" {
-                // 用户其他程序调用, 密码没有空格，因此空格做分隔符
-                println!("{} {} {}", pwd.user, pwd.ip, pwd.password);
-            } else if verify(&rdata.pwd) {
-                println!("{}@{}\n{}", pwd.user, pwd.ip, pwd.password);
+                // 用户其他程序调用, 不能输入密码: 只有解锁策略不要求 get 验证的主机才输出
+                // 密码没有空格, 因此空格做分隔符
+                if locked {
+                    eprintln!(
+                        "{} 获取密码需要验证, 脚本调用需先用 unlock set 为其标签去掉 get",
+                        &target
+                    );
+                    audit(&params, "get", &target, "需要验证密码");
+                    std::process::exit(1);
+                }
+                println!("{} {} {}", pwd.user, pwd.ip, &*pwd.password);
+                audit(&params, "get", &target, "ok");
+            } else if !locked || verify(&rdata.pwd) {
+                if &params.format != "plain" {
+                    output::print_one(&params.format, &["ip", "user", "password"], &row);
+                } else {
+                    println!("{}@{}\n{}", pwd.user, pwd.ip, &*pwd.password);
+                }
+                audit(&params, "get", &target, "ok");
+            } else {
+                audit(&params, "get", &target, "密码错误");
             }
         }
         'r' => {
-            // 非特权：运行指定命令
+            // 运行指定命令
             let pwd = match rdata::Pwd::get_pwd(&mut rdata, &params) {
                 Ok(p) => p,
                 Err(e) => {
@@ -104,48 +320,898 @@ fn main() {
                     std::process::exit(1);
                 }
             };
-            let ip_user = format!("{}@{}", pwd.user, pwd.ip);
+            if need_unlock(&rdata, &params, "exec", &pwd.ip) && !verify(&rdata.pwd) {
+                println!("密码错误");
+                let target = format!("{}@{} {}", pwd.user, pwd.ip, &params.cmd);
+                audit(&params, "run", &target, "密码错误");
+                std::process::exit(1);
+            }
+            if !ensure_trusted(&mut rdata, &params, &pwd.ip) {
+                std::process::exit(1);
+            }
+            let opts = match options(&rdata, &params, &pwd.ip) {
+                Ok(o) => o,
+                Err(e) => {
+                    println!("{}", e.msg);
+                    std::process::exit(1);
+                }
+            };
             if true {
-                match ssh::ssh(&ip_user, &pwd.password, &params.cmd) {
-                    Ok(()) => {}
-                    Err(_) => {
-                        std::process::exit(1);
+                let (code, status) = match connect(&rdata, &params, &pwd, &params.cmd, &opts) {
+                    Ok(()) => {
+                        touch(&mut rdata, &params, &pwd);
+                        (0, String::from("ok"))
                     }
+                    Err(e) => match host_key_changed(&rdata, &params, &pwd.ip) {
+                        true => (1, String::from("主机Key已改变")),
+                        false => (1, e.msg),
+                    },
                 };
+                let target = format!("{}@{} {}", pwd.user, pwd.ip, &params.cmd);
+                audit(&params, "run", &target, &status);
+                if &params.format != "plain" {
+                    // 执行结果在远程命令的输出之后, 作为 stdout 的最后一条记录
+                    let result = output::ExecResult {
+                        ip: pwd.ip.clone(),
+                        user: pwd.user.clone(),
+                        code,
+                        status,
+                    };
+                    print!("{}", output::render_exec(&params.format, &result));
+                }
+                if code != 0 {
+                    // exit 不会执行 drop, 先删除临时 known_hosts
+                    drop(opts);
+                    std::process::exit(1);
+                }
             }
         }
         'd' => {
-            // 特权：删除指定用户
-            if verify(&rdata.pwd) {
-                match rdata.delete_pwd(&params) {
-                    Ok(()) => println!("已删除"),
-                    Err(e) => println!("删除失败: {}", e.msg),
+            // 删除指定用户
+            let target = format!("{}@{}", &params.user, &params.ip);
+            let status =
+                if !need_unlock(&rdata, &params, "delete", &params.ip) || verify(&rdata.pwd) {
+                    match rdata.delete_pwd(&params) {
+                        Ok(()) => {
+                            println!("已删除");
+                            String::from("ok")
+                        }
+                        Err(e) => {
+                            println!("删除失败: {}", e.msg);
+                            e.msg
+                        }
+                    }
+                } else {
+                    println!("密码错误");
+                    String::from("密码错误")
+                };
+            audit(&params, "delete", &target, &status);
+            return;
+        }
+        'p' => {
+            // 列出指定ip所有用户
+            if need_unlock(&rdata, &params, "list", &params.ip) && !verify(&rdata.pwd) {
+                println!("密码错误");
+                return;
+            }
+            let rows: Vec<Vec<String>> = rdata
+                .get_user(&params.ip)
+                .into_iter()
+                .map(|u| vec![params.ip.clone(), u])
+                .collect();
+            if &params.format == "plain" {
+                for r in &rows {
+                    println!("{}", r[1]);
                 }
             } else {
+                output::print(&params.format, &["ip", "user"], &rows);
+            }
+        }
+        'L' => {
+            // 列出所有条目, 显示密码需要验证
+            if (need_unlock(&rdata, &params, "list", "") || params.show_password)
+                && !verify(&rdata.pwd)
+            {
                 println!("密码错误");
+                if params.show_password {
+                    audit(&params, "ls --show-password", "", "密码错误");
+                }
+                return;
+            }
+            if params.show_password {
+                audit(&params, "ls --show-password", &params.filter_host, "ok");
+            }
+            let mut header = vec![
+                "ip",
+                "port",
+                "alias",
+                "tags",
+                "jump",
+                "user",
+                "auth",
+                "last_used",
+                "updated",
+                "stale",
+                "expires",
+            ];
+            if params.show_password {
+                header.push("password");
+            }
+            let rows = rdata.list(&params);
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
             }
-            return;
         }
-        'p' => {
-            // 非特权：列出指定ip所有用户
-            if rdata.version == 1 && !verify(&rdata.pwd) {
+        'B' => {
+            // 备份列表; 修改备份策略为特权操作
+            if &params.action == "config" {
+                let target = params.args.join(" ");
+                if !verify(&rdata.pwd) {
+                    println!("密码错误");
+                    audit(&params, "backup config", &target, "密码错误");
+                    return;
+                }
+                let keep = params.args.first().and_then(|k| k.parse().ok());
+                let days = params.args.get(1).and_then(|d| d.parse().ok());
+                match (keep, days) {
+                    (Some(k), Some(d)) => match rdata.set_backup(&params.file_path, k, d) {
+                        Ok(()) => {
+                            println!("已保存");
+                            audit(&params, "backup config", &target, "ok");
+                        }
+                        Err(e) => println!("{}", e.msg),
+                    },
+                    _ => println!("用法: backup config keep days"),
+                }
+                return;
+            }
+            if locked(&rdata, &params) && !verify(&rdata.pwd) {
                 println!("密码错误");
                 return;
             }
-            rdata.get_user(&params.ip).unwrap();
+            let header = ["id", "time", "entries", "size"];
+            let rows = rdata.list_backup(&params.file_path);
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
+            }
+        }
+        'R' => {
+            // 特权：从备份恢复
+            let status = if verify(&rdata.pwd) {
+                match rdata.restore(&params.file_path, &params.action) {
+                    Ok(()) => {
+                        println!("已恢复到 {}, 执行密码与解锁策略保持不变", &params.action);
+                        String::from("ok")
+                    }
+                    Err(e) => {
+                        println!("恢复失败: {}", e.msg);
+                        e.msg
+                    }
+                }
+            } else {
+                println!("密码错误");
+                String::from("密码错误")
+            };
+            audit(&params, "restore", &params.action, &status);
+        }
+        'H' => {
+            // 查看历史密码; 显示密码与恢复为特权操作
+            let privileged = params.show_password || &params.action == "restore";
+            let command = match params.action.as_str() {
+                "restore" => "history restore",
+                _ => "history --show-password",
+            };
+            let target = format!("{}@{}", &params.user, &params.ip);
+            if (locked(&rdata, &params) || privileged) && !verify(&rdata.pwd) {
+                println!("密码错误");
+                if privileged {
+                    audit(&params, command, &target, "密码错误");
+                }
+                return;
+            }
+            let pwd = match rdata::Pwd::get_pwd(&mut rdata, &params) {
+                Ok(p) => p,
+                Err(e) => {
+                    println!("{}", e.msg);
+                    return;
+                }
+            };
+            if &params.action == "restore" {
+                let n = params
+                    .args
+                    .first()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(0);
+                let status = match rdata.restore_history(&params.file_path, &pwd.ip, &pwd.user, n) {
+                    Ok(()) => {
+                        println!("已恢复 {}@{} 的历史密码 {}", &pwd.user, &pwd.ip, n);
+                        String::from("ok")
+                    }
+                    Err(e) => {
+                        println!("恢复失败: {}", e.msg);
+                        e.msg
+                    }
+                };
+                audit(&params, command, &target, &status);
+                return;
+            }
+            if params.show_password {
+                audit(&params, command, &target, "ok");
+            }
+            let mut header = vec!["index", "set", "replaced"];
+            if params.show_password {
+                header.push("password");
+            }
+            let rows = rdata.history(&pwd.ip, &pwd.user, params.show_password);
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
+            }
+        }
+        'T' => {
+            // 特权：修改远程主机密码并更新
+            if !verify(&rdata.pwd) {
+                println!("密码错误");
+                audit(&params, "rotate", &params.ip, "密码错误");
+                return;
+            }
+            let targets = if params.user.is_empty() {
+                // 批量: 必须指定 pattern、标签或过期条件, 避免误改全部主机
+                if params.ip.is_empty()
+                    && params.tags.is_empty()
+                    && !params.expired
+                    && params.expiring == 0
+                {
+                    println!("批量修改需要指定 pattern、--tag 或 --expired");
+                    return;
+                }
+                rdata.select(&params)
+            } else {
+                match rdata::Pwd::get_pwd(&mut rdata, &params) {
+                    Ok(p) => vec![p],
+                    Err(e) => {
+                        println!("{}", e.msg);
+                        return;
+                    }
+                }
+            };
+
+            let mut rows: Vec<Vec<String>> = Vec::new();
+            let mut failed = false;
+            for t in &targets {
+                let new = match policy_for(&rdata, &t.ip, &params).generate() {
+                    Ok(n) => n,
+                    Err(e) => {
+                        println!("{}", e.msg);
+                        std::process::exit(1);
+                    }
+                };
+                // 新密码会发给远程主机, 未确认主机公钥时可能发给了中间人
+                let opts = match rdata.host_keys(&t.ip).is_empty() {
+                    true => Err(result::MyErr {
+                        msg: format!("没有保存主机公钥, 请先执行 hostkey accept {}", &t.ip),
+                    }),
+                    false => options(&rdata, &params, &t.ip),
+                };
+                let opts = match opts {
+                    Ok(o) => o,
+                    Err(e) => {
+                        rows.push(vec![
+                            t.ip.clone(),
+                            t.user.clone(),
+                            String::from("change_failed"),
+                            e.msg,
+                        ]);
+                        failed = true;
+                        continue;
+                    }
+                };
+                let (status, msg) = match rotate::rotate(t, &new, &params.cmd, &opts) {
+                    rotate::Outcome::Ok => {
+                        rdata.set_pwd(&t.ip, &t.user, &new);
+                        ("ok", String::new())
+                    }
+                    rotate::Outcome::ChangeFailed(m) => ("change_failed", m),
+                    rotate::Outcome::VerifyFailed(m) => {
+                        // 远程可能已经是新密码, 记入历史以便找回
+                        rdata.remember(&t.ip, &t.user, &new);
+                        ("verify_failed", format!("{}, 新密码已记入历史", m))
+                    }
+                };
+                if status != "ok" {
+                    failed = true;
+                }
+                // 每台主机完成后立即保存, 中途退出也不会丢失已修改的密码
+                if status != "change_failed" {
+                    if let Err(e) = rdata.save(&params.file_path) {
+                        println!("{}", e.msg);
+                        std::process::exit(1);
+                    }
+                }
+                let target = format!("{}@{}", t.user, t.ip);
+                audit(&params, "rotate", &target, status);
+                rows.push(vec![t.ip.clone(), t.user.clone(), status.to_string(), msg]);
+            }
+
+            let header = ["ip", "user", "status", "message"];
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        'A' => {
+            // 审计日志, 查看与校验需要验证
+            if !verify(&rdata.pwd) {
+                println!("密码错误");
+                return;
+            }
+            if &params.action == "verify" {
+                match audit::verify(&params.file_path, rdata.audit_anchor()) {
+                    Ok((n, note)) => {
+                        println!("审计日志完整, 共 {} 条", n);
+                        if !note.is_empty() {
+                            println!("注意: {}", note);
+                        }
+                    }
+                    Err(e) => {
+                        println!("审计日志校验失败: {}", e.msg);
+                        std::process::exit(1);
+                    }
+                }
+                return;
+            }
+            let header = ["seq", "time", "os_user", "command", "target", "result"];
+            let rows: Vec<Vec<String>> = audit::read(&params.file_path)
+                .into_iter()
+                .flatten()
+                .filter(|e| e.target.contains(&params.ip))
+                .map(|e| {
+                    vec![
+                        e.seq.to_string(),
+                        utils::fmt_time(e.time),
+                        e.user,
+                        e.command,
+                        e.target,
+                        e.result,
+                    ]
+                })
+                .collect();
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
+            }
+        }
+        'E' => {
+            // 查看录像设置; 修改为特权操作
+            if !params.action.is_empty() {
+                if !verify(&rdata.pwd) {
+                    println!("密码错误");
+                    audit(&params, "record", &params.action, "密码错误");
+                    return;
+                }
+                match rdata.set_record(&params.file_path, &params.action) {
+                    Ok(()) => {
+                        println!("已保存");
+                        audit(&params, "record", &params.action, "ok");
+                    }
+                    Err(e) => println!("{}", e.msg),
+                }
+                return;
+            }
+            if locked(&rdata, &params) && !verify(&rdata.pwd) {
+                println!("密码错误");
+                return;
+            }
+            let header = ["host", "record"];
+            let rows: Vec<Vec<String>> = rdata
+                .record_settings()
+                .into_iter()
+                .map(|(h, r)| vec![h, r])
+                .collect();
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
+            }
+        }
+        'S' => {
+            // 录像列表; 回放可能包含敏感内容, 为特权操作
+            if (locked(&rdata, &params) || !params.action.is_empty()) && !verify(&rdata.pwd) {
+                println!("密码错误");
+                if !params.action.is_empty() {
+                    audit(&params, "replay", &params.action, "密码错误");
+                }
+                return;
+            }
+            if !params.action.is_empty() {
+                let status = match record::replay(&params.file_path, &params.action) {
+                    Ok(()) => String::from("ok"),
+                    Err(e) => {
+                        println!("{}", e.msg);
+                        e.msg
+                    }
+                };
+                audit(&params, "replay", &params.action, &status);
+                return;
+            }
+            let header = ["id", "started", "duration", "ip", "user", "status"];
+            let rows: Vec<Vec<String>> = record::list(&params.file_path, &params.filter_host)
+                .into_iter()
+                .map(|s| {
+                    vec![
+                        s.id,
+                        utils::fmt_time(s.started),
+                        format!("{}s", s.duration),
+                        s.ip,
+                        s.user,
+                        s.status,
+                    ]
+                })
+                .collect();
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
+            }
+        }
+        'G' => {
+            // 非特权：生成密码
+            let mut policy = match params.policy.as_str() {
+                "" => gen::Policy::builtin(),
+                name => match rdata.policies().into_iter().find(|p| p.name == name) {
+                    Some(p) => p,
+                    None => {
+                        println!("策略 {} 不存在", name);
+                        return;
+                    }
+                },
+            };
+            policy.apply(&params);
+            for _ in 0..params.count.max(1) {
+                match policy.generate() {
+                    Ok(p) => println!("{}", p),
+                    Err(e) => {
+                        println!("{}", e.msg);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        'P' => {
+            // 查看密码策略; 修改为特权操作
+            let command = format!("policy {}", &params.action);
+            if &params.action != "list" && !verify(&rdata.pwd) {
+                println!("密码错误");
+                audit(&params, &command, &params.ip, "密码错误");
+                return;
+            }
+            let re = match params.action.as_str() {
+                "set" => {
+                    // 在已有策略上修改
+                    let mut policy = rdata
+                        .policies()
+                        .into_iter()
+                        .find(|p| p.name == params.ip)
+                        .unwrap_or_else(gen::Policy::builtin);
+                    policy.name.clone_from(&params.ip);
+                    policy.apply(&params);
+                    match policy.generate() {
+                        Ok(_) => rdata.set_policy(&params.file_path, policy),
+                        Err(e) => Err(e),
+                    }
+                }
+                "rm" => rdata.remove_policy(&params.file_path, &params.ip),
+                _ => {
+                    let header = [
+                        "name",
+                        "length",
+                        "classes",
+                        "no_ambiguous",
+                        "words",
+                        "separator",
+                        "wordlist",
+                        "max_age",
+                    ];
+                    let rows: Vec<Vec<String>> = rdata
+                        .policies()
+                        .iter()
+                        .map(|p| {
+                            vec![
+                                p.name.clone(),
+                                p.length.to_string(),
+                                p.classes.clone(),
+                                p.no_ambiguous.to_string(),
+                                p.words.to_string(),
+                                p.separator.clone(),
+                                p.wordlist.clone(),
+                                p.max_age.map(|d| d.to_string()).unwrap_or_default(),
+                            ]
+                        })
+                        .collect();
+                    if &params.format == "plain" {
+                        output::print_aligned(&header, &rows);
+                    } else {
+                        output::print(&params.format, &header, &rows);
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(e) = &re {
+                println!("{}", e.msg);
+            }
+            if &params.action != "list" {
+                let status = re.map(|_| String::from("ok")).unwrap_or_else(|e| e.msg);
+                audit(&params, &command, &params.ip, &status);
+            }
+        }
+        'U' => {
+            // 查看解锁策略; 修改为特权操作
+            let command = format!("unlock {}", &params.action);
+            if &params.action != "list" && !verify(&rdata.pwd) {
+                println!("密码错误");
+                audit(&params, &command, &params.ip, "密码错误");
+                return;
+            }
+            let re = match params.action.as_str() {
+                "set" => match unlock::parse_ops(&params.args[0]) {
+                    Ok(ops) => rdata.set_unlock(
+                        &params.file_path,
+                        unlock::Rule {
+                            name: params.ip.clone(),
+                            ops,
+                        },
+                    ),
+                    Err(e) => Err(e),
+                },
+                "rm" => rdata.remove_unlock(&params.file_path, &params.ip),
+                _ => {
+                    let rows: Vec<Vec<String>> = rdata
+                        .unlock_rules()
+                        .into_iter()
+                        .map(|r| {
+                            // 未设置的 default 来自 -f/-k 的 flag
+                            let source = match rdata.unlock_is_set(&r.name) {
+                                true => String::from("unlock set"),
+                                false => format!("flag {}", rdata.version),
+                            };
+                            let ops = match r.ops.is_empty() {
+                                true => String::from("none"),
+                                false => r.ops.join(","),
+                            };
+                            vec![r.name, ops, source]
+                        })
+                        .collect();
+                    let header = ["name", "ops", "source"];
+                    if &params.format == "plain" {
+                        output::print_aligned(&header, &rows);
+                    } else {
+                        output::print(&params.format, &header, &rows);
+                    }
+                    if &params.format == "plain" && &params.config.unlock == "always" {
+                        println!("注意: 配置文件中 unlock = \"always\", 所有操作都需要密码");
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(e) = &re {
+                println!("{}", e.msg);
+            }
+            if &params.action != "list" {
+                let target = format!("{} {}", &params.ip, params.args.join(" "));
+                let status = re.map(|_| String::from("ok")).unwrap_or_else(|e| e.msg);
+                audit(&params, &command, target.trim_end(), &status);
+            }
+        }
+        'C' => {
+            // 非特权：检查保存的密码是否可用
+            if locked(&rdata, &params) && !verify(&rdata.pwd) {
+                println!("密码错误");
+                return;
+            }
+            let mut targets: Vec<check::Target> = Vec::new();
+            for p in rdata.select(&params) {
+                // 未保存主机公钥且没有确认的条目不检查, 结果为 hostkey_unknown
+                ensure_trusted(&mut rdata, &params, &p.ip);
+                let proxy = match proxy_command(&rdata, &params, &p.ip) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        println!("{}", e.msg);
+                        return;
+                    }
+                };
+                targets.push(check::Target {
+                    port: port(&rdata, &params, &p.ip),
+                    proxy,
+                    keys: rdata.host_keys(&p.ip),
+                    pwd: p,
+                });
+            }
+            if targets.is_empty() {
+                println!("not found");
+                return;
+            }
+
+            let results = check::check(
+                &params.exe_path,
+                &targets,
+                params.jobs,
+                params.config.check_timeout,
+            );
+            let mut rows: Vec<Vec<String>> = Vec::new();
+            let mut marks: Vec<(&str, &str, Option<bool>)> = Vec::new();
+            let mut failed = false;
+            for (t, (status, msg)) in targets.iter().zip(results.iter()) {
+                if *status != check::Status::Ok {
+                    failed = true;
+                }
+                if params.mark {
+                    // 只有认证结果能说明密码是否失效, 连接失败不改变标记
+                    let stale = match status {
+                        check::Status::Ok => Some(false),
+                        check::Status::AuthFailed => Some(true),
+                        _ => None,
+                    };
+                    marks.push((&t.pwd.ip, &t.pwd.user, stale));
+                }
+                rows.push(vec![
+                    t.pwd.ip.clone(),
+                    t.pwd.user.clone(),
+                    status.name().to_string(),
+                    msg.clone(),
+                ]);
+            }
+            if params.mark {
+                // 检查耗时较长, 写入时重新读取, 不覆盖期间其他进程的修改
+                let re = rdata::Rdata::update(&params.file_path, |r| {
+                    for (ip, user, stale) in &marks {
+                        r.mark_checked(ip, user, *stale);
+                    }
+                });
+                if let Err(e) = re {
+                    println!("{}", e.msg);
+                }
+            }
+            let failures = rows.iter().filter(|r| r[2] != "ok").count();
+            let status = format!("{} 个条目, {} 个失败", rows.len(), failures);
+            audit(&params, "check", &params.ip, &status);
+            let header = ["ip", "user", "status", "message"];
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        'K' => {
+            // 查看主机公钥; 修改为特权操作
+            if &params.action == "show" {
+                if locked(&rdata, &params) && !verify(&rdata.pwd) {
+                    println!("密码错误");
+                    return;
+                }
+                let header = ["ip", "port", "type", "fingerprint"];
+                let rows = rdata.list_host_keys(&params.ip);
+                if &params.format == "plain" {
+                    output::print_aligned(&header, &rows);
+                } else {
+                    output::print(&params.format, &header, &rows);
+                }
+                return;
+            }
+            let command = format!("hostkey {}", &params.action);
+            if !verify(&rdata.pwd) {
+                println!("密码错误");
+                audit(&params, &command, &params.ip, "密码错误");
+                return;
+            }
+            let ip = match rdata.resolve_host(&params.ip) {
+                Ok(ip) => ip,
+                Err(e) => {
+                    println!("{}", e.msg);
+                    return;
+                }
+            };
+            if &params.action == "forget" {
+                let status = match rdata.set_host_keys(&params.file_path, &ip, &[]) {
+                    Ok(()) => {
+                        println!("已删除");
+                        String::from("ok")
+                    }
+                    Err(e) => {
+                        println!("{}", e.msg);
+                        e.msg
+                    }
+                };
+                audit(&params, &command, &ip, &status);
+                return;
+            }
+            if jump_trusted(&mut rdata, &params, &ip) && trust(&mut rdata, &params, &ip) {
+                println!("已保存");
+                audit(&params, &command, &ip, "ok");
+            }
+        }
+        'J' => {
+            // 跳板机的 ProxyCommand, 由 ssh 调用, 标准输入输出为转发的数据
+            let (user, ip) = match params.ip.find('@') {
+                Some(n) => (&params.ip[..n], &params.ip[n + 1..]),
+                None => ("root", params.ip.as_str()),
+            };
+            let pwd = match rdata.find(ip, user) {
+                Some(p) => p,
+                None => {
+                    eprintln!("跳板机 {} 不存在", &params.ip);
+                    std::process::exit(1);
+                }
+            };
+            if !parent_unlocked(&rdata, &params, ip, &env_proof()) {
+                eprintln!("跳板机 {} 需要先验证密码", &params.ip);
+                std::process::exit(1);
+            }
+            let opts = match options(&rdata, &params, ip) {
+                Ok(o) => o,
+                Err(e) => {
+                    eprintln!("{}", e.msg);
+                    std::process::exit(1);
+                }
+            };
+            let target = format!("{}:{}", params.args[0], params.args[1]);
+            let ip_user = format!("{}@{}", user, ip);
+            let code = ssh::forward_stdio(&ip_user, &pwd.password, &target, &opts);
+            drop(opts);
+            std::process::exit(code);
+        }
+        'F' => {
+            // 非特权：端口转发
+            if &params.action == "list" {
+                let header = ["id", "started", "target", "forward"];
+                let rows: Vec<Vec<String>> = tunnel::list(&params.file_path)
+                    .iter()
+                    .map(|t| {
+                        vec![
+                            t.id.to_string(),
+                            utils::fmt_time(t.started),
+                            t.target.clone(),
+                            t.specs.clone(),
+                        ]
+                    })
+                    .collect();
+                if &params.format == "plain" {
+                    output::print_aligned(&header, &rows);
+                } else {
+                    output::print(&params.format, &header, &rows);
+                }
+                return;
+            }
+            if &params.action == "stop" {
+                let re = match params.ip.parse() {
+                    Ok(id) => tunnel::stop(&params.file_path, id),
+                    Err(_) => Err(result::MyErr {
+                        msg: format!("隧道 {} 不存在", &params.ip),
+                    }),
+                };
+                match re {
+                    Ok(()) => println!("已结束"),
+                    Err(e) => println!("{}", e.msg),
+                }
+                return;
+            }
+
+            let pwd = match rdata::Pwd::get_pwd(&mut rdata, &params) {
+                Ok(p) => p,
+                Err(e) => {
+                    println!("{}", e.msg);
+                    return;
+                }
+            };
+            let target = format!("{}@{} {}", pwd.user, pwd.ip, params.args.join(" "));
+            // 端口转发与登录的权限相同, 按 login 的解锁策略
+            if need_unlock(&rdata, &params, "login", &pwd.ip) && !verify(&rdata.pwd) {
+                println!("密码错误");
+                audit(&params, "forward", &target, "密码错误");
+                return;
+            }
+            if !ensure_trusted(&mut rdata, &params, &pwd.ip) {
+                return;
+            }
+            audit(&params, "forward", &target, "ok");
+
+            if params.background {
+                // 子进程脱离终端运行, 使用已确定的 ip 与用户, 不再交互
+                let child = unsafe {
+                    Command::new(&params.exe_path)
+                        .arg("--vault")
+                        .arg(&params.vault)
+                        .arg("__tunnel")
+                        .args(&params.args)
+                        .arg(&pwd.ip)
+                        .arg(&pwd.user)
+                        .env_remove(unlock::PROOF_ENV)
+                        .stdin(Stdio::piped())
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::null())
+                        .pre_exec(|| {
+                            libc::setsid();
+                            Ok(())
+                        })
+                        .spawn()
+                };
+                match child {
+                    Ok(mut c) => {
+                        // 解锁凭证绑定子进程, 经管道传递, 不出现在环境变量中
+                        if let Some(mut stdin) = c.stdin.take() {
+                            let _ = writeln!(stdin, "{}", proof_for(&rdata, c.id()));
+                        }
+                        println!("隧道已在后台运行, id: {}", c.id());
+                    }
+                    Err(_) => println!("启动后台进程失败"),
+                }
+                return;
+            }
+            let opts = match options(&rdata, &params, &pwd.ip) {
+                Ok(o) => o,
+                Err(e) => {
+                    println!("{}", e.msg);
+                    return;
+                }
+            };
+            println!("{}@{} {}", pwd.user, pwd.ip, params.args.join(" "));
+            let mut used = || touch(&mut rdata, &params, &pwd);
+            let code = tunnel::run(&params.file_path, &pwd, &params.args, &opts, &mut used);
+            drop(opts);
+            std::process::exit(code);
+        }
+        'N' => {
+            // forward --background 的子进程
+            let pwd = match rdata.find(&params.ip, &params.user) {
+                Some(p) => p,
+                None => std::process::exit(1),
+            };
+            // 父进程经标准输入传递解锁凭证
+            let mut proof = String::new();
+            let _ = std::io::stdin().read_line(&mut proof);
+            if !parent_unlocked(&rdata, &params, &pwd.ip, proof.trim_end()) {
+                std::process::exit(1);
+            }
+            let opts = match options(&rdata, &params, &pwd.ip) {
+                Ok(o) => o,
+                Err(_) => std::process::exit(1),
+            };
+            let mut used = || touch(&mut rdata, &params, &pwd);
+            let code = tunnel::run(&params.file_path, &pwd, &params.args, &opts, &mut used);
+            drop(opts);
+            std::process::exit(code);
         }
         'k' => {
             // 特权：修改验证密码
             if verify(&rdata.pwd) {
-                rdata.pwd = params.pwd;
+                rdata.pwd.clone_from(&params.pwd);
                 rdata.version = params.version;
-                rdata.save(&params.file_path).unwrap();
+                match rdata.save(&params.file_path) {
+                    Ok(()) => audit(&params, "passwd", "", "ok"),
+                    Err(e) => {
+                        println!("{}", e.msg);
+                        audit(&params, "passwd", "", &e.msg);
+                    }
+                }
             } else {
                 println!("密码错误");
+                audit(&params, "passwd", "", "密码错误");
             }
         }
         _ => {
-            // 非特权：登录
+            // 登录
             let pwd = match rdata::Pwd::get_pwd(&mut rdata, &params) {
                 Ok(p) => p,
                 Err(e) => {
@@ -156,32 +1222,589 @@ fn main() {
 
             let ip_user = format!("{}@{}", pwd.user, pwd.ip);
 
-            // version = 1, 验证密码
-            if rdata.version == 1 && !verify(&rdata.pwd) {
+            if need_unlock(&rdata, &params, "login", &pwd.ip) && !verify(&rdata.pwd) {
                 println!("密码错误");
+                audit(&params, "login", &ip_user, "密码错误");
                 return;
             }
+            audit(&params, "login", &ip_user, "ok");
+            if let Some(w) = rdata.expiry_warning(&pwd.ip, &pwd.user) {
+                println!("{}", w);
+            }
+            // 首次连接确认主机公钥
+            if !ensure_trusted(&mut rdata, &params, &pwd.ip) {
+                return;
+            }
+            let opts = match options(&rdata, &params, &pwd.ip) {
+                Ok(o) => o,
+                Err(e) => {
+                    println!("{}", e.msg);
+                    return;
+                }
+            };
             if true {
                 println!("ssh {}", &ip_user);
-                match ssh::ssh(&ip_user, &pwd.password, "") {
-                    Ok(()) => {}
-                    Err(_) => {} // 不打印错误信息, 子进程执行的任何语句报错都会捕获
+                match connect(&rdata, &params, &pwd, "", &opts) {
+                    Ok(()) => touch(&mut rdata, &params, &pwd),
+                    Err(_) => {
+                        // 不打印错误信息, 子进程执行的任何语句报错都会捕获; 只提示主机公钥变化
+                        if host_key_changed(&rdata, &params, &pwd.ip) {
+                            println!(
+                                "警告: {} 的主机Key已改变, 可能存在中间人攻击; 确认无误后执行 hostkey accept {}",
+                                &pwd.ip, &pwd.ip
+                            );
+                        }
+                    }
                 };
             }
         }
     }
 }
 
-/* 验证口令 */
-fn verify(key: &str) -> bool {
-    print!("请输入密码: ");
-    std::io::stdout().flush().unwrap();
+/* 连接条目, cmd 为空时登录; 设置了提权时以登录用户连接, 再 sudo/su 到条目的用户
+ * sudo 应答登录用户的密码, su 应答条目自身的密码; 登录时按设置录像 */
+fn connect(
+    rdata: &rdata::Rdata,
+    params: &params::Params,
+    pwd: &rdata::Pwd,
+    cmd: &str,
+    opts: &ssh::Options,
+) -> Result<(), result::MyErr> {
+    let escalation = rdata.escalation(&pwd.ip, &pwd.user)?;
+    let mode = match cmd.is_empty() {
+        true => rdata.record_mode(&pwd.ip),
+        false => String::from("off"),
+    };
+    let mut rec = match mode.as_str() {
+        "off" => None,
+        m => Some(record::Recorder::start(
+            &params.file_path,
+            &pwd.ip,
+            &pwd.user,
+            m == "input",
+        )?),
+    };
+    if escalation.is_none() && rec.is_none() {
+        let ip_user = format!("{}@{}", pwd.user, pwd.ip);
+        return ssh::ssh(&ip_user, &pwd.password, cmd, opts);
+    }
 
-    if let Ok(n) = read_password() {
-        if &n == key {
-            return true;
+    let re = session(pwd, escalation, cmd, opts, rec.as_mut());
+    if let Some(r) = rec {
+        let id = r.id().to_string();
+        r.finish(match &re {
+            Ok(()) => "ok",
+            Err(e) => &e.msg,
+        });
+        println!("会话已录像: {}", id);
+    }
+    re
+}
+
+/* 经过伪终端的会话, 没有提权时直接登录条目 */
+fn session(
+    pwd: &rdata::Pwd,
+    escalation: Option<(String, rdata::Pwd)>,
+    cmd: &str,
+    opts: &ssh::Options,
+    rec: Option<&mut record::Recorder>,
+) -> Result<(), result::MyErr> {
+    let (method, login) = match escalation {
+        Some(e) => e,
+        None => {
+            let ip_user = format!("{}@{}", pwd.user, pwd.ip);
+            return ssh::session(&ip_user, &pwd.password, "", cmd, opts, rec);
         }
     };
+    let user = utils::shell_quote(&pwd.user);
+    let (esc_password, run) = match (method.as_str(), cmd.is_empty()) {
+        ("sudo", true) => (
+            &login.password,
+            format!("exec sudo -p 'Password: ' -u {} -i", user),
+        ),
+        ("sudo", false) => (
+            &login.password,
+            format!(
+                "exec sudo -p 'Password: ' -u {} -- sh -c {}",
+                user,
+                utils::shell_quote(cmd)
+            ),
+        ),
+        (_, true) => (&pwd.password, format!("exec su - {}", user)),
+        (_, false) => (
+            &pwd.password,
+            format!("exec su - {} -c {}", user, utils::shell_quote(cmd)),
+        ),
+    };
+    // 标记行之后的密码提示才是 sudo/su 的
+    let remote = format!(
+        "echo {}; {}",
+        utils::shell_quote(&format!("[rssh] {} {}", method, pwd.user)),
+        run
+    );
+    let login_user = format!("{}@{}", login.user, login.ip);
+    ssh::session(
+        &login_user,
+        &login.password,
+        esc_password,
+        &remote,
+        opts,
+        rec,
+    )
+}
+
+/* 主机的密码策略, 命令行选项优先 */
+fn policy_for(rdata: &rdata::Rdata, ip: &str, params: &params::Params) -> gen::Policy {
+    let mut policy = match params.policy.as_str() {
+        "" => rdata.policy_for(ip),
+        name => rdata
+            .policies()
+            .into_iter()
+            .find(|p| p.name == name)
+            .unwrap_or_else(|| rdata.policy_for(ip)),
+    };
+    policy.apply(params);
+    policy
+}
+
+/* 主机的连接选项: 端口、跳板机, 保存了公钥时写入临时 known_hosts */
+fn options(
+    rdata: &rdata::Rdata,
+    params: &params::Params,
+    ip: &str,
+) -> Result<ssh::Options, result::MyErr> {
+    let port = port(rdata, params, ip);
+    let keys = rdata.host_keys(ip);
+    let known_hosts = match keys.is_empty() {
+        true => None,
+        false => Some(hostkey::KnownHosts::new(ip, port, &keys)?),
+    };
+    Ok(ssh::Options {
+        port,
+        proxy: proxy_command(rdata, params, ip)?,
+        known_hosts,
+        ..params.config.ssh_options()
+    })
+}
+
+/* 主机端口: 数据文件中的设置, 没有时为配置文件中匹配的主机设置 */
+fn port(rdata: &rdata::Rdata, params: &params::Params, ip: &str) -> u16 {
+    match rdata.get_port(ip) {
+        0 => params.config.port_for(ip),
+        p => p,
+    }
+}
+
+/* 命令是否会修改数据文件; 只修改最近使用时间等状态的命令在写入时才加锁 */
+fn writes(params: &params::Params) -> bool {
+    let action = params.action.as_str();
+    match params.option {
+        'c' | 'd' | 'l' | 'k' | 'M' | 'R' | 'T' => true,
+        'W' => action == "pull",
+        'U' | 'P' => action == "set" || action == "rm",
+        'B' => action == "config",
+        'H' => action == "restore",
+        'K' => action == "accept" || action == "forget",
+        'E' => !action.is_empty(),
+        _ => false,
+    }
+}
+
+/* 操作是否需要验证密码: 配置文件中 unlock = "always" 时都需要, 否则按数据文件中的解锁策略 */
+fn need_unlock(rdata: &rdata::Rdata, params: &params::Params, op: &str, ip: &str) -> bool {
+    &params.config.unlock == "always" || rdata.need_unlock(op, ip)
+}
+
+/* 其余非特权命令是否也需要密码: 数据文件设置为 1, 或配置文件中 unlock = "always" */
+fn locked(rdata: &rdata::Rdata, params: &params::Params) -> bool {
+    rdata.version == 1 || &params.config.unlock == "always"
+}
+
+/* 经过跳板机时的 ProxyCommand: 调用自身的 __proxy, 用跳板机保存的密码认证
+ * 跳板机还有跳板机时在 __proxy 中继续展开 */
+fn proxy_command(
+    rdata: &rdata::Rdata,
+    params: &params::Params,
+    ip: &str,
+) -> Result<String, result::MyErr> {
+    let (user, jump) = match rdata.jump(ip)? {
+        Some(j) => j,
+        None => return Ok(String::new()),
+    };
+    // __proxy 不能输入密码, 为即将启动的 ssh 设置解锁凭证
+    // 生成凭证需要派生密钥, 未过半有效期时沿用
+    let now = utils::now();
+    if UNLOCKED.load(Ordering::Relaxed)
+        && PROOF_EXPIRY.load(Ordering::Relaxed) < now + unlock::PROOF_TTL / 2
+    {
+        std::env::set_var(unlock::PROOF_ENV, proof_for(rdata, std::process::id()));
+        PROOF_EXPIRY.store(now + unlock::PROOF_TTL, Ordering::Relaxed);
+    }
+    // 保存数据时可执行文件会被替换, 使用启动时的路径; 子进程读取同一个 vault
+    Ok(format!(
+        "{} --vault {} __proxy {} %h %p",
+        utils::shell_quote(&params.exe_path),
+        utils::shell_quote(&params.vault),
+        utils::shell_quote(&format!("{}@{}", user, jump))
+    ))
+}
+
+/* 连接前确认主机与各级跳板机的公钥已保存, 没有时获取并确认 (首次连接信任)
+ * 所有连接都只使用保存的主机公钥, 返回 false 时不能连接 */
+fn ensure_trusted(rdata: &mut rdata::Rdata, params: &params::Params, ip: &str) -> bool {
+    jump_trusted(rdata, params, ip) && (!rdata.host_keys(ip).is_empty() || trust(rdata, params, ip))
+}
+
+/* 跳板机的公钥已保存; 经过跳板机获取主机公钥前也需要确认 */
+fn jump_trusted(rdata: &mut rdata::Rdata, params: &params::Params, ip: &str) -> bool {
+    match rdata.jump(ip) {
+        Ok(Some((_, jump))) => {
+            jump_unlocked(rdata, params, &jump) && ensure_trusted(rdata, params, &jump)
+        }
+        Ok(None) => true,
+        Err(e) => {
+            println!("{}", e.msg);
+            false
+        }
+    }
+}
+
+/* 跳板机的登录需要验证密码时, 在启动 __proxy 子进程前验证 */
+fn jump_unlocked(rdata: &rdata::Rdata, params: &params::Params, jump: &str) -> bool {
+    if parent_unlocked(rdata, params, jump, &env_proof()) || verify(&rdata.pwd) {
+        return true;
+    }
+    println!("密码错误");
     false
 }
 
+/* 不需要验证密码, 本进程已解锁, 或 proof 为父进程生成的有效凭证
+ * __proxy/__tunnel 子进程不能输入密码, 没有解锁时拒绝运行 */
+fn parent_unlocked(rdata: &rdata::Rdata, params: &params::Params, ip: &str, proof: &str) -> bool {
+    // 凭证须未过期, 且绑定的进程是本进程或其祖先, 不能被其他进程重放
+    let valid = match my_crypto::check_unlock_proof(&rdata.pwd, proof) {
+        Some((pid, expiry)) => expiry >= utils::now() && unlock::ancestor(pid),
+        None => false,
+    };
+    if valid {
+        // 之后的跳板机同样需要凭证
+        UNLOCKED.store(true, Ordering::Relaxed);
+    }
+    UNLOCKED.load(Ordering::Relaxed) || !need_unlock(rdata, params, "login", ip)
+}
+
+// 环境变量中父进程的解锁凭证
+fn env_proof() -> String {
+    std::env::var(unlock::PROOF_ENV).unwrap_or_default()
+}
+
+// 已解锁时生成绑定 pid 的凭证, 否则为空
+fn proof_for(rdata: &rdata::Rdata, pid: u32) -> String {
+    if !UNLOCKED.load(Ordering::Relaxed) {
+        return String::new();
+    }
+    my_crypto::unlock_proof(&rdata.pwd, pid, utils::now() + unlock::PROOF_TTL)
+}
+
+/* 获取主机公钥, 确认后保存 (首次连接或 hostkey accept) */
+fn trust(rdata: &mut rdata::Rdata, params: &params::Params, ip: &str) -> bool {
+    let proxy = match proxy_command(rdata, params, ip) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("{}", e.msg);
+            return false;
+        }
+    };
+    let opts = ssh::Options {
+        port: port(rdata, params, ip),
+        proxy,
+        ..params.config.ssh_options()
+    };
+    let keys = match hostkey::scan(ip, &opts) {
+        Ok(k) => k,
+        Err(e) => {
+            println!("{}", e.msg);
+            return false;
+        }
+    };
+    let old = rdata.host_keys(ip);
+    if old == keys {
+        println!("{} 的主机Key未改变", ip);
+        return true;
+    }
+    for k in &old {
+        println!("  原 {} {}", hostkey::key_type(k), hostkey::fingerprint(k));
+    }
+    for k in &keys {
+        println!("  新 {} {}", hostkey::key_type(k), hostkey::fingerprint(k));
+    }
+    if !params.yes && !utils::confirm(&format!("是否信任 {} 的主机Key? [y/N]: ", ip)) {
+        return false;
+    }
+    match rdata.set_host_keys(&params.file_path, ip, &keys) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("{}", e.msg);
+            false
+        }
+    }
+}
+
+/* 连接失败后检查主机公钥是否与保存的不同 */
+fn host_key_changed(rdata: &rdata::Rdata, params: &params::Params, ip: &str) -> bool {
+    let keys = rdata.host_keys(ip);
+    let opts = ssh::Options {
+        port: port(rdata, params, ip),
+        proxy: proxy_command(rdata, params, ip).unwrap_or_default(),
+        ..params.config.ssh_options()
+    };
+    !keys.is_empty() && hostkey::changed(ip, &opts, &keys)
+}
+
+/* 写入审计日志, 失败只提示, 不影响操作 */
+fn audit(params: &params::Params, command: &str, target: &str, status: &str) {
+    audit_file(&params.file_path, command, target, status);
+}
+
+/* 写入指定数据文件的审计日志, 用于当前 vault 之外的数据文件 */
+fn audit_file(file_name: &str, command: &str, target: &str, status: &str) {
+    if let Err(e) = audit::append(file_name, command, target, status) {
+        eprintln!("警告: {}", e.msg);
+    }
+}
+
+/* vault list|use|create|copy */
+fn vault_command(params: &params::Params) -> Result<(), result::MyErr> {
+    let config = &params.config;
+    let path = |name: &str| vault::path(config, &params.exe_path, name);
+    match params.action.as_str() {
+        "use" => {
+            vault::check_name(&params.ip)?;
+            if params.ip != vault::BASE && rdata::Rdata::get(&path(&params.ip)).is_err() {
+                return Err(result::MyErr {
+                    msg: format!("vault {} 不存在", &params.ip),
+                });
+            }
+            vault::set_current(config, &params.ip)?;
+            println!("当前 vault: {}", &params.ip);
+        }
+        "create" => {
+            vault::check_name(&params.ip)?;
+            let file = path(&params.ip);
+            if params.ip == vault::BASE || std::fs::metadata(&file).is_ok() {
+                return Err(result::MyErr {
+                    msg: format!("vault {} 已存在", &params.ip),
+                });
+            }
+            vault::create_dir(config)?;
+            rdata::Rdata::init(&file, &params.pwd, params.version)?;
+            audit_file(&file, "init", "", "ok");
+            println!("已创建 vault {}: {}", &params.ip, &file);
+        }
+        "copy" => {
+            let (from, to) = (&params.args[0], &params.args[1]);
+            vault::check_name(from)?;
+            vault::check_name(to)?;
+            if from == to {
+                return Err(result::MyErr {
+                    msg: String::from("源与目标是同一个 vault"),
+                });
+            }
+            let (src_file, dst_file) = (path(from), path(to));
+            let open = |name: &str, file: &str| {
+                rdata::Rdata::get(file).map_err(|_| result::MyErr {
+                    msg: format!("vault {} 不存在", name),
+                })
+            };
+            let src = open(from, &src_file)?;
+            let _lock = rdata::lock(&dst_file)?;
+            let dst = open(to, &dst_file)?;
+
+            // 各自按自己的解锁策略验证: 源为导出, 目标为导入
+            let entries = src.entries(params);
+            let target = format!("{} -> {} {}", from, to, &params.ip);
+            if need_unlock(&src, params, "export", "") {
+                println!("vault {}", from);
+                if !verify(&src.pwd) {
+                    audit_file(&src_file, "vault copy", target.trim_end(), "密码错误");
+                    return Err(result::MyErr {
+                        msg: String::from("密码错误"),
+                    });
+                }
+            }
+            if !params.dry_run && need_unlock(&dst, params, "import", "") {
+                println!("vault {}", to);
+                if !verify(&dst.pwd) {
+                    return Err(result::MyErr {
+                        msg: String::from("密码错误"),
+                    });
+                }
+            }
+            let re = dst.merge(&entries, 0, &dst_file, params);
+            let status = match &re {
+                Ok(()) => format!("ok, {} 条", entries.len()),
+                Err(e) => e.msg.clone(),
+            };
+            if !params.dry_run {
+                audit_file(&src_file, "vault copy", target.trim_end(), &status);
+                audit_file(&dst_file, "vault copy", target.trim_end(), &status);
+            }
+            re?;
+        }
+        _ => {
+            let current = vault::current(config);
+            let rows: Vec<Vec<String>> = vault::list(config)
+                .into_iter()
+                .map(|name| {
+                    let file = path(&name);
+                    let (hosts, entries) = match rdata::Rdata::get(&file) {
+                        Ok(r) => {
+                            let (h, e) = r.count();
+                            (h.to_string(), e.to_string())
+                        }
+                        Err(_) => (String::from("-"), String::from("-")),
+                    };
+                    let mark = match name == current {
+                        true => String::from("*"),
+                        false => String::new(),
+                    };
+                    vec![mark, name, hosts, entries, file]
+                })
+                .collect();
+            let header = ["current", "name", "hosts", "entries", "path"];
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
+            }
+        }
+    }
+    Ok(())
+}
+
+/* team keygen|init|members|add|rm */
+fn team_command(params: &params::Params) -> Result<(), result::MyErr> {
+    let identity = &params.config.identity;
+    let file = &params.in_file;
+    match params.action.as_str() {
+        "keygen" => {
+            let (created, public) = team::keygen(identity)?;
+            if created {
+                println!("已生成私钥: {}", identity);
+            } else {
+                println!("私钥已存在: {}", identity);
+            }
+            println!("公钥: {}", team::encode_key(&public));
+        }
+        "init" => {
+            if std::fs::metadata(file).is_ok() {
+                return Err(result::MyErr {
+                    msg: format!("{} 已存在", file),
+                });
+            }
+            let secret = team::read_identity(identity)?;
+            team::Team::create(&params.ip, &secret)?.save(file)?;
+            println!("已创建 team vault {}, 成员: {}", file, &params.ip);
+        }
+        "add" => {
+            let key = team::parse_key(&params.args[0])?;
+            let mut t = team::Team::load(file)?;
+            t.add(&team::read_identity(identity)?, &params.ip, key)?;
+            t.save(file)?;
+            println!("已添加成员 {}: {}", &params.ip, team::fingerprint(&key));
+        }
+        "rm" => {
+            let mut t = team::Team::load(file)?;
+            t.remove(&team::read_identity(identity)?, &params.ip)?;
+            t.save(file)?;
+            println!("已删除成员 {}, 数据密钥已更换", &params.ip);
+        }
+        _ => {
+            let t = team::Team::load(file)?;
+            let rows: Vec<Vec<String>> = t
+                .members
+                .iter()
+                .map(|m| {
+                    vec![
+                        m.name.clone(),
+                        team::encode_key(&m.key),
+                        team::fingerprint(&m.key),
+                    ]
+                })
+                .collect();
+            let header = ["name", "key", "fingerprint"];
+            if &params.format == "plain" {
+                output::print_aligned(&header, &rows);
+            } else {
+                output::print(&params.format, &header, &rows);
+            }
+        }
+    }
+    Ok(())
+}
+
+/* team push: 当前 vault 的条目合并到 team vault; team pull: team vault 的条目导入当前 vault */
+fn team_sync(rdata: rdata::Rdata, params: &params::Params) -> Result<(), result::MyErr> {
+    let secret = team::read_identity(&params.config.identity)?;
+    let mut t = team::Team::load(&params.in_file)?;
+    if &params.action == "push" {
+        let entries = rdata.entries(params);
+        let (added, updated, kept) = t.push(&secret, entries)?;
+        println!(
+            "新增 {} 条, 更新 {} 条, team vault 中较新未覆盖 {} 条",
+            added, updated, kept
+        );
+        if params.dry_run {
+            println!("--dry-run: 未保存");
+            return Ok(());
+        }
+        return t.save(&params.in_file);
+    }
+
+    let mut entries = t.entries(&secret)?;
+    entries.retain(|e| {
+        (params.ip.is_empty()
+            || e.ip.contains(params.ip.as_str())
+            || e.alias.iter().any(|a| a.contains(params.ip.as_str())))
+            && params.tags.iter().all(|t| e.tags.contains(t))
+    });
+    rdata.merge(&entries, 0, &params.file_path, params)
+}
+
+/* merge file [base]: 读取对方与共同版本的数据文件后合并到当前 vault */
+fn merge_vault(rdata: rdata::Rdata, params: &params::Params) -> Result<(), result::MyErr> {
+    let other = rdata::Rdata::get(&params.in_file)?;
+    let base = match params.args.first() {
+        Some(b) => Some(rdata::Rdata::get(b)?),
+        None => {
+            println!("未指定共同版本, 只合并新增与修改, 不合并删除");
+            None
+        }
+    };
+    rdata.merge_vault(&other, base.as_ref(), &params.file_path, params)
+}
+
+/* 记录最近使用时间, 没有变化时不改写数据文件; 保存失败只提示, 不影响登录 */
+fn touch(rdata: &mut rdata::Rdata, params: &params::Params, pwd: &rdata::Pwd) {
+    if !rdata.touch(&pwd.ip, &pwd.user) {
+        return;
+    }
+    if let Err(e) = rdata::Rdata::update_if(&params.file_path, |r| r.touch(&pwd.ip, &pwd.user)) {
+        eprintln!("保存最近使用时间失败: {}", e.msg);
+    }
+}
+
+/* 验证口令; 通过后本进程为已解锁, 之后启动的 __proxy/__tunnel 子进程获得解锁凭证 */
+fn verify(key: &str) -> bool {
+    print!("请输入密码: ");
+    std::io::stdout().flush().unwrap();
+
+    let ok = match read_password() {
+        Ok(n) => *Secret::new(n) == *key,
+        Err(_) => false,
+    };
+    if ok {
+        UNLOCKED.store(true, Ordering::Relaxed);
+    }
+    ok
+}